@@ -1,200 +1,584 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::BTreeMap;
+use std::env;
 use std::fs;
-use std::fs::File;
-use std::io;
-use std::io::Read;
-use std::io::Write;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::{symlink, PermissionsExt};
 use std::path::Path;
-use std::path::PathBuf;
-use std::process::Command;
-
-use core::str;
-use hex::FromHex;
-use tempfile::NamedTempFile;
+use std::str::FromStr;
 
+use crate::config::{self, Config, ConfigFile, Level};
 use crate::data;
-use crate::data::RefValue;
-use crate::data::GIT_DIR;
+use crate::data::{RefValue, GIT_DIR};
 use crate::diff;
-
-pub fn init() -> io::Result<()> {
-    data::init()?;
-
+use crate::error::{Error, Result};
+use crate::graph::Graph;
+use crate::ignore::Ignore;
+use crate::index::{self, Index, IndexEntry};
+use crate::merge;
+use crate::object::{
+    Commit, Object, Signature, Tree, TreeEntry, MODE_EXECUTABLE, MODE_SYMLINK, MODE_TREE,
+};
+use crate::Repository;
+use crate::{date, pretty, revision, walk, xdiff};
+
+/// Files of a tree flattened to `path -> (mode, oid)`, with `/`-separated paths.
+pub type TreeFiles = BTreeMap<String, (u32, String)>;
+
+/// Creates the rgit directory, with HEAD on the branch named by
+/// `init.defaultBranch` (`master` by default).
+pub fn init(repo: &Repository) -> Result<()> {
+    data::init(repo)?;
+
+    let config = Config::load(Some(repo))?;
+    let branch = config.get("init.defaultBranch").unwrap_or("master");
     let value = RefValue {
         symbolic: true,
-        value: Some(String::from("refs/heads/master")),
+        value: Some(format!("refs/heads/{branch}")),
     };
-    data::set_ref("HEAD", value, false);
+    data::set_ref(repo, "HEAD", value, false)?;
+
+    // Level::Local always has a path when given a repository
+    let mut local = ConfigFile::read(&Level::Local.path(Some(repo)).unwrap())?;
+    local.set("core.repositoryformatversion", "0", false)?;
+    local.set("core.filemode", "true", false)?;
+    local.set("core.bare", &repo.is_bare().to_string(), false)?;
+    local.write()
+}
 
-    Ok(())
+pub fn write_tree(repo: &Repository) -> Result<String> {
+    Index::read(repo)?.write_tree(repo)
 }
 
-pub fn write_tree() -> String {
-    let rgit_path = PathBuf::from("./test");
-    visit_dirs(&rgit_path)
+/// Lists the files below `dir` as work tree relative paths, skipping
+/// anything matched by the ignore rules.
+pub fn visit_dirs(repo: &Repository, dir: &Path) -> Result<Vec<String>> {
+    let work_tree = repo.work_tree()?;
+    let mut ignore = Ignore::new(repo)?;
+    let mut files = vec![];
+    visit_dirs_iner(repo, work_tree, dir, &mut ignore, &mut files)?;
+    files.sort();
+
+    Ok(files)
 }
 
 // one possible implementation of walking a directory only visiting files
 // https://doc.rust-lang.org/std/fs/fn.read_dir.html
-fn visit_dirs(dir: &Path) -> String {
-    let mut entries = vec![];
-    let mut rgit_type;
-    let mut oid;
-    let mut tree = String::from("");
+fn visit_dirs_iner(
+    repo: &Repository,
+    work_tree: &Path,
+    dir: &Path,
+    ignore: &mut Ignore,
+    files: &mut Vec<String>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_name() == GIT_DIR || path == repo.git_dir() {
+            continue;
+        }
 
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        let relative = relative_to(work_tree, &path);
+        if ignore
+            .last_match(&relative, is_dir)?
+            .is_some_and(|p| !p.negated)
+        {
+            continue;
+        }
 
-            if is_dot_path(&path) {
-                continue;
-            }
+        if is_dir {
+            visit_dirs_iner(repo, work_tree, &path, ignore, files)?;
+        } else {
+            files.push(relative);
+        }
+    }
 
-            if path.is_dir() {
-                rgit_type = "tree";
-                oid = visit_dirs(&path);
-            } else {
-                let mut file = File::open(path).expect("input file not exist");
-                let mut contents = String::new();
-                file.read_to_string(&mut contents).unwrap();
+    Ok(())
+}
 
-                rgit_type = "blob";
-                oid = data::hash_object(&contents, rgit_type);
-            }
+// like visit_dirs, but without applying the ignore rules
+fn walk_all(work_tree: &Path, dir: &Path) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name() == GIT_DIR {
+            continue;
+        }
 
-            let e = format!(
-                "{} {} {}",
-                rgit_type,
-                oid,
-                entry.file_name().into_string().unwrap()
-            );
-            entries.push(e);
+        if entry.file_type()?.is_dir() {
+            files.extend(walk_all(work_tree, &entry.path())?);
+        } else {
+            files.push(relative_to(work_tree, &entry.path()));
         }
+    }
+
+    Ok(files)
+}
+
+fn relative_to(work_tree: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(work_tree).unwrap_or(path);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn flatten_tree(repo: &Repository, oid: &str) -> Result<TreeFiles> {
+    let mut files = TreeFiles::new();
+    flatten_tree_iner(repo, oid, "", &mut files)?;
 
-        for e in &entries {
-            tree.push_str(e);
-            tree.push('\n');
+    Ok(files)
+}
+
+fn flatten_tree_iner(
+    repo: &Repository,
+    oid: &str,
+    base_path: &str,
+    files: &mut TreeFiles,
+) -> Result<()> {
+    for entry in data::get_tree(repo, oid)?.entries {
+        let path = format!("{base_path}{}", entry.name);
+
+        if entry.is_tree() {
+            flatten_tree_iner(repo, &entry.oid, &format!("{path}/"), files)?;
+        } else {
+            files.insert(path, (entry.mode, entry.oid));
         }
     }
 
-    data::hash_object(&tree, "tree")
+    Ok(())
 }
 
-fn empty_current_directory(dir: &Path) -> io::Result<()> {
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let path = entry.unwrap().path();
+/// Writes the nested tree objects for a flat file map and returns the root tree.
+pub fn build_tree(repo: &Repository, files: &TreeFiles) -> Result<String> {
+    let mut entries = vec![];
+    let mut subdirs: BTreeMap<&str, TreeFiles> = BTreeMap::new();
 
-            if is_dot_path(&path) {
-                continue;
+    for (path, (mode, oid)) in files {
+        match path.split_once('/') {
+            Some((dir, rest)) => {
+                let subdir = subdirs.entry(dir).or_default();
+                subdir.insert(rest.to_string(), (*mode, oid.clone()));
             }
+            None => entries.push(TreeEntry {
+                mode: *mode,
+                name: path.clone(),
+                oid: oid.clone(),
+            }),
+        }
+    }
 
-            if path.is_dir() {
-                empty_current_directory(&path)?;
-            } else if path.is_file() {
-                fs::remove_file(path)?;
-            }
+    for (dir, subfiles) in subdirs {
+        entries.push(TreeEntry {
+            mode: MODE_TREE,
+            name: dir.to_string(),
+            oid: build_tree(repo, &subfiles)?,
+        });
+    }
+
+    data::write_object(repo, &Object::Tree(Tree::new(entries)))
+}
+
+/// The files of HEAD's tree, or nothing before the first commit.
+pub fn head_files(repo: &Repository) -> Result<TreeFiles> {
+    match data::get_ref(repo, "HEAD", true)?.value {
+        Some(head) => flatten_tree(repo, &data::get_commit(repo, &head)?.tree),
+        None => Ok(TreeFiles::new()),
+    }
+}
+
+/// Replaces the tracked files of the work tree and the index with `tree`.
+/// Untracked files are left alone unless the tree would overwrite them.
+pub fn read_tree(repo: &Repository, tree: &str) -> Result<()> {
+    let work_tree = repo.work_tree()?;
+    let old_index = Index::read(repo)?;
+    let files = flatten_tree(repo, tree)?;
+
+    for (path, (_, oid)) in &files {
+        let file = work_tree.join(path);
+        if !old_index.contains(path)
+            && fs::symlink_metadata(&file).is_ok_and(|meta| !meta.is_dir())
+            && data::compute_oid(&read_work_tree_file(&file)?, "blob") != *oid
+        {
+            return Err(Error::DirtyWorktree);
+        }
+    }
+
+    for entry in old_index.entries() {
+        if !files.contains_key(&entry.path) {
+            remove_work_tree_file(work_tree, &entry.path)?;
         }
+    }
+
+    let mut index = Index::default();
+    for (path, (mode, oid)) in &files {
+        let file = work_tree.join(path);
+        write_work_tree_file(&file, *mode, &data::get_object(repo, oid, Some("blob"))?)?;
+        index.add(IndexEntry::from_metadata(
+            path,
+            *mode,
+            oid,
+            &fs::symlink_metadata(&file)?,
+        ));
+    }
+
+    index.write(repo)
+}
+
+/// The contents to hash for a work tree file: the link target for symlinks,
+/// which are never followed.
+pub fn read_work_tree_file(file: &Path) -> Result<Vec<u8>> {
+    if fs::symlink_metadata(file)?.file_type().is_symlink() {
+        Ok(fs::read_link(file)?.into_os_string().into_encoded_bytes())
+    } else {
+        Ok(fs::read(file)?)
+    }
+}
 
-        fs::remove_dir(dir)?;
+/// Writes a blob to the work tree as a symlink or as a regular file with
+/// the executable bit set according to `mode`.
+fn write_work_tree_file(file: &Path, mode: u32, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // a symlink would be written through, so the old file always goes first
+    match fs::remove_file(file) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
     }
 
-    // } else if dir.is_file() {
-    //     fs::remove_file(dir)?;
-    // }
+    if mode == MODE_SYMLINK {
+        symlink(String::from_utf8_lossy(contents).as_ref(), file)?;
+        return Ok(());
+    }
+
+    data::mkfile(file, contents)?;
+    let mut permissions = fs::metadata(file)?.permissions();
+    if mode == MODE_EXECUTABLE {
+        // executable for everyone who can read it, like git does
+        let read = permissions.mode() & 0o444;
+        permissions.set_mode(permissions.mode() | read >> 2);
+    } else {
+        permissions.set_mode(permissions.mode() & !0o111);
+    }
+    fs::set_permissions(file, permissions)?;
 
     Ok(())
 }
 
-fn iter_tree_entries(oid: &str, base_path: &str) -> (HashSet<String>, HashMap<String, String>) {
-    let mut dirs = HashSet::new();
-    let mut files = HashMap::new();
+/// Deletes a work tree file and any directories it leaves empty.
+fn remove_work_tree_file(work_tree: &Path, path: &str) -> Result<()> {
+    let file = work_tree.join(path);
+    match fs::remove_file(&file) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
 
-    let content = data::get_object(oid, Some("tree"));
-    for entry in content.lines() {
-        let mut e = entry.split_whitespace().collect::<Vec<&str>>();
+    for dir in file.ancestors().skip(1) {
+        if dir == work_tree || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
 
-        let name = e.pop().unwrap();
-        let mut path = base_path.to_string();
-        path.push_str(name);
+    Ok(())
+}
 
-        let oid = e.pop().unwrap();
-        let rgit_type = e.pop().unwrap();
+/// Stages the work tree state of each path. Directories are added recursively
+/// and tracked files that no longer exist are staged as deleted. Ignored
+/// files are only added when `force` is set.
+pub fn add(repo: &Repository, paths: &[String], force: bool) -> Result<()> {
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::read(repo)?;
+    let mut ignore = Ignore::new(repo)?;
+
+    for path in paths {
+        let file = work_tree.join(path);
+        let tracked = index.paths_under(path);
+
+        let mut present = match fs::symlink_metadata(&file) {
+            Ok(meta) => {
+                if !force && tracked.is_empty() && ignore.is_ignored(path, meta.is_dir())? {
+                    return Err(Error::PathIgnored(path.clone()));
+                }
+
+                if meta.is_dir() && force {
+                    walk_all(work_tree, &file)?
+                } else if meta.is_dir() {
+                    visit_dirs(repo, &file)?
+                } else {
+                    vec![path.clone()]
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
 
-        if rgit_type == "blob" {
-            files.insert(path, oid.to_string());
-        } else if rgit_type == "tree" {
-            dirs.insert(path.to_string());
+        if present.is_empty() && tracked.is_empty() {
+            return Err(Error::PathNotFound(path.clone()));
+        }
 
-            let (subdirs, subfiles) = iter_tree_entries(oid, &format!("{path}/"));
-            for p in subdirs {
-                dirs.insert(p);
+        // tracked files stay tracked even if they now match an ignore rule
+        for p in tracked {
+            if fs::symlink_metadata(work_tree.join(&p)).is_ok() {
+                present.push(p);
+            } else {
+                index.remove(&p);
             }
-            for (p, o) in subfiles {
-                files.insert(p, o);
+        }
+        present.sort();
+        present.dedup();
+
+        for p in present {
+            let file = work_tree.join(&p);
+            let meta = fs::symlink_metadata(&file)?;
+            if let Some(entry) = index.get(&p) {
+                if index.is_up_to_date(entry, &meta) {
+                    continue;
+                }
             }
-        } else {
-            panic!("Unknown entry")
+
+            let oid = data::hash_object(repo, &read_work_tree_file(&file)?, "blob")?;
+            let mode = index::file_mode(&meta);
+            index.add(IndexEntry::from_metadata(&p, mode, &oid, &meta));
         }
     }
 
-    (dirs, files)
+    index.write(repo)
 }
 
-pub fn read_tree(tree: &str) {
-    let path = PathBuf::from("./test");
-    empty_current_directory(&path).unwrap();
+/// Unstages each path and, unless `cached`, deletes it from the work tree too.
+pub fn remove(repo: &Repository, paths: &[String], cached: bool) -> Result<()> {
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::read(repo)?;
 
-    let (dirs, files) = iter_tree_entries(tree, "./test/");
-    data::mkdir("./test").unwrap();
+    for path in paths {
+        let tracked = index.paths_under(path);
+        if tracked.is_empty() {
+            return Err(Error::PathNotFound(path.clone()));
+        }
 
-    let mut dirs = dirs.into_iter().collect::<Vec<String>>();
-    dirs.sort();
-    for p in dirs {
-        data::mkdir(&p).unwrap();
+        for p in tracked {
+            index.remove(&p);
+            if !cached {
+                remove_work_tree_file(work_tree, &p)?;
+            }
+        }
     }
 
-    for (p, o) in files {
-        data::mkfile(&p, data::get_object(&o, None).as_bytes()).unwrap();
+    index.write(repo)
+}
+
+/// With `staged`, resets the index entries of each path to HEAD. Otherwise
+/// overwrites the work tree files with their staged contents.
+pub fn restore(repo: &Repository, paths: &[String], staged: bool) -> Result<()> {
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::read(repo)?;
+
+    for path in paths {
+        if staged {
+            let head = head_files(repo)?;
+            let in_head = head
+                .iter()
+                .filter(|(p, _)| index::is_under(p, path))
+                .collect::<Vec<_>>();
+            let tracked = index.paths_under(path);
+            if in_head.is_empty() && tracked.is_empty() {
+                return Err(Error::PathNotFound(path.clone()));
+            }
+
+            for p in tracked {
+                index.remove(&p);
+            }
+            for (p, (mode, oid)) in in_head {
+                index.add(IndexEntry::new(p, *mode, oid));
+            }
+        } else {
+            let tracked = index.paths_under(path);
+            if tracked.is_empty() {
+                return Err(Error::PathNotFound(path.clone()));
+            }
+
+            for p in tracked {
+                let Some(entry) = index.get_mut(&p) else {
+                    continue;
+                };
+                let file = work_tree.join(&p);
+                let contents = data::get_object(repo, &entry.oid, Some("blob"))?;
+                write_work_tree_file(&file, entry.mode, &contents)?;
+                entry.update_stat(&fs::symlink_metadata(&file)?);
+            }
+        }
     }
+
+    index.write(repo)
 }
 
-pub fn commit(message: &str) -> String {
-    let mut commit = "tree ".to_string();
-    commit.push_str(&write_tree());
-    commit.push('\n');
+/// Commits the index. `author` (`Name <email>`) and `date` override the
+/// author identity and time; the committer always comes from the environment.
+pub fn commit(
+    repo: &Repository,
+    message: &str,
+    author: Option<&str>,
+    date: Option<&str>,
+) -> Result<String> {
+    let mut signature = signature(repo, "author")?;
+    if let Some(ident) = author {
+        (signature.name, signature.email) = parse_ident(ident)?;
+    }
+    if let Some(date) = date {
+        (signature.time, signature.offset) = date::parse(date)?;
+    }
+
+    if !Index::read(repo)?.unmerged().is_empty() {
+        return Err(Error::UnmergedFiles);
+    }
+    let merge_head = merge_head(repo)?;
+
+    let commit = Commit {
+        tree: write_tree(repo)?,
+        parents: data::get_ref(repo, "HEAD", true)?
+            .value
+            .into_iter()
+            .chain(merge_head.clone())
+            .collect(),
+        author: Some(signature),
+        committer: Some(self::signature(repo, "committer")?),
+        message: format!("{message}\n"),
+    };
+    let kind = if merge_head.is_some() {
+        "commit (merge)"
+    } else if commit.parents.is_empty() {
+        "commit (initial)"
+    } else {
+        "commit"
+    };
+    let reflog_message = format!("{kind}: {message}");
+
+    let oid = data::write_object(repo, &Object::Commit(commit))?;
+    update_ref(repo, "HEAD", &oid, &reflog_message)?;
+    if merge_head.is_some() {
+        clear_merge_state(repo)?;
+    }
+    remove_state_file(repo, "SQUASH_MSG")?;
+
+    Ok(oid)
+}
 
-    if let Some(head) = data::get_ref("HEAD", true).value {
-        commit.push_str("parent ");
-        commit.push_str(&head);
-        commit.push('\n');
+/// Points `rgit_ref`, or the branch it refers to, at `oid` and records the
+/// move in that ref's reflog, and in HEAD's when HEAD is on the branch.
+pub fn update_ref(repo: &Repository, rgit_ref: &str, oid: &str, message: &str) -> Result<()> {
+    let name = data::resolve_ref(repo, rgit_ref)?;
+    let old = data::get_ref(repo, &name, false)?.value;
+    data::set_ref(repo, &name, RefValue::new(Some(oid.to_string())), false)?;
+
+    let entry = data::ReflogEntry {
+        old: old.map_or_else(|| data::NULL_OID.to_string(), |old| old.trim().to_string()),
+        new: oid.to_string(),
+        committer: signature(repo, "committer")?,
+        message: message.to_string(),
+    };
+    data::append_reflog(repo, &name, &entry)?;
+    if name != "HEAD" && data::resolve_ref(repo, "HEAD")? == name {
+        data::append_reflog(repo, "HEAD", &entry)?;
     }
 
-    commit.push('\n');
-    commit.push_str(message);
-    commit.push('\n');
+    Ok(())
+}
+
+/// The identity and current time for `role`, either "author" or "committer".
+/// `RGIT_AUTHOR_NAME`, `RGIT_AUTHOR_EMAIL` and `RGIT_AUTHOR_DATE` (or their
+/// committer counterparts) win over `user.name` and `user.email` from the
+/// config, which win over the login name and host.
+pub fn signature(repo: &Repository, role: &str) -> Result<Signature> {
+    let var = |field: &str| {
+        env::var(format!("RGIT_{}_{field}", role.to_uppercase()))
+            .ok()
+            .filter(|value| !value.is_empty())
+    };
+    let config = Config::load(Some(repo))?;
+    let user = env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or_else(|_| String::from("unknown"));
+
+    let name = match var("NAME") {
+        Some(name) => name,
+        None => config.get("user.name").unwrap_or(&user).to_string(),
+    };
+    let email = match var("EMAIL") {
+        Some(email) => email,
+        None => match config.get("user.email") {
+            Some(email) => email.to_string(),
+            None => env::var("EMAIL").unwrap_or_else(|_| format!("{user}@{}", hostname())),
+        },
+    };
+    let (time, offset) = match var("DATE") {
+        Some(date) => date::parse(&date)?,
+        None => date::now(),
+    };
 
-    let oid = data::hash_object(&commit, "commit");
-    let tmp = RefValue::new(Some(oid.clone()));
-    data::set_ref("HEAD", tmp, true);
+    Ok(Signature {
+        name,
+        email,
+        time,
+        offset,
+    })
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("localhost"))
+}
 
-    oid
+/// Splits `Name <email>` as given to `--author`.
+fn parse_ident(ident: &str) -> Result<(String, String)> {
+    match (ident.find('<'), ident.rfind('>')) {
+        (Some(lt), Some(gt)) if lt < gt && ident[gt + 1..].trim().is_empty() => Ok((
+            ident[..lt].trim().to_string(),
+            ident[lt + 1..gt].to_string(),
+        )),
+        _ => Err(Error::BadIdent(ident.to_string())),
+    }
 }
 
-pub fn checkout(name: &str) {
-    let oid = get_oid(name);
-    let commit = data::get_object(&oid, Some("commit"));
+/// Refuses to continue if tracked files have staged or unstaged changes,
+/// since replacing them would throw the changes away.
+fn ensure_clean(repo: &Repository) -> Result<()> {
+    let status = diff::status(repo)?;
+    if !status.staged.is_empty() || !status.unmerged.is_empty() || !status.unstaged.is_empty() {
+        return Err(Error::DirtyWorktree);
+    }
 
-    let tree = commit.lines().collect::<Vec<&str>>()[0]
-        .split_whitespace()
-        .nth(1)
-        .unwrap();
+    Ok(())
+}
 
-    read_tree(tree);
+/// Checks out a branch, attaching HEAD to it, or any other commit with
+/// HEAD detached. `@{-N}` switches back to the branch it names.
+pub fn checkout(repo: &Repository, name: &str) -> Result<()> {
+    let previous = revision::previous_checkout(repo, name)?;
+    let name = previous.as_deref().unwrap_or(name);
+    let oid = get_oid(repo, name)?;
+    let commit = data::get_commit(repo, &oid)?;
+    ensure_clean(repo)?;
+    read_tree(repo, &commit.tree)?;
+
+    let old = data::get_ref(repo, "HEAD", true)?.value;
+    let old = old.map_or_else(|| data::NULL_OID.to_string(), |old| old.trim().to_string());
+    let from = get_status_name(repo)?.unwrap_or_else(|| old.clone());
+    let entry = data::ReflogEntry {
+        old,
+        new: oid.clone(),
+        committer: signature(repo, "committer")?,
+        message: format!("checkout: moving from {from} to {name}"),
+    };
 
-    let tmp = if is_branch(name) {
+    let tmp = if is_branch(repo, name)? {
         let value = format!("refs/heads/{name}");
         data::RefValue {
             symbolic: true,
@@ -204,176 +588,713 @@ pub fn checkout(name: &str) {
         data::RefValue::new(Some(oid))
     };
 
-    data::set_ref("HEAD", tmp, false);
+    data::set_ref(repo, "HEAD", tmp, false)?;
+    data::append_reflog(repo, "HEAD", &entry)
 }
 
-fn is_branch(name: &str) -> bool {
+fn is_branch(repo: &Repository, name: &str) -> Result<bool> {
     let rgit_ref = format!("refs/heads/{name}");
-    data::get_ref(&rgit_ref, false).value.is_some()
+    Ok(data::get_ref(repo, &rgit_ref, false)?.value.is_some())
+}
+
+pub fn iter_branch_names(repo: &Repository) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(repo.git_dir().join("refs/heads"))? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+
+    Ok(names)
+}
+
+pub fn get_status_name(repo: &Repository) -> Result<Option<String>> {
+    let value = data::get_ref(repo, "HEAD", false)?;
+    if !value.symbolic {
+        return Ok(None);
+    }
+
+    let head = value.value.unwrap_or_default();
+    Ok(head
+        .strip_prefix("ref: refs/heads/")
+        .map(|name| name.trim_end().to_string()))
 }
 
-pub fn iter_branch_names() -> Vec<String> {
-    let path = format!("{GIT_DIR}/refs/heads/");
+pub fn reset(repo: &Repository, oid: &str) -> Result<()> {
+    update_ref(repo, "HEAD", oid, &format!("reset: moving to {oid}"))
+}
 
-    fs::read_dir(path)
-        .unwrap()
-        .map(|res| res.unwrap().file_name().into_string().unwrap())
-        .collect::<Vec<String>>()
+/// Whether a merge may just move the branch forward when the other side
+/// already contains it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FastForward {
+    /// Fast-forward when possible, merge otherwise.
+    #[default]
+    Allow,
+    /// Always create a merge commit.
+    Never,
+    /// Refuse to merge unless the branch can be fast-forwarded.
+    Only,
 }
 
-fn iter_branch_contents() -> Vec<(String, String)> {
-    let mut contents = vec![];
-    for name in iter_branch_names() {
-        let path = format!("{GIT_DIR}/refs/heads/{name}");
-        let mut file = File::open(path).unwrap();
-        let mut content = String::new();
-        file.read_to_string(&mut content).unwrap();
-        contents.push((name, content));
+/// The `merge.ff` values: a boolean or "only".
+impl FromStr for FastForward {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FastForward> {
+        match s.to_lowercase().as_str() {
+            "only" => Ok(FastForward::Only),
+            value => match config::parse_bool(value) {
+                Some(true) => Ok(FastForward::Allow),
+                Some(false) => Ok(FastForward::Never),
+                None => Err(Error::BadConfig(format!("bad value '{s}' for 'merge.ff'"))),
+            },
+        }
     }
+}
 
-    contents
+/// How `read_tree_merged` combines the two sides.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Resolve conflicting changes in favor of one side.
+    pub favor: Option<xdiff::Favor>,
+    /// Overrides `merge.ff` when set.
+    pub fast_forward: Option<FastForward>,
+    /// Update the index and work tree but leave HEAD alone.
+    pub squash: bool,
+    /// The merge commit message instead of "Merge branch ...".
+    pub message: Option<String>,
 }
 
-pub fn get_status_name() -> Option<String> {
-    let value = data::get_ref("HEAD", false);
-    if !value.symbolic {
-        return None;
+/// What a successful `read_tree_merged` did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The other side was already part of the history.
+    UpToDate,
+    /// The branch moved from the first oid to the second.
+    FastForward(String, String),
+    /// The merged files were staged without committing.
+    Squashed,
+    /// A merge commit with this oid was created.
+    Merged(String),
+}
+
+/// Merges `tree2` into `tree1`, fast-forwarding when `tree1` is an ancestor
+/// and `options` allow it, and commits the result. A conflicted result is
+/// left in the work tree with each side staged in the index until
+/// `continue_merge` or `abort_merge` concludes it. Conflicts are written in
+/// the `merge.conflictStyle` style unless `favor` resolves them.
+pub fn read_tree_merged(
+    repo: &Repository,
+    tree1: &str,
+    tree2: &str,
+    options: &MergeOptions,
+) -> Result<MergeOutcome> {
+    if merge_head(repo)?.is_some() {
+        return Err(Error::MergeInProgress);
+    }
+    let tree1_oid = get_oid(repo, tree1)?;
+    let tree2_oid = get_oid(repo, tree2)?;
+    if merge::is_ancestor(repo, &tree2_oid, &tree1_oid)? {
+        return Ok(MergeOutcome::UpToDate);
     }
+    ensure_clean(repo)?;
+
+    let config = Config::load(Some(repo))?;
+    let fast_forward = match options.fast_forward {
+        Some(fast_forward) => fast_forward,
+        None => match config.get("merge.ff") {
+            Some(value) => value.parse()?,
+            None => FastForward::default(),
+        },
+    };
+    let can_fast_forward = merge::is_ancestor(repo, &tree1_oid, &tree2_oid)?;
+    if fast_forward == FastForward::Only && !can_fast_forward {
+        return Err(Error::NotFastForward);
+    }
+
+    data::set_ref(
+        repo,
+        "ORIG_HEAD",
+        RefValue::new(Some(tree1_oid.clone())),
+        false,
+    )?;
+    if can_fast_forward && fast_forward != FastForward::Never && !options.squash {
+        read_tree(repo, &data::get_commit(repo, &tree2_oid)?.tree)?;
+        update_ref(
+            repo,
+            "HEAD",
+            &tree2_oid,
+            &format!("merge {tree2}: Fast-forward"),
+        )?;
+        return Ok(MergeOutcome::FastForward(tree1_oid, tree2_oid));
+    }
+
+    let xdiff_options = xdiff::MergeOptions {
+        style: match config.get("merge.conflictStyle") {
+            Some(style) => style.parse()?,
+            None => xdiff::ConflictStyle::default(),
+        },
+        favor: options.favor,
+        labels: [tree1.to_string(), String::new(), tree2.to_string()],
+        ..xdiff::MergeOptions::default()
+    };
+    let merged = merge::merge_commits(repo, &tree1_oid, &tree2_oid, &xdiff_options)?;
+    let message = match &options.message {
+        Some(message) => message.clone(),
+        None => merge_message(repo, tree2)?,
+    };
 
-    let head = value.value.unwrap();
-    assert!(head.starts_with("ref: refs/heads/"));
+    read_tree(repo, &merged.tree)?;
+    // a squash is committed as an ordinary commit, so nothing marks it as a
+    // merge; the log of what it squashed is saved for that commit instead
+    if options.squash {
+        let squash_msg = squash_message(repo, &tree1_oid, &tree2_oid)?;
+        fs::write(repo.git_dir().join("SQUASH_MSG"), squash_msg)?;
+    } else {
+        data::set_ref(repo, "MERGE_HEAD", RefValue::new(Some(tree2_oid)), false)?;
+    }
+
+    if !merged.conflicts.is_empty() {
+        record_conflicts(repo, &merged.conflicts)?;
+        if !options.squash {
+            let mut merge_msg = format!("{message}\n\n# Conflicts:\n");
+            for conflict in &merged.conflicts {
+                merge_msg.push_str(&format!("#\t{}\n", conflict.path));
+            }
+            fs::write(repo.git_dir().join("MERGE_MSG"), merge_msg)?;
+        }
+
+        for conflict in &merged.conflicts {
+            println!("{conflict}");
+        }
+        return Err(Error::MergeConflict);
+    }
 
-    Some(head.split("ref: refs/heads/").last().unwrap().to_string())
+    if options.squash {
+        return Ok(MergeOutcome::Squashed);
+    }
+    Ok(MergeOutcome::Merged(commit(repo, &message, None, None)?))
 }
 
-pub fn reset(oid: &str) {
-    let value = RefValue::new(Some(oid.to_string()));
-    data::set_ref("HEAD", value, true);
+/// The commit being merged while a conflicted merge waits to be concluded.
+pub fn merge_head(repo: &Repository) -> Result<Option<String>> {
+    let value = data::get_ref(repo, "MERGE_HEAD", false)?.value;
+    Ok(value.map(|oid| oid.trim().to_string()))
 }
 
-pub fn read_tree_merged(tree1: &str, tree2: &str) {
-    let tree1_oid = get_oid(tree1);
-    let tree2_oid = get_oid(tree2);
-    let tree_oid = diff::merge(&tree1_oid, &tree2_oid);
+/// Commits a conflicted merge once every conflict has been resolved and
+/// staged, using the message saved when the merge stopped.
+pub fn continue_merge(repo: &Repository) -> Result<String> {
+    if merge_head(repo)?.is_none() {
+        return Err(Error::NoMergeInProgress);
+    }
 
-    let mut commit = "tree ".to_string();
-    commit.push_str(&tree_oid);
-    commit.push('\n');
+    let message = saved_message(repo, "MERGE_MSG")?.unwrap_or_default();
+    commit(repo, &message, None, None)
+}
 
-    commit.push_str("parent ");
-    commit.push_str(&tree1_oid);
-    commit.push('\n');
+/// The message a `merge --squash` saved for the commit that concludes it,
+/// or None if there is no squash to commit.
+pub fn squash_msg(repo: &Repository) -> Result<Option<String>> {
+    saved_message(repo, "SQUASH_MSG")
+}
 
-    commit.push_str("parent ");
-    commit.push_str(&tree2_oid);
-    commit.push('\n');
+// a message file in the rgit directory, without its comment lines
+fn saved_message(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let contents = match fs::read_to_string(repo.git_dir().join(name)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let message = contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n");
 
-    commit.push('\n');
-    commit.push_str("merge message");
-    commit.push('\n');
+    Ok(Some(message.trim_end().to_string()))
+}
 
-    let oid = data::hash_object(&commit, "commit");
-    let tmp = RefValue::new(Some(oid.clone()));
-    data::set_ref(tree1, tmp, true);
+// "Squashed commit of the following:" and the log of the commits being
+// squashed, as git writes it
+fn squash_message(repo: &Repository, head: &str, other: &str) -> Result<String> {
+    let tips = [
+        revision::Tip {
+            oid: other.to_string(),
+            excluded: false,
+        },
+        revision::Tip {
+            oid: head.to_string(),
+            excluded: true,
+        },
+    ];
+    let walk = walk::commits(repo, &tips, &walk::WalkOptions::default())?;
+
+    let mut message = String::from("Squashed commit of the following:\n");
+    for oid in &walk.commits {
+        let commit = data::get_commit(repo, oid)?;
+        let entry =
+            pretty::format_commit(repo, oid, &commit, &pretty::Format::Medium, None, false)?;
+        message.push('\n');
+        message.push_str(&entry);
+    }
 
-    println!("{commit}");
-    read_tree(&tree_oid);
+    Ok(message)
 }
 
-pub fn create_tag(name: &str, oid: &str) {
-    let tmp = RefValue::new(Some(oid.to_string()));
-    data::set_ref(&format!("refs/tags/{name}"), tmp, true);
+/// Throws away a conflicted merge, putting HEAD's files back in the index
+/// and the work tree.
+pub fn abort_merge(repo: &Repository) -> Result<()> {
+    if merge_head(repo)?.is_none() {
+        return Err(Error::NoMergeInProgress);
+    }
+
+    let head = get_oid(repo, "HEAD")?;
+    read_tree(repo, &data::get_commit(repo, &head)?.tree)?;
+    clear_merge_state(repo)
 }
 
-pub fn create_branch(name: &str, oid: &str) {
-    let tmp = RefValue::new(Some(oid.to_string()));
-    data::set_ref(&format!("refs/heads/{name}"), tmp, true);
+fn clear_merge_state(repo: &Repository) -> Result<()> {
+    for name in ["MERGE_HEAD", "MERGE_MSG"] {
+        remove_state_file(repo, name)?;
+    }
+
+    Ok(())
 }
 
-pub fn print_commit(modi_contents: &Vec<(String, String)>) {
-    for (i, j) in modi_contents {
-        let arg1 = format!("{}", data::get_object(i, None));
-        let arg2 = format!("{}", data::get_object(j, None));
+fn remove_state_file(repo: &Repository, name: &str) -> Result<()> {
+    match fs::remove_file(repo.git_dir().join(name)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
 
-        let mut file1 = NamedTempFile::new().unwrap();
-        file1.write_all(arg1.as_bytes()).unwrap();
+// "Merge branch 'topic' into dev", leaving out the target on master and main
+fn merge_message(repo: &Repository, name: &str) -> Result<String> {
+    let kind = if is_branch(repo, name)? {
+        "branch"
+    } else if data::get_ref(repo, &format!("refs/tags/{name}"), false)?
+        .value
+        .is_some()
+    {
+        "tag"
+    } else {
+        "commit"
+    };
 
-        let mut file2 = NamedTempFile::new().unwrap();
-        file2.write_all(arg2.as_bytes()).unwrap();
+    let mut message = format!("Merge {kind} '{name}'");
+    if let Some(current) = get_status_name(repo)? {
+        if current != "master" && current != "main" {
+            message.push_str(&format!(" into {current}"));
+        }
+    }
 
-        let mut child = Command::new("diff")
-            .args([
-                "--text",
-                "--unified",
-                file1.path().to_str().unwrap(),
-                file2.path().to_str().unwrap(),
-            ])
-            .spawn()
-            .expect("failed to spawn child process");
+    Ok(message)
+}
 
-        child.wait().expect("failed to wait for child process");
+// Replaces the merged entry of each conflicted path with the base, our and
+// their versions as stages 1, 2 and 3.
+fn record_conflicts(repo: &Repository, conflicts: &[merge::Conflict]) -> Result<()> {
+    let mut index = Index::read(repo)?;
+    for conflict in conflicts {
+        let path = match &conflict.kind {
+            merge::ConflictKind::FileDirectory { moved_to, .. } => moved_to,
+            _ => &conflict.path,
+        };
+        let sides = [
+            (1, &conflict.base),
+            (2, &conflict.ours),
+            (3, &conflict.theirs),
+        ];
+        for (stage, side) in sides {
+            if let Some((mode, oid)) = side {
+                let mut entry = IndexEntry::new(path, *mode, oid);
+                entry.stage = stage;
+                index.add_unmerged(entry);
+            }
+        }
     }
+
+    index.write(repo)
+}
+
+pub fn create_tag(repo: &Repository, name: &str, oid: &str) -> Result<()> {
+    let tmp = RefValue::new(Some(oid.to_string()));
+    data::set_ref(repo, &format!("refs/tags/{name}"), tmp, true)
 }
 
-pub fn get_commit(oid: &str) {
-    let branch_oids = iter_branch_contents();
-    let mut refs = String::new();
+pub fn create_branch(repo: &Repository, name: &str, oid: &str) -> Result<()> {
+    let message = format!("branch: Created from {oid}");
+    update_ref(repo, &format!("refs/heads/{name}"), oid, &message)
+}
 
-    for (br, br_oid) in branch_oids {
-        if br_oid == oid.to_string() {
-            let b = format!("<- {br} ");
-            refs.push_str(&b);
+/// Prints a git-style patch for each change.
+pub fn print_commit(
+    repo: &Repository,
+    changes: &[diff::TreeChange],
+    options: &xdiff::Options,
+) -> Result<()> {
+    const NULL_OID: &str = "0000000";
+
+    for change in changes {
+        let (old_path, path) = (change.source_path(), &change.path);
+        println!("diff --git a/{old_path} b/{path}");
+
+        let (old_oid, new_oid) = match (&change.old, &change.new) {
+            (None, Some((mode, oid))) => {
+                println!("new file mode {mode:o}");
+                println!("index {NULL_OID}..{}", &oid[..7]);
+                (None, Some(oid))
+            }
+            (Some((mode, oid)), None) => {
+                println!("deleted file mode {mode:o}");
+                println!("index {}..{NULL_OID}", &oid[..7]);
+                (Some(oid), None)
+            }
+            (Some((old_mode, old_oid)), Some((new_mode, new_oid))) => {
+                if old_mode != new_mode {
+                    println!("old mode {old_mode:o}");
+                    println!("new mode {new_mode:o}");
+                }
+                if change.old_path.is_some() {
+                    let kind = match change.change {
+                        diff::FileChange::Copied => "copy",
+                        _ => "rename",
+                    };
+                    println!("similarity index {}%", change.similarity);
+                    println!("{kind} from {old_path}");
+                    println!("{kind} to {path}");
+                }
+                if old_oid == new_oid {
+                    continue;
+                }
+                if old_mode == new_mode {
+                    println!("index {}..{} {old_mode:o}", &old_oid[..7], &new_oid[..7]);
+                } else {
+                    println!("index {}..{}", &old_oid[..7], &new_oid[..7]);
+                }
+                (Some(old_oid), Some(new_oid))
+            }
+            (None, None) => continue,
+        };
+
+        let old = match old_oid {
+            Some(oid) => diff::blob_contents(repo, oid, old_path)?,
+            None => vec![],
+        };
+        let new = match new_oid {
+            Some(oid) => diff::blob_contents(repo, oid, path)?,
+            None => vec![],
+        };
+        let old_name = old_oid.map_or(String::from("/dev/null"), |_| format!("a/{old_path}"));
+        let new_name = new_oid.map_or(String::from("/dev/null"), |_| format!("b/{path}"));
+        if xdiff::is_binary(&old) || xdiff::is_binary(&new) {
+            println!("Binary files {old_name} and {new_name} differ");
+            continue;
         }
+
+        println!("--- {old_name}");
+        println!("+++ {new_name}");
+        io::stdout().write_all(&xdiff::unified(&old, &new, options))?;
     }
 
-    let commit = data::get_object(oid, Some("commit"));
-    println!("commit {oid} {refs}");
+    Ok(())
+}
 
-    let mut lines = commit.lines().collect::<Vec<&str>>();
-    let message = lines.pop().unwrap();
-    println!("    {message}\n");
+/// Prints `changes` in one of the short forms of `--stat`, `--numstat`,
+/// `--shortstat`, `--name-only` or `--name-status`.
+pub fn print_summary(
+    repo: &Repository,
+    changes: &[diff::TreeChange],
+    summary: diff::Summary,
+    options: &xdiff::Options,
+) -> Result<()> {
+    print!(
+        "{}",
+        format_summary(repo, changes, summary, options, STAT_WIDTH)?
+    );
+    Ok(())
+}
 
-    if let Some(parent_oid) = lines[1].split_whitespace().nth(1) {
-        get_commit(parent_oid)
+// how many columns `--stat` fits into, as git does when not writing to a
+// terminal
+const STAT_WIDTH: usize = 80;
+
+/// `print_summary` into a string, with `--stat` fitted into `width` columns.
+pub fn format_summary(
+    repo: &Repository,
+    changes: &[diff::TreeChange],
+    summary: diff::Summary,
+    options: &xdiff::Options,
+    width: usize,
+) -> Result<String> {
+    let mut out = String::new();
+    match summary {
+        diff::Summary::NameOnly => {
+            for change in changes {
+                out.push_str(&format!("{}\n", change.path));
+            }
+            return Ok(out);
+        }
+        diff::Summary::NameStatus => {
+            for change in changes {
+                match &change.old_path {
+                    Some(old_path) => out.push_str(&format!(
+                        "{}\t{old_path}\t{}\n",
+                        change.status(),
+                        change.path
+                    )),
+                    None => out.push_str(&format!("{}\t{}\n", change.status(), change.path)),
+                }
+            }
+            return Ok(out);
+        }
+        _ => {}
     }
 
-    if lines.len() > 3 {
-        println!("another parent ----------");
-        if let Some(parent_oid) = lines[2].split_whitespace().nth(1) {
-            get_commit(parent_oid)
+    let mut stats = vec![];
+    for change in changes {
+        stats.push(diff::file_stat(repo, change, options.algorithm)?);
+    }
+    match summary {
+        diff::Summary::Numstat => {
+            for (change, stat) in changes.iter().zip(&stats) {
+                let path = diff::display_path(change);
+                match stat.binary {
+                    Some(_) => out.push_str(&format!("-\t-\t{path}\n")),
+                    None => out.push_str(&format!("{}\t{}\t{path}\n", stat.added, stat.deleted)),
+                }
+            }
         }
+        diff::Summary::Stat => {
+            format_stat(&mut out, changes, &stats, width);
+            format_shortstat(&mut out, &stats);
+        }
+        _ => format_shortstat(&mut out, &stats),
     }
+
+    Ok(out)
 }
 
-pub fn get_oid(mut name: &str) -> String {
-    if name == "@" {
-        name = "HEAD";
+// the histogram of `--stat`, fitted into `width` columns
+fn format_stat(
+    out: &mut String,
+    changes: &[diff::TreeChange],
+    stats: &[diff::FileStat],
+    width: usize,
+) {
+    let names = changes.iter().map(diff::display_path).collect::<Vec<_>>();
+    let max_change = stats
+        .iter()
+        .filter(|s| s.binary.is_none())
+        .map(|s| s.added + s.deleted)
+        .max()
+        .unwrap_or(0);
+    let mut number_width = max_change.to_string().len();
+    if stats.iter().any(|s| s.binary.is_some()) {
+        number_width = number_width.max(3);
     }
+    let mut name_width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+    let mut graph_width = max_change;
 
-    let refs_to_try = vec![
-        format!("{name}"),
-        format!("refs/{name}"),
-        format!("refs/tags/{name}"),
-        format!("refs/heads/{name}"),
-    ];
+    if name_width + number_width + 6 + graph_width > width {
+        graph_width = graph_width.min((width * 3 / 8).saturating_sub(number_width + 6).max(6));
+        if name_width > width.saturating_sub(number_width + 6 + graph_width) {
+            name_width = width.saturating_sub(number_width + 6 + graph_width);
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+    let scale = |n: usize| match n {
+        0 => 0,
+        n => 1 + n * (graph_width - 1) / max_change,
+    };
+
+    for (name, stat) in names.iter().zip(stats) {
+        // long names keep their end, cut at a directory if possible
+        let chars = name.chars().count();
+        let (prefix, name) = if chars > name_width {
+            let tail = name
+                .chars()
+                .skip(chars - name_width.saturating_sub(3))
+                .collect::<String>();
+            match tail.find('/') {
+                Some(slash) => ("...", tail[slash..].to_string()),
+                None => ("...", tail),
+            }
+        } else {
+            ("", name.clone())
+        };
+        let pad = name_width.saturating_sub(prefix.len());
+        out.push_str(&format!(" {prefix}{name:<pad$} |"));
+
+        if let Some((old_size, new_size)) = stat.binary {
+            out.push_str(&format!(" {:>number_width$}", "Bin"));
+            if old_size != 0 || new_size != 0 {
+                out.push_str(&format!(" {old_size} -> {new_size} bytes"));
+            }
+            out.push('\n');
+            continue;
+        }
 
-    for r in refs_to_try {
-        if let Some(r) = data::get_ref(&r, true).value {
-            return r;
+        let (mut added, mut deleted) = (stat.added, stat.deleted);
+        if graph_width < max_change {
+            let mut total = scale(added + deleted);
+            if total < 2 && added > 0 && deleted > 0 {
+                total = 2;
+            }
+            if added < deleted {
+                added = scale(added);
+                deleted = total - added;
+            } else {
+                deleted = scale(deleted);
+                added = total - deleted;
+            }
         }
+        let changed = stat.added + stat.deleted;
+        let space = if changed > 0 { " " } else { "" };
+        out.push_str(&format!(
+            " {changed:>number_width$}{space}{}{}\n",
+            "+".repeat(added),
+            "-".repeat(deleted)
+        ));
     }
+}
 
-    if let Some(oid) = data::get_ref(name, true).value {
-        return oid;
+fn format_shortstat(out: &mut String, stats: &[diff::FileStat]) {
+    let files = stats.len();
+    if files == 0 {
+        out.push_str(" 0 files changed\n");
+        return;
+    }
+
+    let insertions: usize = stats.iter().map(|s| s.added).sum();
+    let deletions: usize = stats.iter().map(|s| s.deleted).sum();
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let mut line = format!(" {files} file{} changed", plural(files));
+    if insertions > 0 || deletions == 0 {
+        line.push_str(&format!(
+            ", {insertions} insertion{}(+)",
+            plural(insertions)
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        line.push_str(&format!(", {deletions} deletion{}(-)", plural(deletions)));
     }
+    out.push_str(&format!("{line}\n"));
+}
+
+/// What `log` prints about each commit.
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    pub format: pretty::Format,
+    /// Label commits with the refs pointing at them, named this way.
+    pub decorate: Option<pretty::Decorate>,
+    /// Draw the history graph left of the commits.
+    pub graph: bool,
+    /// Name commits by abbreviated oids.
+    pub abbrev_commit: bool,
+    /// Print the changes in this short form; by default they are not shown.
+    pub summary: Option<diff::Summary>,
+    /// Only print the changes under these paths, unless it is empty.
+    pub paths: Vec<String>,
+    pub renames: diff::Renames,
+    pub diff: xdiff::Options,
+}
+
+/// When a commit was made, or 0 for commits without a committer line.
+pub fn commit_time(commit: &Commit) -> i64 {
+    commit.committer.as_ref().map_or(0, |c| c.time)
+}
+
+/// Prints the commits of `walk` as log entries.
+pub fn log(repo: &Repository, walk: &walk::Walk, options: &LogOptions) -> Result<()> {
+    // %d and %D show the refs even without --decorate
+    let decorate = match &options.format {
+        pretty::Format::Template { template, .. }
+            if template.contains("%d") || template.contains("%D") =>
+        {
+            options.decorate.or(Some(pretty::Decorate::Short))
+        }
+        _ => options.decorate,
+    };
+    let decorations = decorate
+        .map(|style| pretty::Decorations::load(repo, style))
+        .transpose()?;
+    let mut graph = options.graph.then(Graph::new);
+    let terminated = options.format.uses_terminator();
+    // whether the previous entry's text ended without a newline, so the
+    // graph must not be drawn before the blank line separating entries
+    let mut missing_newline = None;
+
+    for oid in &walk.commits {
+        let commit = data::get_commit(repo, oid)?;
+        let mut out = String::new();
+        if let Some(graph) = &mut graph {
+            graph.update(oid, walk.parents.get(oid).map_or(&[], Vec::as_slice));
+        }
+
+        if let Some(missing_newline) = missing_newline.filter(|_| !terminated) {
+            if let (Some(graph), false) = (&mut graph, missing_newline) {
+                out.push_str(&graph.padding_line());
+            }
+            out.push('\n');
+        }
+
+        let text = pretty::format_commit(
+            repo,
+            oid,
+            &commit,
+            &options.format,
+            decorations.as_ref(),
+            options.abbrev_commit,
+        )?;
+        match &mut graph {
+            Some(graph) => {
+                graph.show_commit(&mut out);
+                graph.show_commit_msg(&mut out, &text);
+            }
+            None => out.push_str(&text),
+        }
+        if terminated && !options.format.is_empty() {
+            if let (Some(graph), true) = (&mut graph, text.ends_with('\n')) {
+                out.push_str(&graph.padding_line());
+            }
+            out.push('\n');
+        }
+        missing_newline = Some(!text.ends_with('\n'));
+
+        // like git, merges are listed without their changes
+        if let (Some(summary), [] | [_]) = (options.summary, &commit.parents[..]) {
+            let changes = diff::compare_trees(repo, oid, &options.paths, &options.renames)?;
+            let width = graph.as_ref().map_or(0, Graph::width);
+            let mut text = String::new();
+            if options.format != pretty::Format::Oneline && !options.format.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&format_summary(
+                repo,
+                &changes,
+                summary,
+                &options.diff,
+                STAT_WIDTH.saturating_sub(width),
+            )?);
+            for line in text.lines() {
+                if let Some(graph) = &mut graph {
+                    out.push_str(&graph.padding_line());
+                }
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
 
-    if <[u8; 20]>::from_hex(name).is_ok() {
-        return name.to_string();
+        print!("{out}");
     }
 
-    unreachable!()
+    Ok(())
 }
 
-fn is_dot_path(path: &Path) -> bool {
-    format!("{path:?}").split('/').last().unwrap().as_bytes()[0] == b'.'
+/// Resolves a revision such as `HEAD~2` or `topic@{1}`; see `revision::resolve`.
+pub fn get_oid(repo: &Repository, name: &str) -> Result<String> {
+    revision::resolve(repo, name)
 }
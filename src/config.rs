@@ -0,0 +1,675 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::Repository;
+
+/// Where a setting comes from. Later levels override earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    System,
+    Global,
+    Local,
+}
+
+impl Level {
+    /// The file backing this level: `/etc/rgitconfig`, `~/.rgitconfig` or
+    /// `.rgit/config`. The first two can be moved with `RGIT_CONFIG_SYSTEM`
+    /// and `RGIT_CONFIG_GLOBAL`.
+    pub fn path(self, repo: Option<&Repository>) -> Option<PathBuf> {
+        match self {
+            Level::System => Some(
+                env::var_os("RGIT_CONFIG_SYSTEM")
+                    .map_or_else(|| PathBuf::from("/etc/rgitconfig"), PathBuf::from),
+            ),
+            Level::Global => env::var_os("RGIT_CONFIG_GLOBAL")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rgitconfig"))
+                }),
+            Level::Local => repo.map(|repo| repo.git_dir().join("config")),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::System => write!(f, "system"),
+            Level::Global => write!(f, "global"),
+            Level::Local => write!(f, "local"),
+        }
+    }
+}
+
+/// One `key = value` line. A key written without `=` has no value, which
+/// counts as true for booleans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The canonical `section.subsection.name` key, with the section and
+    /// name lowercased.
+    pub key: String,
+    pub value: Option<String>,
+    pub origin: PathBuf,
+    pub level: Level,
+}
+
+/// The settings of every level merged in precedence order.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<Entry>,
+}
+
+impl Config {
+    /// Reads the system, global and, given a repository, local files.
+    pub fn load(repo: Option<&Repository>) -> Result<Config> {
+        let mut config = Config::default();
+        for level in [Level::System, Level::Global, Level::Local] {
+            if let Some(path) = level.path(repo) {
+                config
+                    .entries
+                    .extend(ConfigFile::read(&path)?.entries(level));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads a single level only.
+    pub fn load_level(repo: Option<&Repository>, level: Level) -> Result<Config> {
+        let entries = match level.path(repo) {
+            Some(path) => ConfigFile::read(&path)?.entries(level),
+            None => vec![],
+        };
+
+        Ok(Config { entries })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The value with the highest precedence, i.e. the last one read.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = canonical_key(key).ok()?;
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.key == key)
+            .map(|e| e.value.as_deref().unwrap_or(""))
+    }
+
+    /// Every value of a multi-valued key, lowest precedence first.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let Ok(key) = canonical_key(key) else {
+            return vec![];
+        };
+        self.entries
+            .iter()
+            .filter(|e| e.key == key)
+            .map(|e| e.value.as_deref().unwrap_or(""))
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        let key = canonical_key(key)?;
+        match self.entries.iter().rev().find(|e| e.key == key) {
+            None => Ok(None),
+            Some(Entry { value: None, .. }) => Ok(Some(true)),
+            Some(Entry {
+                value: Some(value), ..
+            }) => parse_bool(value)
+                .map(Some)
+                .ok_or_else(|| bad_value(&key, value)),
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) => parse_int(value)
+                .map(Some)
+                .ok_or_else(|| bad_value(key, value)),
+        }
+    }
+
+    /// A path value, with a leading `~/` expanded to the home directory.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        self.get(key).map(expand_path)
+    }
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// An integer with an optional `k`, `m` or `g` suffix.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+pub fn expand_path(value: &str) -> PathBuf {
+    match (value.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(value),
+    }
+}
+
+fn bad_value(key: &str, value: &str) -> Error {
+    Error::BadConfig(format!("bad value '{value}' for '{key}'"))
+}
+
+/// Splits a key into section, optional subsection and name, lowercasing
+/// the parts that are case-insensitive.
+fn split_key(key: &str) -> Result<(String, Option<String>, String)> {
+    let invalid = || Error::BadConfig(format!("invalid key: {key}"));
+    let (section, rest) = key.split_once('.').ok_or_else(invalid)?;
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection.to_string()), name),
+        None => (None, rest),
+    };
+
+    let is_name =
+        |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !is_name(section) || !is_name(name) || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(invalid());
+    }
+
+    Ok((section.to_lowercase(), subsection, name.to_lowercase()))
+}
+
+fn canonical_key(key: &str) -> Result<String> {
+    let (section, subsection, name) = split_key(key)?;
+    Ok(match subsection {
+        Some(subsection) => format!("{section}.{subsection}.{name}"),
+        None => format!("{section}.{name}"),
+    })
+}
+
+/// A parsed line of a config file. The original text is kept so rewriting
+/// a file leaves untouched lines, comments included, exactly as they were.
+#[derive(Debug, Clone)]
+enum Line {
+    /// `[section]` or `[section "subsection"]`, as a canonical key prefix.
+    Section {
+        prefix: String,
+        text: String,
+    },
+    Entry {
+        key: String,
+        value: Option<String>,
+        text: String,
+    },
+    Other(String),
+}
+
+/// One config file, as read for editing.
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    path: PathBuf,
+    lines: Vec<Line>,
+}
+
+impl ConfigFile {
+    /// Reads and parses `path`; a missing file is empty.
+    pub fn read(path: &Path) -> Result<ConfigFile> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut lines = vec![];
+        let mut prefix = None;
+        let mut pending = String::new();
+
+        for (i, raw) in text.lines().enumerate() {
+            let bad_line = || {
+                Error::BadConfig(format!(
+                    "bad config line {} in file {}",
+                    i + 1,
+                    path.display()
+                ))
+            };
+
+            // a trailing backslash continues the value on the next line,
+            // unless it is itself escaped by the one before it
+            pending.push_str(raw);
+            let backslashes = raw.bytes().rev().take_while(|&b| b == b'\\').count();
+            if backslashes % 2 == 1 {
+                pending.push('\n');
+                continue;
+            }
+            let text = std::mem::take(&mut pending);
+            let trimmed = text.trim();
+
+            if trimmed.is_empty() || trimmed.starts_with(['#', ';']) {
+                lines.push(Line::Other(text));
+            } else if trimmed.starts_with('[') {
+                let section = parse_section(trimmed).ok_or_else(bad_line)?;
+                prefix = Some(section.clone());
+                lines.push(Line::Section {
+                    prefix: section,
+                    text,
+                });
+            } else {
+                let section = prefix.as_ref().ok_or_else(bad_line)?;
+                let (name, value) = parse_entry(trimmed).ok_or_else(bad_line)?;
+                lines.push(Line::Entry {
+                    key: format!("{section}.{}", name.to_lowercase()),
+                    value,
+                    text,
+                });
+            }
+        }
+
+        Ok(ConfigFile {
+            path: path.to_path_buf(),
+            lines,
+        })
+    }
+
+    fn entries(&self, level: Level) -> Vec<Entry> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Entry { key, value, .. } => Some(Entry {
+                    key: key.clone(),
+                    value: value.clone(),
+                    origin: self.path.clone(),
+                    level,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sets `key` to `value`. With `add` a new value is appended to a
+    /// multi-valued key; otherwise the key must have at most one value.
+    pub fn set(&mut self, key: &str, value: &str, add: bool) -> Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let key = canonical_key(key)?;
+        let text = format!("\t{name} = {}", quote_value(value));
+        let entry = Line::Entry {
+            key: key.clone(),
+            value: Some(value.to_string()),
+            text,
+        };
+
+        let existing = self.positions(&key);
+        if !add {
+            match existing[..] {
+                [] => {}
+                [i] => {
+                    self.lines[i] = entry;
+                    return Ok(());
+                }
+                _ => {
+                    return Err(Error::BadConfig(format!(
+                        "cannot overwrite multiple values of '{key}' with a single value"
+                    )))
+                }
+            }
+        }
+
+        // after the last entry of the section, or at the end of a new one
+        let prefix = key[..key.len() - name.len() - 1].to_string();
+        let mut position = None;
+        let mut in_section = false;
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Section { prefix: p, .. } => {
+                    in_section = *p == prefix;
+                    if in_section {
+                        position = Some(i + 1);
+                    }
+                }
+                Line::Entry { .. } if in_section => position = Some(i + 1),
+                _ => {}
+            }
+        }
+
+        match position {
+            Some(i) => self.lines.insert(i, entry),
+            None => {
+                let header = match subsection {
+                    Some(subsection) => format!("[{section} \"{}\"]", escape(&subsection)),
+                    None => format!("[{section}]"),
+                };
+                self.lines.push(Line::Section {
+                    prefix,
+                    text: header,
+                });
+                self.lines.push(entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes `key`, or every value of it with `all`. Returns whether
+    /// anything was removed.
+    pub fn unset(&mut self, key: &str, all: bool) -> Result<bool> {
+        let key = canonical_key(key)?;
+        let existing = self.positions(&key);
+        if existing.len() > 1 && !all {
+            return Err(Error::BadConfig(format!("'{key}' has multiple values")));
+        }
+
+        for i in existing.iter().rev() {
+            self.lines.remove(*i);
+        }
+
+        Ok(!existing.is_empty())
+    }
+
+    fn positions(&self, key: &str) -> Vec<usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| matches!(line, Line::Entry { key: k, .. } if k == key))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Writes the file through a lock file, like the index.
+    pub fn write(&self) -> Result<()> {
+        let mut out = String::new();
+        for line in &self.lines {
+            let (Line::Section { text, .. } | Line::Entry { text, .. } | Line::Other(text)) = line;
+            out.push_str(text);
+            out.push('\n');
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut lock = self.path.clone().into_os_string();
+        lock.push(".lock");
+        fs::write(&lock, out)?;
+        fs::rename(lock, &self.path)?;
+
+        Ok(())
+    }
+}
+
+// `[core]`, `[remote "origin"]` or the older `[branch.main]`
+fn parse_section(line: &str) -> Option<String> {
+    let inner = line.strip_prefix('[')?;
+    let end = inner.rfind(']')?;
+    if !is_comment_or_empty(&inner[end + 1..]) {
+        return None;
+    }
+    let inner = &inner[..end];
+
+    match inner.split_once(char::is_whitespace) {
+        Some((section, subsection)) => {
+            let subsection = subsection.trim().strip_prefix('"')?.strip_suffix('"')?;
+            Some(format!(
+                "{}.{}",
+                section.to_lowercase(),
+                unescape(subsection)?
+            ))
+        }
+        None => match inner.split_once('.') {
+            Some((section, subsection)) => Some(format!(
+                "{}.{}",
+                section.to_lowercase(),
+                subsection.to_lowercase()
+            )),
+            None if !inner.is_empty() => Some(inner.to_lowercase()),
+            None => None,
+        },
+    }
+}
+
+fn is_comment_or_empty(text: &str) -> bool {
+    let text = text.trim();
+    text.is_empty() || text.starts_with(['#', ';'])
+}
+
+// `name = value`, or a bare `name` meaning true
+fn parse_entry(line: &str) -> Option<(String, Option<String>)> {
+    let Some((name, value)) = line.split_once('=') else {
+        let name = line.split(['#', ';']).next()?.trim();
+        return Some((name.to_string(), None));
+    };
+
+    let mut out = String::new();
+    let mut quoted = false;
+    let mut chars = value.trim().chars().peekable();
+    // whitespace is only kept when something follows it
+    let mut spaces = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                out.push_str(&std::mem::take(&mut spaces));
+                quoted = !quoted;
+            }
+            '\\' => {
+                out.push_str(&std::mem::take(&mut spaces));
+                match chars.next()? {
+                    'n' => out.push('\n'),
+                    't' => out.push('\t'),
+                    'b' => {
+                        out.pop();
+                    }
+                    '\n' => {}
+                    c @ ('\\' | '"') => out.push(c),
+                    _ => return None,
+                }
+            }
+            '#' | ';' if !quoted => break,
+            c if c.is_whitespace() && !quoted => spaces.push(c),
+            c => {
+                out.push_str(&std::mem::take(&mut spaces));
+                out.push(c);
+            }
+        }
+    }
+    if quoted {
+        return None;
+    }
+
+    Some((name.trim().to_string(), Some(out)))
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some(out)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// quotes are only added when the value would not read back as written
+fn quote_value(value: &str) -> String {
+    let escaped = escape(value).replace('\n', "\\n").replace('\t', "\\t");
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    if needs_quotes {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `text` as the local config file `name` in the temp dir.
+    fn parse(name: &str, text: &str) -> Result<ConfigFile> {
+        let path = env::temp_dir().join(format!("rgit-config-{}-{name}", std::process::id()));
+        fs::write(&path, text)?;
+        let file = ConfigFile::read(&path);
+        fs::remove_file(&path)?;
+        file
+    }
+
+    fn values(file: &ConfigFile) -> Vec<(String, Option<String>)> {
+        file.entries(Level::Local)
+            .into_iter()
+            .map(|e| (e.key, e.value))
+            .collect()
+    }
+
+    fn pair(key: &str, value: &str) -> (String, Option<String>) {
+        (key.to_string(), Some(value.to_string()))
+    }
+
+    #[test]
+    fn sections_and_subsections() {
+        let file = parse(
+            "sections",
+            "[Core]\n\tBare = false\n[remote \"Origin\"]\n\turl = x\n[branch.Main]\n\tremote = origin\n",
+        )
+        .unwrap();
+        assert_eq!(
+            values(&file),
+            [
+                pair("core.bare", "false"),
+                pair("remote.Origin.url", "x"),
+                pair("branch.main.remote", "origin"),
+            ]
+        );
+    }
+
+    #[test]
+    fn values_with_quotes_escapes_and_comments() {
+        let file = parse(
+            "values",
+            "[a]\n\
+             \tplain = one two   # comment\n\
+             \tquoted = \"  padded ; kept \"\n\
+             \tescaped = tab\\there \\\"q\\\"\n\
+             \tcontinued = first \\\n second\n\
+             \tflag\n\
+             \tempty =\n",
+        )
+        .unwrap();
+        assert_eq!(
+            values(&file),
+            [
+                pair("a.plain", "one two"),
+                pair("a.quoted", "  padded ; kept "),
+                pair("a.escaped", "tab\there \"q\""),
+                pair("a.continued", "first  second"),
+                ("a.flag".to_string(), None),
+                pair("a.empty", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_an_odd_run_of_trailing_backslashes_continues() {
+        let file = parse(
+            "backslashes",
+            "[a]\n\
+             \teven = dir\\\\\n\
+             \todd = dir\\\\\\\n more\n\
+             \tnext = x\n",
+        )
+        .unwrap();
+        assert_eq!(
+            values(&file),
+            [
+                pair("a.even", "dir\\"),
+                pair("a.odd", "dir\\ more"),
+                pair("a.next", "x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(parse("no-section", "key = value\n").is_err());
+        assert!(parse("open-section", "[core\n").is_err());
+        assert!(parse("open-quote", "[a]\n\tb = \"x\n").is_err());
+        assert!(parse("bad-escape", "[a]\n\tb = \\q\n").is_err());
+    }
+
+    #[test]
+    fn set_keeps_other_lines_and_quotes_when_needed() {
+        let mut file = parse("set", "# top\n[core]\n\tbare = false ; note\n").unwrap();
+        file.set("core.editor", "vi # not a comment", false)
+            .unwrap();
+        file.set("remote.origin.url", "x", false).unwrap();
+        file.set("core.bare", "true", false).unwrap();
+
+        let text = file
+            .lines
+            .iter()
+            .map(|line| match line {
+                Line::Section { text, .. } | Line::Entry { text, .. } | Line::Other(text) => {
+                    text.as_str()
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            text,
+            [
+                "# top",
+                "[core]",
+                "\tbare = true",
+                "\teditor = \"vi # not a comment\"",
+                "[remote \"origin\"]",
+                "\turl = x",
+            ]
+        );
+    }
+
+    #[test]
+    fn multiple_values() {
+        let mut file = parse("multi", "[a]\n\tb = 1\n\tb = 2\n").unwrap();
+        assert!(file.set("a.b", "3", false).is_err());
+        assert!(file.unset("a.b", false).is_err());
+        file.set("a.b", "3", true).unwrap();
+        assert_eq!(
+            values(&file),
+            [pair("a.b", "1"), pair("a.b", "2"), pair("a.b", "3")]
+        );
+        assert!(file.unset("A.B", true).unwrap());
+        assert!(values(&file).is_empty());
+    }
+
+    #[test]
+    fn keys_and_typed_values() {
+        assert_eq!(canonical_key("Core.Bare").unwrap(), "core.bare");
+        assert_eq!(
+            canonical_key("Remote.My.Origin.URL").unwrap(),
+            "remote.My.Origin.url"
+        );
+        assert!(canonical_key("core").is_err());
+        assert!(canonical_key("core.1x").is_err());
+
+        assert_eq!(parse_bool("Yes"), Some(true));
+        assert_eq!(parse_bool(""), Some(false));
+        assert_eq!(parse_bool("maybe"), None);
+        assert_eq!(parse_int("8k"), Some(8192));
+        assert_eq!(parse_int(" 2M"), Some(2 << 20));
+        assert_eq!(parse_int("-3"), Some(-3));
+        assert_eq!(parse_int("x"), None);
+    }
+}
@@ -1,8 +1,15 @@
 use std::fs::{self, File};
-use std::io::{self, Read, Write};
-use std::path::Path;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
 
-use crate::base;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::error::{Error, Result};
+use crate::object::{Commit, Object, Signature, Tree};
+use crate::Repository;
 
 pub const GIT_DIR: &str = ".rgit";
 
@@ -20,20 +27,15 @@ pub fn mkfile<P: AsRef<Path>>(path: P, data: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
-pub fn init() -> io::Result<()> {
-    mkdir(GIT_DIR)?;
-
-    let object = format!("{GIT_DIR}/objects");
-    mkdir(object)?;
-
-    let ref_path = format!("{GIT_DIR}/refs");
-    mkdir(ref_path)?;
-
-    let tags = format!("{GIT_DIR}/refs/tags");
-    mkdir(tags)?;
-
-    let heads = format!("{GIT_DIR}/refs/heads");
-    mkdir(heads)
+pub fn init(repo: &Repository) -> io::Result<()> {
+    let git_dir = repo.git_dir();
+    if !git_dir.exists() {
+        mkdir(git_dir)?;
+    }
+    mkdir(git_dir.join("objects"))?;
+    mkdir(git_dir.join("refs"))?;
+    mkdir(git_dir.join("refs/tags"))?;
+    mkdir(git_dir.join("refs/heads"))
 }
 
 #[derive(Debug)]
@@ -51,101 +53,276 @@ impl RefValue {
     }
 }
 
-pub fn set_ref(rgit_ref: &str, value: RefValue, deref: bool) {
-    let rgit_ref = get_ref_iner(rgit_ref, deref).0;
-    assert!(value.value.is_some());
-    let ref_value;
+pub fn set_ref(repo: &Repository, rgit_ref: &str, value: RefValue, deref: bool) -> Result<()> {
+    let rgit_ref = get_ref_iner(repo, rgit_ref, deref)?.0;
+    let Some(target) = value.value else {
+        return Err(Error::BadRevision(rgit_ref));
+    };
 
-    if value.symbolic {
-        ref_value = format!("ref: {}", value.value.unwrap());
+    let ref_value = if value.symbolic {
+        format!("ref: {target}")
     } else {
-        ref_value = value.value.unwrap();
-    }
+        target
+    };
 
-    let path = format!("{GIT_DIR}/{rgit_ref}");
+    let path = repo.git_dir().join(&rgit_ref);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    mkfile(path, ref_value.as_bytes())?;
 
-    mkfile(path, ref_value.as_bytes()).unwrap();
+    Ok(())
 }
 
-pub fn get_ref(rgit_ref: &str, deref: bool) -> RefValue {
-    get_ref_iner(rgit_ref, deref).1
+pub fn get_ref(repo: &Repository, rgit_ref: &str, deref: bool) -> Result<RefValue> {
+    Ok(get_ref_iner(repo, rgit_ref, deref)?.1)
 }
 
-fn get_ref_iner(rgit_ref: &str, deref: bool) -> (String, RefValue) {
-    let path = format!("{GIT_DIR}/{rgit_ref}");
+fn get_ref_iner(repo: &Repository, rgit_ref: &str, deref: bool) -> Result<(String, RefValue)> {
+    let path = repo.git_dir().join(rgit_ref);
     let mut value = None;
     let mut contents = String::new();
 
-    if let Ok(mut file) = File::open(path) {
-        file.read_to_string(&mut contents).unwrap();
-        value = Some(contents.clone());
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut contents)?;
+            value = Some(contents.clone());
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::IsADirectory => {}
+        Err(e) => return Err(e.into()),
     }
 
     let symbolic = !contents.is_empty() && contents.starts_with("ref:");
-    if symbolic {
-        if deref {
-            let value = contents.split(": ").nth(1).unwrap();
-            return get_ref_iner(value, true);
+    if symbolic && deref {
+        let value = contents.trim_start_matches("ref:").trim();
+        return get_ref_iner(repo, value, true);
+    }
+
+    Ok((rgit_ref.to_string(), RefValue { symbolic, value }))
+}
+
+/// The name `rgit_ref` ends up at after following symbolic refs, such as
+/// `refs/heads/master` for HEAD.
+pub fn resolve_ref(repo: &Repository, rgit_ref: &str) -> Result<String> {
+    Ok(get_ref_iner(repo, rgit_ref, true)?.0)
+}
+
+/// Every ref under `refs/` with the oid it points at, sorted by name.
+pub fn list_refs(repo: &Repository) -> Result<Vec<(String, String)>> {
+    let mut refs = vec![];
+    let mut dirs = vec![String::from("refs")];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(repo.git_dir().join(&dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = format!("{dir}/{}", entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                dirs.push(name);
+            } else if let Some(oid) = get_ref(repo, &name, true)?.value {
+                refs.push((name, oid.trim().to_string()));
+            }
         }
     }
 
-    (rgit_ref.to_string(), RefValue { symbolic, value })
+    refs.sort();
+    Ok(refs)
 }
 
-pub fn iter_refs() -> Vec<(String, Vec<String>)> {
-    let path = format!("{GIT_DIR}/refs/tags/");
-    fs::read_dir(path)
-        .unwrap()
-        .map(|res| {
-            let mut oids = Vec::new();
-            let filename = res.unwrap().file_name().into_string().unwrap();
-            let oid = base::get_oid(&filename);
-            oids.push(oid.clone());
-            get_commit_oid(&oid, &mut oids);
+/// An oid of all zeros, standing for a ref that did not exist.
+pub const NULL_OID: &str = "0000000000000000000000000000000000000000";
 
-            (filename, oids)
-        })
-        .collect::<Vec<(String, Vec<String>)>>()
+/// One move of a ref, as recorded in `logs/<ref>`.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub committer: Signature,
+    pub message: String,
 }
 
-fn get_commit_oid(oid: &str, oids: &mut Vec<String>) {
-    let commit = get_object(oid, Some("commit"));
-    let lines = commit.lines().collect::<Vec<&str>>();
+pub fn append_reflog(repo: &Repository, rgit_ref: &str, entry: &ReflogEntry) -> Result<()> {
+    let path = repo.git_dir().join("logs").join(rgit_ref);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let message = entry.message.lines().next().unwrap_or_default();
+    writeln!(
+        file,
+        "{} {} {}\t{message}",
+        entry.old, entry.new, entry.committer
+    )?;
+
+    Ok(())
+}
 
-    if let Some(parent_oid) = lines[1].split_whitespace().nth(1) {
-        oids.push(parent_oid.to_string());
-        get_commit_oid(parent_oid, oids)
+/// The reflog of `rgit_ref`, oldest entry first; empty when it has none.
+pub fn read_reflog(repo: &Repository, rgit_ref: &str) -> Result<Vec<ReflogEntry>> {
+    let contents = match fs::read_to_string(repo.git_dir().join("logs").join(rgit_ref)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut entries = vec![];
+    for line in contents.lines() {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = head.splitn(3, ' ');
+        let (Some(old), Some(new), Some(committer)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(Error::CorruptObject(format!("bad reflog line '{line}'")));
+        };
+        entries.push(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            committer: Signature::parse(committer)?,
+            message: message.to_string(),
+        });
     }
+
+    Ok(entries)
+}
+
+fn object_bytes(data: &[u8], type_obj: &str) -> Vec<u8> {
+    let mut obj = format!("{type_obj} {}\x00", data.len()).into_bytes();
+    obj.extend_from_slice(data);
+    obj
 }
 
-pub fn hash_object(data: &str, type_obj: &str) -> String {
-    let mut obj = type_obj.as_bytes().to_owned();
-    obj.push(b'\x00');
-    obj.append(&mut data.as_bytes().to_owned());
+/// The oid `hash_object` would give `data`, without storing anything.
+pub fn compute_oid(data: &[u8], type_obj: &str) -> String {
+    sha1_smol::Sha1::from(object_bytes(data, type_obj))
+        .digest()
+        .to_string()
+}
 
-    let oid = sha1_smol::Sha1::from(obj.clone()).digest().to_string();
-    let path = format!("{GIT_DIR}/objects/{oid}");
-    mkfile(path, &obj).expect("create failed");
+pub fn hash_object(repo: &Repository, data: &[u8], type_obj: &str) -> Result<String> {
+    let obj = object_bytes(data, type_obj);
+    let oid = sha1_smol::Sha1::from(&obj).digest().to_string();
+    let path = object_path(repo, &oid);
+    if path.exists() {
+        return Ok(oid);
+    }
+
+    let dir = path.parent().unwrap();
+    if !dir.exists() {
+        mkdir(dir)?;
+    }
 
-    oid
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&obj)?;
+    mkfile(path, &encoder.finish()?)?;
+
+    Ok(oid)
 }
 
-pub fn get_object(oid: &str, expected: Option<&str>) -> String {
-    let path = format!("{GIT_DIR}/objects/{oid}");
-    let mut file = File::open(path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
+pub fn has_object(repo: &Repository, oid: &str) -> bool {
+    object_path(repo, oid).is_file()
+}
+
+/// The stored oids that start with the hex digits of `prefix`.
+pub fn find_objects(repo: &Repository, prefix: &str) -> Result<Vec<String>> {
+    if prefix.len() < 2 {
+        return Ok(vec![]);
+    }
+
+    let (dir, rest) = prefix.split_at(2);
+    let entries = match fs::read_dir(repo.git_dir().join("objects").join(dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut oids = vec![];
+    for entry in entries {
+        let file = entry?.file_name().to_string_lossy().into_owned();
+        if file.starts_with(rest) {
+            oids.push(format!("{dir}{file}"));
+        }
+    }
+
+    oids.sort();
+    Ok(oids)
+}
 
-    let mut cont = contents
-        .split(b'\x00' as char)
-        .take(2)
-        .collect::<Vec<&str>>();
-    let content = cont.pop().unwrap();
-    let type_obj = cont.pop().unwrap();
+pub fn get_object(repo: &Repository, oid: &str, expected: Option<&str>) -> Result<Vec<u8>> {
+    let (type_obj, content) = read_raw_object(repo, oid)?;
 
     if let Some(expected_type) = expected {
-        assert_eq!(expected_type, type_obj);
+        if expected_type != type_obj {
+            return Err(Error::WrongObjectType {
+                oid: oid.to_string(),
+                expected: expected_type.to_string(),
+                actual: type_obj,
+            });
+        }
     }
 
-    content.to_string()
+    Ok(content)
+}
+
+fn read_raw_object(repo: &Repository, oid: &str) -> Result<(String, Vec<u8>)> {
+    let compressed = match fs::read(object_path(repo, oid)) {
+        Ok(compressed) => compressed,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(Error::ObjectNotFound(oid.to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut contents = Vec::new();
+    ZlibDecoder::new(&compressed[..])
+        .read_to_end(&mut contents)
+        .map_err(|e| Error::CorruptObject(format!("{oid}: {e}")))?;
+
+    let corrupt = |reason: &str| Error::CorruptObject(format!("{oid}: {reason}"));
+    let nul = contents
+        .iter()
+        .position(|&b| b == b'\x00')
+        .ok_or_else(|| corrupt("header not terminated"))?;
+    let content = contents.split_off(nul + 1);
+    let header = str::from_utf8(&contents[..nul]).map_err(|_| corrupt("bad header"))?;
+    let (type_obj, size) = header
+        .split_once(' ')
+        .ok_or_else(|| corrupt("header has no size"))?;
+
+    if size.parse::<usize>().ok() != Some(content.len()) {
+        return Err(corrupt("size does not match header"));
+    }
+
+    Ok((type_obj.to_string(), content))
+}
+
+/// Loose objects are fanned out like git: `objects/ab/cdef...`.
+fn object_path(repo: &Repository, oid: &str) -> PathBuf {
+    if oid.len() < 3 {
+        return repo.git_dir().join("objects").join(oid);
+    }
+    let (dir, file) = oid.split_at(2);
+    repo.git_dir().join("objects").join(dir).join(file)
+}
+
+pub fn get_tree(repo: &Repository, oid: &str) -> Result<Tree> {
+    Tree::parse(&get_object(repo, oid, Some("tree"))?)
+}
+
+pub fn get_commit(repo: &Repository, oid: &str) -> Result<Commit> {
+    Commit::parse(&get_object(repo, oid, Some("commit"))?)
+}
+
+pub fn read_object(repo: &Repository, oid: &str) -> Result<Object> {
+    let (type_obj, content) = read_raw_object(repo, oid)?;
+    Object::parse(&type_obj, &content)
+}
+
+pub fn write_object(repo: &Repository, object: &Object) -> Result<String> {
+    hash_object(repo, &object.serialize()?, object.kind())
 }
@@ -0,0 +1,134 @@
+use chrono::{DateTime, FixedOffset, Local, Months, NaiveDate, NaiveDateTime, Offset, TimeZone};
+
+use crate::error::{Error, Result};
+
+/// The current time as seconds since the epoch and the local UTC offset in minutes.
+pub fn now() -> (i64, i32) {
+    let now = Local::now();
+    (now.timestamp(), now.offset().fix().local_minus_utc() / 60)
+}
+
+/// Parses a date given to `--date` or `RGIT_AUTHOR_DATE`. Accepts git's
+/// internal `<epoch> <zone>` and `@<epoch>` forms, RFC 2822 and ISO 8601;
+/// dates without a zone are taken as local time.
+pub fn parse(date: &str) -> Result<(i64, i32)> {
+    let date = date.trim();
+    let bad = || Error::BadDate(date.to_string());
+
+    if let Some(epoch) = date.strip_prefix('@') {
+        return Ok((epoch.parse().map_err(|_| bad())?, 0));
+    }
+    if let Some((epoch, zone)) = date.split_once(' ') {
+        if let (Ok(epoch), Some(offset)) = (epoch.parse(), parse_zone(zone)) {
+            return Ok((epoch, offset));
+        }
+    }
+
+    let with_zone = DateTime::parse_from_rfc2822(date).or_else(|_| {
+        [
+            "%Y-%m-%dT%H:%M:%S%z",
+            "%Y-%m-%d %H:%M:%S %z",
+            "%Y-%m-%d %H:%M:%S%z",
+        ]
+        .iter()
+        .find_map(|format| DateTime::parse_from_str(date, format).ok())
+        .ok_or(())
+    });
+    if let Ok(date) = with_zone {
+        return Ok((date.timestamp(), date.offset().local_minus_utc() / 60));
+    }
+
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .ok_or_else(bad)?;
+    let local = Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(bad)?;
+    Ok((
+        local.timestamp(),
+        local.offset().fix().local_minus_utc() / 60,
+    ))
+}
+
+/// Parses a date given to `--since`, `--until` or `<ref>@{<date>}` into
+/// seconds since the epoch. Besides what `parse` accepts, takes a bare
+/// `2024-05-01` (at the current time of day, like git), `now`,
+/// `yesterday`, and relative dates such as `3 days ago` or `2.weeks.ago`.
+pub fn parse_approx(date: &str) -> Result<i64> {
+    if let Ok((time, _)) = parse(date) {
+        return Ok(time);
+    }
+    let date = date.trim();
+    let bad = || Error::BadDate(date.to_string());
+    let now = Local::now();
+
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        let local = Local
+            .from_local_datetime(&day.and_time(now.time()))
+            .earliest()
+            .ok_or_else(bad)?;
+        return Ok(local.timestamp());
+    }
+
+    let words = date
+        .split(|c: char| c.is_whitespace() || c == '.')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let (count, unit) = match words[..] {
+        ["now"] => return Ok(now.timestamp()),
+        ["yesterday"] => (1, "day"),
+        [count, unit, "ago"] => (count.parse::<u32>().map_err(|_| bad())?, unit),
+        _ => return Err(bad()),
+    };
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        // calendar months and years, which vary in length
+        "month" | "year" => {
+            let months = if unit.starts_with("month") {
+                count
+            } else {
+                count * 12
+            };
+            let then = now
+                .checked_sub_months(Months::new(months))
+                .ok_or_else(bad)?;
+            return Ok(then.timestamp());
+        }
+        _ => return Err(bad()),
+    };
+    Ok(now.timestamp() - i64::from(count) * seconds)
+}
+
+// "+0200" or "-0530"
+fn parse_zone(zone: &str) -> Option<i32> {
+    let (sign, digits) = match zone.as_bytes().first()? {
+        b'+' => (1, &zone[1..]),
+        b'-' => (-1, &zone[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let value: i32 = digits.parse().ok()?;
+    Some(sign * (value / 100 * 60 + value % 100))
+}
+
+/// Formats a timestamp the way `git log` does by default,
+/// e.g. `Thu Apr 7 22:13:13 2005 +0200`.
+pub fn format(time: i64, offset: i32) -> String {
+    let zone = FixedOffset::east_opt(offset * 60).unwrap_or(FixedOffset::east_opt(0).unwrap());
+    match DateTime::from_timestamp(time, 0) {
+        Some(date) => date
+            .with_timezone(&zone)
+            .format("%a %b %-d %H:%M:%S %Y %z")
+            .to_string(),
+        None => format!("{time} {}", zone),
+    }
+}
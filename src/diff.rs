@@ -1,269 +1,766 @@
-use crate::{base, data};
-use std::{io::Write, process::Command};
-use tempfile::NamedTempFile;
+use crate::base::TreeFiles;
+use crate::error::{Error, Result};
+use crate::index::{self, Index};
+use crate::object::{Object, MODE_TREE};
+use crate::xdiff::{self, Algorithm, Edit};
+use crate::{base, data, Repository};
+use regex::bytes::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+
+/// One file that differs between two snapshots. `old` and `new` hold the
+/// `(mode, oid)` of each side and are `None` where the file does not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    pub change: FileChange,
+    pub path: String,
+    /// Where a renamed or copied file came from.
+    pub old_path: Option<String>,
+    /// How alike the two sides of a rename or copy are, in percent.
+    pub similarity: u32,
+    pub old: Option<(u32, String)>,
+    pub new: Option<(u32, String)>,
+}
 
-pub fn compare_trees(oid: &str) -> Vec<(String, String)> {
-    let binding = data::get_object(&oid, None);
-    let parent_oid = binding.lines().nth(1).unwrap().split(' ').nth(1).unwrap();
+impl TreeChange {
+    fn new(
+        change: FileChange,
+        path: &str,
+        old: Option<&(u32, String)>,
+        new: Option<&(u32, String)>,
+    ) -> TreeChange {
+        TreeChange {
+            change,
+            path: path.to_string(),
+            old_path: None,
+            similarity: 0,
+            old: old.cloned(),
+            new: new.cloned(),
+        }
+    }
 
-    let binding = data::get_object(parent_oid, None);
-    let parent_tree = binding.lines().next().unwrap().split(' ').nth(1).unwrap();
+    /// The path on the old side, which differs for renames and copies.
+    pub fn source_path(&self) -> &str {
+        self.old_path.as_deref().unwrap_or(&self.path)
+    }
 
-    let parent_file_content = data::get_object(parent_tree, None);
+    /// The status letter, with the similarity for renames and copies: `M`, `R087`.
+    pub fn status(&self) -> String {
+        match self.change {
+            FileChange::Added => String::from("A"),
+            FileChange::Modified => String::from("M"),
+            FileChange::Deleted => String::from("D"),
+            FileChange::Renamed => format!("R{:03}", self.similarity),
+            FileChange::Copied => format!("C{:03}", self.similarity),
+        }
+    }
+}
 
-    let binding = data::get_object(&oid, None);
-    let child_tree = binding.lines().next().unwrap().split(' ').nth(1).unwrap();
+/// `M path`, or `R087 old -> new` for renames and copies.
+impl fmt::Display for TreeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.old_path {
+            Some(old_path) => write!(f, "{} {old_path} -> {}", self.status(), self.path),
+            None => write!(f, "{} {}", self.status(), self.path),
+        }
+    }
+}
 
-    let now_file_content = data::get_object(child_tree, None);
+/// The short forms of a diff printed instead of a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Summary {
+    /// A histogram of changed lines per file, then the totals.
+    Stat,
+    /// Added and deleted line counts, tab separated.
+    Numstat,
+    /// Only the totals line of `Stat`.
+    Shortstat,
+    NameOnly,
+    NameStatus,
+}
 
-    diff_trees(parent_file_content, now_file_content)
+/// How much a single file changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileStat {
+    pub added: usize,
+    pub deleted: usize,
+    /// The old and new sizes in bytes of a binary file, whose lines are not counted.
+    pub binary: Option<(usize, usize)>,
 }
 
-pub fn diff_trees(parent: String, child: String) -> Vec<(String, String)> {
-    // oid
-    let parent = parent
-        .lines()
-        .map(|x| x.split(' ').map(|x| x.to_string()).collect::<Vec<String>>())
-        .collect::<Vec<Vec<String>>>();
+/// Counts the lines `change` added and deleted.
+pub fn file_stat(repo: &Repository, change: &TreeChange, algorithm: Algorithm) -> Result<FileStat> {
+    let read = |side: &Option<(u32, String)>, path: &str| match side {
+        Some((_, oid)) => blob_contents(repo, oid, path),
+        None => Ok(vec![]),
+    };
+    if change.old.as_ref().map(|o| &o.1) == change.new.as_ref().map(|n| &n.1) {
+        return Ok(FileStat::default());
+    }
+    let old = read(&change.old, change.source_path())?;
+    let new = read(&change.new, &change.path)?;
+    if xdiff::is_binary(&old) || xdiff::is_binary(&new) {
+        return Ok(FileStat {
+            binary: Some((old.len(), new.len())),
+            ..FileStat::default()
+        });
+    }
 
-    let child = child
-        .lines()
-        .map(|x| x.split(' ').map(|x| x.to_string()).collect::<Vec<String>>())
-        .collect::<Vec<Vec<String>>>();
+    let edits = xdiff::diff_lines(
+        &xdiff::split_lines(&old),
+        &xdiff::split_lines(&new),
+        algorithm,
+    );
+    Ok(FileStat {
+        added: edits
+            .iter()
+            .filter(|e| matches!(e, Edit::Insert(_)))
+            .count(),
+        deleted: edits
+            .iter()
+            .filter(|e| matches!(e, Edit::Delete(_)))
+            .count(),
+        binary: None,
+    })
+}
 
-    let mut diffs = vec![];
+/// The path of a change as `--stat` shows it, with the parts a rename
+/// left alone factored out: `src/{old.rs => new.rs}`.
+pub fn display_path(change: &TreeChange) -> String {
+    let Some(old_path) = &change.old_path else {
+        return change.path.clone();
+    };
+    let (a, b) = (old_path.as_bytes(), change.path.as_bytes());
+
+    // the common prefix and suffix only count up to a '/'
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+    // the suffix may share the slash that ends the prefix
+    let lower = prefix.saturating_sub(1);
+    let mut suffix = 0;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i >= lower && j >= lower && a.get(i) == b.get(j) {
+        if a.get(i) == Some(&b'/') {
+            suffix = a.len() - i;
+        }
+        if i == 0 || j == 0 {
+            break;
+        }
+        (i, j) = (i - 1, j - 1);
+    }
 
-    for c in child.clone() {
-        for p in parent.clone() {
-            if p[0] == c[0] && p[2] == c[2] {
-                if p[1] != c[1] {
-                    if p[0] == String::from("blob") {
-                        diffs.push((p[1].clone(), c[1].clone()));
-                        break;
-                    } else if p[0] == String::from("tree") {
-                        let parent_file_content = data::get_object(&p[1], None);
-                        let now_file_content = data::get_object(&c[1], None);
+    if prefix + suffix == 0 {
+        return format!("{old_path} => {}", change.path);
+    }
+    let old_middle = a.len().saturating_sub(prefix + suffix);
+    let new_middle = b.len().saturating_sub(prefix + suffix);
+    format!(
+        "{}{{{} => {}}}{}",
+        &old_path[..prefix],
+        &old_path[prefix..prefix + old_middle],
+        &change.path[prefix..prefix + new_middle],
+        &old_path[a.len() - suffix..],
+    )
+}
 
-                        for i in diff_trees(parent_file_content, now_file_content) {
-                            diffs.push(i);
-                        }
-                        break;
-                    }
-                }
-            }
+/// Rename and copy detection settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Renames {
+    /// The similarity in percent a deleted and an added file need to be
+    /// paired up; `None` turns detection off.
+    pub threshold: Option<u32>,
+    /// Also pair added files with modified files they were copied from.
+    pub copies: bool,
+}
+
+impl Renames {
+    pub const OFF: Renames = Renames {
+        threshold: None,
+        copies: false,
+    };
+}
+
+impl Default for Renames {
+    fn default() -> Renames {
+        Renames {
+            threshold: Some(50),
+            copies: false,
         }
     }
+}
 
-    for p in parent.clone() {
-        if find_remove(p.clone(), child.clone()) {
-            if p[0] == String::from("blob") {
-                println!("remove file {}", p[2]);
-            } else if p[0] == String::from("tree") {
-                println!("remove folder {}", p[2]);
-            }
-        }
+/// Parses the score given to `-M` or `-C`: `90%`, or digits read as a
+/// fraction like git does, so `5` is 50% and `05` is 5%.
+pub fn parse_score(score: &str) -> Result<u32> {
+    let bad = || Error::BadConfig(format!("invalid similarity score '{score}'"));
+    if let Some(percent) = score.strip_suffix('%') {
+        return percent
+            .parse::<u32>()
+            .ok()
+            .filter(|&p| p <= 100)
+            .ok_or_else(bad);
     }
 
-    for c in child.clone() {
-        if find_add(parent.clone(), c.clone()) {
-            if c[0] == String::from("blob") {
-                println!("add new file {}", c[2]);
-            } else if c[0] == String::from("tree") {
-                println!("add new folder {}", c[2]);
-            }
-        }
+    if score.is_empty() || !score.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(bad());
     }
+    let digits = &score[..score.len().min(9)];
+    let value: u64 = digits.parse().map_err(|_| bad())?;
+    Ok((value * 100 / 10u64.pow(digits.len() as u32)) as u32)
+}
 
-    diffs
+/// A snapshot of files to diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A tree oid, or `None` for the empty tree before a root commit.
+    Tree(Option<String>),
+    Index,
+    WorkTree,
 }
 
-fn iter_changed_files(parent: String, child: String) {
-    let parent = parent
-        .lines()
-        .map(|x| x.split(' ').map(|x| x.to_string()).collect::<Vec<String>>())
-        .collect::<Vec<Vec<String>>>();
+/// Reads the files of `source` as a path -> (mode, oid) map. Work tree
+/// files are hashed without being stored.
+pub fn read_source(repo: &Repository, source: &Source) -> Result<TreeFiles> {
+    match source {
+        Source::Tree(Some(tree)) => base::flatten_tree(repo, tree),
+        Source::Tree(None) => Ok(TreeFiles::new()),
+        Source::Index => Ok(Index::read(repo)?.files()),
+        Source::WorkTree => scan_work_tree(repo, &mut Index::read(repo)?),
+    }
+}
 
-    let child = child
-        .lines()
-        .map(|x| x.split(' ').map(|x| x.to_string()).collect::<Vec<String>>())
-        .collect::<Vec<Vec<String>>>();
+/// The tree of a commit or tree given as a revision.
+pub fn rev_tree(repo: &Repository, rev: &str) -> Result<String> {
+    let oid = base::get_oid(repo, rev)?;
+    match data::read_object(repo, &oid)? {
+        Object::Commit(commit) => Ok(commit.tree),
+        Object::Tree(_) => Ok(oid),
+        object => Err(Error::WrongObjectType {
+            oid,
+            expected: String::from("tree"),
+            actual: object.kind().to_string(),
+        }),
+    }
+}
 
-    for c in child.clone() {
-        for p in parent.clone() {
-            if p[0] == c[0] && p[2] == c[2] {
-                if p[1] != c[1] {
-                    if p[0] == String::from("blob") {
-                        println!("modified file {}", p[2]);
-                        break;
-                    } else if p[0] == String::from("tree") {
-                        let parent_file_content = data::get_object(&p[1], None);
-                        let now_file_content = data::get_object(&c[1], None);
+/// Diffs two snapshots, keeping only changes under one of `paths` unless
+/// it is empty.
+pub fn diff_sources(
+    repo: &Repository,
+    old: &Source,
+    new: &Source,
+    paths: &[String],
+    renames: &Renames,
+) -> Result<Vec<TreeChange>> {
+    let mut changes = diff_files(&read_source(repo, old)?, &read_source(repo, new)?);
+    if !paths.is_empty() {
+        changes.retain(|c| paths.iter().any(|p| index::is_under(&c.path, p)));
+    }
+
+    detect_renames(repo, changes, renames)
+}
+
+/// Pairs deleted files with added ones holding the same or similar contents
+/// into renames, and with `copies` added files with modified ones into
+/// copies. Identical contents are paired first, then the most similar
+/// pairs above the threshold.
+pub fn detect_renames(
+    repo: &Repository,
+    changes: Vec<TreeChange>,
+    renames: &Renames,
+) -> Result<Vec<TreeChange>> {
+    let Some(threshold) = renames.threshold else {
+        return Ok(changes);
+    };
+
+    let indices = |kind: FileChange| {
+        (0..changes.len())
+            .filter(|&i| changes[i].change == kind)
+            .collect::<Vec<_>>()
+    };
+    let added = indices(FileChange::Added);
+    let mut sources = indices(FileChange::Deleted);
+    if renames.copies {
+        sources.extend(indices(FileChange::Modified));
+    }
+    if added.is_empty() || sources.is_empty() {
+        return Ok(changes);
+    }
 
-                        println!("modified folder {}", p[2]);
-                        diff_trees(parent_file_content, now_file_content);
-                        break;
+    // (exact, similarity, source, destination) for every pair worth considering
+    let mut contents: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut pairs = vec![];
+    for &d in &added {
+        let (_, dst_oid) = changes[d].new.as_ref().unwrap();
+        for &s in &sources {
+            let (_, src_oid) = changes[s].old.as_ref().unwrap();
+            let score = if src_oid == dst_oid {
+                100
+            } else {
+                for (oid, path) in [(src_oid, &changes[s].path), (dst_oid, &changes[d].path)] {
+                    if !contents.contains_key(oid) {
+                        contents.insert(oid.clone(), blob_contents(repo, oid, path)?);
                     }
                 }
+                similarity(&contents[src_oid], &contents[dst_oid])
+            };
+            if score >= threshold {
+                pairs.push((src_oid == dst_oid, score, s, d));
             }
         }
     }
-
-    for p in parent.clone() {
-        if find_remove(p.clone(), child.clone()) {
-            if p[0] == String::from("blob") {
-                println!("remove file {}", p[2]);
-            } else if p[0] == String::from("tree") {
-                println!("remove folder {}", p[2]);
-            }
+    // exact matches first, then the most similar, then in path order
+    pairs.sort_by(|a, b| {
+        (b.0, b.1)
+            .cmp(&(a.0, a.1))
+            .then((a.3, a.2).cmp(&(b.3, b.2)))
+    });
+
+    let mut paired: HashMap<usize, (FileChange, usize, u32)> = HashMap::new();
+    let mut renamed = HashSet::new();
+    for (_, score, s, d) in pairs {
+        if paired.contains_key(&d) || (!renames.copies && renamed.contains(&s)) {
+            continue;
+        }
+        if changes[s].change == FileChange::Deleted {
+            renamed.insert(s);
         }
+        paired.insert(d, (FileChange::Copied, s, score));
     }
-
-    for c in child.clone() {
-        if find_add(parent.clone(), c.clone()) {
-            if c[0] == String::from("blob") {
-                println!("add new file {}", c[2]);
-            } else if c[0] == String::from("tree") {
-                println!("add new folder {}", c[2]);
-            }
+    // like git, the last destination of a deleted file is its rename and
+    // any earlier ones are copies
+    for &s in &renamed {
+        let last = paired
+            .iter()
+            .filter(|(_, p)| p.1 == s)
+            .map(|(&d, _)| d)
+            .max();
+        if let Some(d) = last {
+            paired.get_mut(&d).unwrap().0 = FileChange::Renamed;
         }
     }
-}
 
-fn find_remove(parent: Vec<String>, child: Vec<Vec<String>>) -> bool {
-    for c in child {
-        if parent[0] == c[0] {
-            if parent[2] == c[2] {
-                return false;
-            }
+    let mut result = vec![];
+    for (i, change) in changes.iter().enumerate() {
+        if renamed.contains(&i) {
+            continue;
+        }
+        match paired.get(&i) {
+            Some(&(kind, s, score)) => result.push(TreeChange {
+                change: kind,
+                path: change.path.clone(),
+                old_path: Some(changes[s].path.clone()),
+                similarity: score,
+                old: changes[s].old.clone(),
+                new: change.new.clone(),
+            }),
+            None => result.push(change.clone()),
         }
     }
 
-    true
+    Ok(result)
 }
 
-fn find_add(parent: Vec<Vec<String>>, child: Vec<String>) -> bool {
-    for p in parent {
-        if p[0] == child[0] {
-            if p[2] == child[2] {
-                return false;
-            }
-        }
+/// Where the file at `path`, added between `old` and `new`, was renamed or
+/// copied from. Like `log --follow` in git, every file of `old` counts as
+/// a possible source, whether or not it changed.
+pub fn find_source(
+    repo: &Repository,
+    old: &TreeFiles,
+    new: &TreeFiles,
+    path: &str,
+    renames: &Renames,
+) -> Result<Option<String>> {
+    let added = match (old.get(path), new.get(path)) {
+        (None, Some(entry)) => entry,
+        _ => return Ok(None),
+    };
+
+    let mut changes = vec![TreeChange::new(FileChange::Added, path, None, Some(added))];
+    for (source, entry) in old {
+        let kind = match new.contains_key(source) {
+            true => FileChange::Modified,
+            false => FileChange::Deleted,
+        };
+        changes.push(TreeChange::new(kind, source, Some(entry), new.get(source)));
+    }
+    // sorted by path, as rename detection breaks ties in path order
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+    // a file that was renamed is preferred to one that was copied
+    let source = |changes: Vec<TreeChange>, copies| -> Result<Option<String>> {
+        let renames = Renames { copies, ..*renames };
+        Ok(detect_renames(repo, changes, &renames)?
+            .into_iter()
+            .find(|change| change.path == path)
+            .and_then(|change| change.old_path))
+    };
+    let deleted = changes
+        .iter()
+        .filter(|change| change.change != FileChange::Modified)
+        .cloned()
+        .collect();
+    match source(deleted, false)? {
+        Some(source) => Ok(Some(source)),
+        None => source(changes, true),
     }
-
-    true
 }
 
-pub fn get_working_tree_diff(oid: &str) -> Vec<(String, String)> {
-    let now_tree = base::write_tree();
-    let now_uncommit_file_content = data::get_object(&now_tree, Some("tree"));
-
-    let binding = data::get_object(&oid, None);
-    let child_tree = binding.lines().next().unwrap().split(' ').nth(1).unwrap();
+/// How much of the larger file is made of lines also found in the other
+/// one, in percent, a cheaper stand-in for a full diff.
+fn similarity(old: &[u8], new: &[u8]) -> u32 {
+    let larger = old.len().max(new.len());
+    if larger == 0 {
+        return 100;
+    }
 
-    let now_commit_file_content = data::get_object(child_tree, None);
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for line in old.split_inclusive(|&b| b == b'\n') {
+        *counts.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in new.split_inclusive(|&b| b == b'\n') {
+        if let Some(count) = counts.get_mut(line).filter(|c| **c > 0) {
+            *count -= 1;
+            common += line.len();
+        }
+    }
 
-    diff_trees(now_commit_file_content, now_uncommit_file_content)
+    (common * 100 / larger) as u32
 }
 
-pub fn get_working_tree_diff_simple_version(oid: &str) {
-    let now_tree = base::write_tree();
-    let now_uncommit_file_content = data::get_object(&now_tree, Some("tree"));
-
-    let binding = data::get_object(&oid, None);
-    let child_tree = binding.lines().next().unwrap().split(' ').nth(1).unwrap();
+/// The contents of a blob on either side of a diff. Work tree files are
+/// diffed without being stored, so a blob missing from the object store is
+/// read from the file it was hashed from.
+pub fn blob_contents(repo: &Repository, oid: &str, path: &str) -> Result<Vec<u8>> {
+    if data::has_object(repo, oid) {
+        return data::get_object(repo, oid, Some("blob"));
+    }
 
-    let now_commit_file_content = data::get_object(child_tree, None);
+    let contents = base::read_work_tree_file(&repo.work_tree()?.join(path))?;
+    if data::compute_oid(&contents, "blob") != oid {
+        return Err(Error::ObjectNotFound(oid.to_string()));
+    }
+    Ok(contents)
+}
 
-    iter_changed_files(now_commit_file_content, now_uncommit_file_content);
+/// The changes a commit made to its first parent, or to nothing for a root
+/// commit, keeping only those under one of `paths` unless it is empty.
+pub fn compare_trees(
+    repo: &Repository,
+    oid: &str,
+    paths: &[String],
+    renames: &Renames,
+) -> Result<Vec<TreeChange>> {
+    let commit = data::get_commit(repo, oid)?;
+    let parent_tree = match commit.parents.first() {
+        Some(parent_oid) => Some(data::get_commit(repo, parent_oid)?.tree),
+        None => None,
+    };
+
+    diff_sources(
+        repo,
+        &Source::Tree(parent_tree),
+        &Source::Tree(Some(commit.tree)),
+        paths,
+        renames,
+    )
 }
 
-pub fn merge(tree1_oid: &str, tree2_oid: &str) -> String {
-    let binding = data::get_object(tree1_oid, None);
-    let child_tree = binding.lines().next().unwrap().split(' ').nth(1).unwrap();
+/// Whether anything under one of `paths` differs between two trees, where
+/// `None` is the empty tree. Only the subtrees leading to `paths` are read,
+/// and only where their oids differ.
+pub fn tree_changed(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+    paths: &[String],
+) -> Result<bool> {
+    tree_changed_under(repo, old, new, "", paths)
+}
 
-    let tree1_content = data::get_object(child_tree, None);
+fn tree_changed_under(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    paths: &[String],
+) -> Result<bool> {
+    if old == new {
+        return Ok(false);
+    }
+    // the mode is compared too, so a chmod or a file becoming a symlink
+    // with the same contents counts as a change
+    let entries = |tree: Option<&str>| -> Result<BTreeMap<String, (u32, String)>> {
+        let Some(tree) = tree else {
+            return Ok(BTreeMap::new());
+        };
+        Ok(data::get_tree(repo, tree)?
+            .entries
+            .into_iter()
+            .map(|e| (e.name.clone(), (e.mode, e.oid)))
+            .collect())
+    };
+    let (old, new) = (entries(old)?, entries(new)?);
+    fn subtree(entry: Option<&(u32, String)>) -> Option<&str> {
+        entry
+            .filter(|(mode, _)| *mode == MODE_TREE)
+            .map(|(_, oid)| oid.as_str())
+    }
 
-    let binding = data::get_object(tree2_oid, None);
-    let child_tree = binding.lines().next().unwrap().split(' ').nth(1).unwrap();
+    for name in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+        let (old_entry, new_entry) = (old.get(name), new.get(name));
+        if old_entry == new_entry {
+            continue;
+        }
+        let path = format!("{prefix}{name}");
+        if paths.iter().any(|p| index::is_under(&path, p)) {
+            return Ok(true);
+        }
+        // a directory some of the paths are inside of
+        let (old_tree, new_tree) = (subtree(old_entry), subtree(new_entry));
+        if paths.iter().any(|p| index::is_under(p, &path))
+            && (old_tree.is_some() || new_tree.is_some())
+            && tree_changed_under(repo, old_tree, new_tree, &format!("{path}/"), paths)?
+        {
+            return Ok(true);
+        }
+    }
 
-    let tree2_content = data::get_object(child_tree, None);
-    merge_diff_trees(tree1_content, tree2_content)
+    Ok(false)
 }
 
-fn merge_diff_trees(parent: String, child: String) -> String {
-    let parent = parent
-        .lines()
-        .map(|x| x.split(' ').map(|x| x.to_string()).collect::<Vec<String>>())
-        .collect::<Vec<Vec<String>>>();
+/// A search through the changes of a commit for `log -S` and `log -G`.
+#[derive(Debug, Clone)]
+pub enum Pickaxe {
+    /// Changes that make a string occur a different number of times in a
+    /// file, as when adding or removing a use of it.
+    Count(String),
+    /// Changes that add or remove a line matching a regex. Binary files
+    /// are not searched.
+    Lines(Regex),
+}
 
-    let child = child
-        .lines()
-        .map(|x| x.split(' ').map(|x| x.to_string()).collect::<Vec<String>>())
-        .collect::<Vec<Vec<String>>>();
+impl Pickaxe {
+    /// Whether the search finds any of `changes`.
+    pub fn matches(&self, repo: &Repository, changes: &[TreeChange]) -> Result<bool> {
+        let read = |side: &Option<(u32, String)>, path: &str| match side {
+            Some((_, oid)) => blob_contents(repo, oid, path),
+            None => Ok(vec![]),
+        };
+
+        for change in changes {
+            let old = read(&change.old, change.source_path())?;
+            let new = read(&change.new, &change.path)?;
+            let found = match self {
+                Pickaxe::Count(needle) => count(&old, needle) != count(&new, needle),
+                Pickaxe::Lines(_) if xdiff::is_binary(&old) || xdiff::is_binary(&new) => false,
+                Pickaxe::Lines(regex) => {
+                    let (old, new) = (xdiff::split_lines(&old), xdiff::split_lines(&new));
+                    let is_match =
+                        |line: &[u8]| regex.is_match(line.strip_suffix(b"\n").unwrap_or(line));
+                    xdiff::diff_lines(&old, &new, Algorithm::default())
+                        .iter()
+                        .any(|edit| match edit {
+                            Edit::Delete(i) => is_match(old[*i]),
+                            Edit::Insert(i) => is_match(new[*i]),
+                            Edit::Equal(..) => false,
+                        })
+                }
+            };
+            if found {
+                return Ok(true);
+            }
+        }
 
-    let mut entries = vec![];
-    let mut rgit_type = String::from("");
-    let mut oid = String::from("");
-    let mut filename = String::from("");
-    let mut tree = String::from("");
+        Ok(false)
+    }
+}
 
-    for c in child.clone() {
-        for p in parent.clone() {
-            if p[0] == c[0] && p[2] == c[2] {
-                if p[1] != c[1] {
-                    if p[0] == String::from("blob") {
-                        rgit_type = p[0].clone();
+// how many times `needle` occurs in `haystack` without overlapping
+fn count(haystack: &[u8], needle: &str) -> usize {
+    let needle = needle.as_bytes();
+    if needle.is_empty() {
+        return 0;
+    }
 
-                        let new_file_content = merge_blobs(&p[1], &c[1]);
-                        oid = data::hash_object(&new_file_content, "blob");
-                        filename = p[2].clone();
-                    } else if p[0] == String::from("tree") {
-                        rgit_type = p[0].clone();
-                        let parent_file_content = data::get_object(&p[1], None);
-                        let now_file_content = data::get_object(&c[1], None);
+    let (mut count, mut i) = (0, 0);
+    while i + needle.len() <= haystack.len() {
+        if haystack[i..].starts_with(needle) {
+            count += 1;
+            i += needle.len();
+        } else {
+            i += 1;
+        }
+    }
+    count
+}
 
-                        oid = merge_diff_trees(parent_file_content, now_file_content);
-                        filename = p[2].clone();
-                    }
+/// The work tree version of every tracked file, leaving out deleted ones and
+/// unmerged paths. Files whose stat data still matches the index reuse the
+/// staged oid; the rest are re-hashed without storing anything. Entries
+/// found unchanged get fresh stat data so the next scan can skip them.
+pub fn scan_work_tree(repo: &Repository, index: &mut Index) -> Result<TreeFiles> {
+    let work_tree = repo.work_tree()?;
+    let mut files = TreeFiles::new();
+    let mut refreshed = vec![];
+
+    for entry in index.entries().filter(|e| e.stage == 0) {
+        let file = work_tree.join(&entry.path);
+        let meta = match fs::symlink_metadata(&file) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let (mode, oid) = if index.is_up_to_date(entry, &meta) {
+            (entry.mode, entry.oid.clone())
+        } else {
+            let mode = index::file_mode(&meta);
+            let oid = data::compute_oid(&base::read_work_tree_file(&file)?, "blob");
+            if oid == entry.oid && mode == entry.mode {
+                refreshed.push((entry.path.clone(), meta));
+            }
+            (mode, oid)
+        };
+        files.insert(entry.path.clone(), (mode, oid));
+    }
 
-                    let e = format!("{} {} {}", rgit_type, oid, filename);
-                    entries.push(e);
-                    break;
-                } else {
-                    let e = format!("{} {} {}", p[0], p[1], p[2]);
-                    entries.push(e);
-                    break;
-                }
+    if !refreshed.is_empty() {
+        for (path, meta) in refreshed {
+            if let Some(entry) = index.get_mut(&path) {
+                entry.update_stat(&meta);
             }
         }
+        index.write(repo)?;
     }
 
-    for e in &entries {
-        tree.push_str(e);
-        tree.push('\n');
-    }
+    Ok(files)
+}
 
-    data::hash_object(&tree, "tree")
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    Copied,
 }
 
-fn merge_blobs(blob1_oid: &str, blob2_oid: &str) -> String {
-    let arg1 = format!("{}", data::get_object(blob1_oid, Some("blob")));
-    let arg2 = format!("{}", data::get_object(blob2_oid, Some("blob")));
+/// How the two sides of a conflicted merge left a path, from the stages the
+/// index records for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmerged {
+    BothModified,
+    BothAdded,
+    BothDeleted,
+    AddedByUs,
+    AddedByThem,
+    DeletedByUs,
+    DeletedByThem,
+}
 
-    let mut file1 = NamedTempFile::new().unwrap();
-    file1.write_all(arg1.as_bytes()).unwrap();
+impl Unmerged {
+    fn from_stages(stages: &[u8]) -> Unmerged {
+        match stages {
+            [1, 2, 3] => Unmerged::BothModified,
+            [2, 3] => Unmerged::BothAdded,
+            [1, 3] => Unmerged::DeletedByUs,
+            [1, 2] => Unmerged::DeletedByThem,
+            [2] => Unmerged::AddedByUs,
+            [3] => Unmerged::AddedByThem,
+            _ => Unmerged::BothDeleted,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Status {
+    /// HEAD compared with the index.
+    pub staged: Vec<(FileChange, String)>,
+    /// Paths with unresolved merge conflicts.
+    pub unmerged: Vec<(Unmerged, String)>,
+    /// The index compared with the work tree.
+    pub unstaged: Vec<(FileChange, String)>,
+    pub untracked: Vec<String>,
+}
+
+pub fn status(repo: &Repository) -> Result<Status> {
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::read(repo)?;
+    let unmerged = index.unmerged();
+    let mut head_files = base::head_files(repo)?;
+    head_files.retain(|path, _| !unmerged.contains_key(path));
+    let staged = diff_files(&head_files, &index.files());
+    let staged = compare_files(detect_renames(repo, staged, &Renames::default())?);
+    let unstaged = compare_files(diff_files(
+        &index.files(),
+        &scan_work_tree(repo, &mut index)?,
+    ));
+
+    let untracked = base::visit_dirs(repo, work_tree)?
+        .into_iter()
+        .filter(|path| !index.contains(path))
+        .collect();
+
+    Ok(Status {
+        staged,
+        unmerged: unmerged
+            .into_iter()
+            .map(|(path, stages)| (Unmerged::from_stages(&stages), path))
+            .collect(),
+        unstaged,
+        untracked,
+    })
+}
 
-    let mut file2 = NamedTempFile::new().unwrap();
-    file2.write_all(arg2.as_bytes()).unwrap();
+/// Compares two flattened snapshots path by path, sorted by path.
+pub fn diff_files(old: &TreeFiles, new: &TreeFiles) -> Vec<TreeChange> {
+    let mut changes = vec![];
+
+    for (path, entry) in old {
+        match new.get(path) {
+            None => changes.push(TreeChange::new(
+                FileChange::Deleted,
+                path,
+                Some(entry),
+                None,
+            )),
+            Some(new_entry) if new_entry != entry => changes.push(TreeChange::new(
+                FileChange::Modified,
+                path,
+                Some(entry),
+                Some(new_entry),
+            )),
+            Some(_) => {}
+        }
+    }
+    for (path, entry) in new {
+        if !old.contains_key(path) {
+            changes.push(TreeChange::new(FileChange::Added, path, None, Some(entry)));
+        }
+    }
 
-    let output = Command::new("diff")
-        .args([
-            "-DHEAD",
-            file1.path().to_str().unwrap(),
-            file2.path().to_str().unwrap(),
-        ])
-        .output()
-        .expect("failed to spawn child process");
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
 
-    String::from_utf8(output.stdout).unwrap()
+// status lists renames as "old -> new"
+fn compare_files(changes: Vec<TreeChange>) -> Vec<(FileChange, String)> {
+    changes
+        .into_iter()
+        .map(|c| match c.old_path {
+            Some(old_path) => (c.change, format!("{old_path} -> {}", c.path)),
+            None => (c.change, c.path),
+        })
+        .collect()
 }
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NotARepository(PathBuf),
+    AlreadyInitialized(PathBuf),
+    ObjectNotFound(String),
+    WrongObjectType {
+        oid: String,
+        expected: String,
+        actual: String,
+    },
+    CorruptObject(String),
+    BadRevision(String),
+    AmbiguousRevision(String),
+    NoUpstream(String),
+    BadDate(String),
+    BadIdent(String),
+    BadConfig(String),
+    InvalidArgument(String),
+    DirtyWorktree,
+    MergeConflict,
+    MergeInProgress,
+    NoMergeInProgress,
+    UnmergedFiles,
+    NotFastForward,
+    BareRepository,
+    CorruptIndex(String),
+    PathNotFound(String),
+    PathIgnored(String),
+    PathOutsideRepository(PathBuf),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::NotARepository(path) => write!(
+                f,
+                "not an rgit repository (or any of the parent directories): {}",
+                path.display()
+            ),
+            Error::AlreadyInitialized(path) => {
+                write!(f, "rgit repository already exists in {}", path.display())
+            }
+            Error::ObjectNotFound(oid) => write!(f, "object {oid} not found"),
+            Error::WrongObjectType {
+                oid,
+                expected,
+                actual,
+            } => write!(f, "object {oid} is a {actual}, not a {expected}"),
+            Error::CorruptObject(reason) => write!(f, "corrupt object: {reason}"),
+            Error::BadRevision(name) => write!(f, "bad revision '{name}'"),
+            Error::AmbiguousRevision(prefix) => {
+                write!(f, "short object ID {prefix} is ambiguous")
+            }
+            Error::NoUpstream(branch) => {
+                write!(f, "no upstream configured for branch '{branch}'")
+            }
+            Error::BadDate(date) => write!(f, "invalid date format: {date}"),
+            Error::BadIdent(ident) => {
+                write!(f, "malformed identity '{ident}', expected 'Name <email>'")
+            }
+            Error::BadConfig(reason) | Error::InvalidArgument(reason) => write!(f, "{reason}"),
+            Error::DirtyWorktree => write!(
+                f,
+                "your local changes would be overwritten; commit them first"
+            ),
+            Error::MergeConflict => write!(
+                f,
+                "Automatic merge failed; fix conflicts and then commit the result."
+            ),
+            Error::MergeInProgress => write!(
+                f,
+                "you have not concluded your merge (MERGE_HEAD exists); \
+                 run 'rgit merge --continue' or 'rgit merge --abort'"
+            ),
+            Error::NoMergeInProgress => {
+                write!(f, "there is no merge in progress (MERGE_HEAD missing)")
+            }
+            Error::UnmergedFiles => write!(
+                f,
+                "committing is not possible because you have unmerged files; \
+                 fix them and stage the result with 'rgit add'"
+            ),
+            Error::NotFastForward => write!(f, "Not possible to fast-forward, aborting."),
+            Error::BareRepository => {
+                write!(f, "this operation must be run in a work tree")
+            }
+            Error::CorruptIndex(reason) => write!(f, "index file corrupt: {reason}"),
+            Error::PathNotFound(path) => {
+                write!(f, "pathspec '{path}' did not match any files")
+            }
+            Error::PathIgnored(path) => write!(
+                f,
+                "path '{path}' is ignored by one of your .rgitignore files; use -f to add it"
+            ),
+            Error::PathOutsideRepository(path) => {
+                write!(f, "{} is outside repository", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
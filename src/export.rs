@@ -0,0 +1,615 @@
+//! Exporting the history graph for `k`: every commit reachable from the
+//! refs and HEAD, written as Graphviz DOT, a Mermaid flowchart, JSON or a
+//! self-contained SVG drawing.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::object::Object;
+use crate::revision::{self, Tip};
+use crate::walk::{self, Order, WalkOptions};
+use crate::{data, pretty, Repository};
+
+// how many hex digits commit labels name commits by at least
+const ABBREV: usize = 7;
+
+/// What `k` writes the history as.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// A Graphviz digraph, for `dot` and the tools that read it.
+    #[default]
+    Dot,
+    /// A Mermaid flowchart, for Markdown renderers that support it.
+    Mermaid,
+    /// The commits, refs and HEAD as data.
+    Json,
+    /// The graph drawn as `log --graph` lays it out.
+    Svg,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        Ok(match s {
+            "dot" => Format::Dot,
+            "mermaid" => Format::Mermaid,
+            "json" => Format::Json,
+            "svg" => Format::Svg,
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown graph format '{s}': expected dot, mermaid, json or svg"
+                )))
+            }
+        })
+    }
+}
+
+/// A commit of the history, with all its parents.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub oid: String,
+    /// The abbreviated oid.
+    pub name: String,
+    pub subject: String,
+    pub parents: Vec<String>,
+}
+
+/// A ref pointing at a commit of the history.
+#[derive(Debug, Clone)]
+pub struct Ref {
+    /// The full name, such as `refs/heads/master`.
+    pub name: String,
+    /// The commit it points at, through any annotated tags.
+    pub commit: String,
+}
+
+impl Ref {
+    /// `branch`, `tag`, `remote` or, for anything else under `refs/`, `ref`.
+    pub fn kind(&self) -> &'static str {
+        match &self.name {
+            name if name.starts_with("refs/heads/") => "branch",
+            name if name.starts_with("refs/tags/") => "tag",
+            name if name.starts_with("refs/remotes/") => "remote",
+            _ => "ref",
+        }
+    }
+
+    /// The name without its `refs/heads/`, `refs/tags/` or `refs/remotes/`.
+    pub fn short_name(&self) -> &str {
+        ["refs/heads/", "refs/tags/", "refs/remotes/"]
+            .iter()
+            .find_map(|prefix| self.name.strip_prefix(prefix))
+            .unwrap_or(&self.name)
+    }
+}
+
+/// Every commit reachable from the refs and HEAD, children before their
+/// parents, and the refs that point at them.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub commits: Vec<Node>,
+    pub refs: Vec<Ref>,
+    /// The branch HEAD is on, or None if it is detached or there is none.
+    pub head_branch: Option<String>,
+    /// The commit HEAD points at, or None on an unborn branch.
+    pub head: Option<String>,
+}
+
+impl History {
+    pub fn load(repo: &Repository) -> Result<History> {
+        let mut refs = vec![];
+        for (name, oid) in data::list_refs(repo)? {
+            // tags may point at trees and blobs, which have no history
+            let oid = revision::peel(repo, &oid, "")?;
+            if matches!(data::read_object(repo, &oid)?, Object::Commit(_)) {
+                refs.push(Ref { name, commit: oid });
+            }
+        }
+
+        let head_branch = match data::get_ref(repo, "HEAD", false)?.symbolic {
+            true => Some(data::resolve_ref(repo, "HEAD")?),
+            false => None,
+        };
+        let head = repo.head()?.map(|oid| oid.trim().to_string());
+
+        let tips = refs
+            .iter()
+            .map(|r| r.commit.clone())
+            .chain(head.clone())
+            .map(|oid| Tip {
+                oid,
+                excluded: false,
+            })
+            .collect::<Vec<_>>();
+        let options = WalkOptions {
+            order: Order::Topo,
+            ..WalkOptions::default()
+        };
+
+        let mut commits = vec![];
+        for oid in walk::commits(repo, &tips, &options)?.commits {
+            let commit = data::get_commit(repo, &oid)?;
+            commits.push(Node {
+                name: revision::abbreviate(repo, &oid, ABBREV)?,
+                subject: pretty::subject(&commit),
+                parents: commit.parents,
+                oid,
+            });
+        }
+
+        Ok(History {
+            commits,
+            refs,
+            head_branch,
+            head,
+        })
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Dot => self.dot(),
+            Format::Mermaid => self.mermaid(),
+            Format::Json => self.json(),
+            Format::Svg => self.svg(),
+        }
+    }
+
+    // HEAD's own node points at the branch it is on, or at its commit
+    fn head_target(&self) -> Option<(bool, &str)> {
+        match (&self.head_branch, &self.head) {
+            (Some(branch), Some(_)) if self.refs.iter().any(|r| &r.name == branch) => {
+                Some((true, branch))
+            }
+            (_, Some(oid)) => Some((false, oid)),
+            (_, None) => None,
+        }
+    }
+
+    fn dot(&self) -> String {
+        let mut out = String::from("digraph history {\n");
+        out.push_str("  node [shape=box, fontname=monospace];\n");
+        for node in &self.commits {
+            let label = format!("{} {}", node.name, node.subject);
+            out.push_str(&format!(
+                "  \"{}\" [label={}];\n",
+                node.oid,
+                dot_quote(&label)
+            ));
+        }
+        for node in &self.commits {
+            for (i, parent) in node.parents.iter().enumerate() {
+                // merged parents are dashed, the first parent is solid
+                let style = if i > 0 { " [style=dashed]" } else { "" };
+                out.push_str(&format!("  \"{}\" -> \"{parent}\"{style};\n", node.oid));
+            }
+        }
+        for r in &self.refs {
+            let (shape, label) = match r.kind() {
+                "tag" => ("cds", format!("tag: {}", r.short_name())),
+                _ => ("note", r.short_name().to_string()),
+            };
+            out.push_str(&format!(
+                "  {} [label={}, shape={shape}];\n",
+                dot_quote(&r.name),
+                dot_quote(&label)
+            ));
+            out.push_str(&format!("  {} -> \"{}\";\n", dot_quote(&r.name), r.commit));
+        }
+        if let Some((_, target)) = self.head_target() {
+            out.push_str("  \"HEAD\" [shape=ellipse];\n");
+            out.push_str(&format!("  \"HEAD\" -> {};\n", dot_quote(target)));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn mermaid(&self) -> String {
+        // Mermaid ids can't hold slashes, so refs are numbered
+        let mut out = String::from("flowchart TD\n");
+        for node in &self.commits {
+            let label = format!("{} {}", node.name, node.subject);
+            out.push_str(&format!("  c{}[{}]\n", node.oid, mermaid_quote(&label)));
+        }
+        for node in &self.commits {
+            for (i, parent) in node.parents.iter().enumerate() {
+                let arrow = if i > 0 { "-.->" } else { "-->" };
+                out.push_str(&format!("  c{} {arrow} c{parent}\n", node.oid));
+            }
+        }
+        let mut ids = HashMap::new();
+        for (i, r) in self.refs.iter().enumerate() {
+            let label = match r.kind() {
+                "tag" => format!("tag: {}", r.short_name()),
+                _ => r.short_name().to_string(),
+            };
+            out.push_str(&format!("  r{i}[/{}/]\n", mermaid_quote(&label)));
+            out.push_str(&format!("  r{i} --> c{}\n", r.commit));
+            ids.insert(r.name.as_str(), format!("r{i}"));
+        }
+        if let Some((symbolic, target)) = self.head_target() {
+            let target = match symbolic {
+                true => ids[target].clone(),
+                false => format!("c{target}"),
+            };
+            out.push_str("  HEAD([HEAD])\n");
+            out.push_str(&format!("  HEAD --> {target}\n"));
+        }
+        out
+    }
+
+    fn json(&self) -> String {
+        let string = |s: Option<&str>| s.map(json_quote).unwrap_or_else(|| "null".into());
+
+        let mut out = String::from("{\n");
+        out.push_str(&format!(
+            "  \"head\": {{\"branch\": {}, \"commit\": {}}},\n",
+            string(self.head_branch.as_deref()),
+            string(self.head.as_deref())
+        ));
+
+        let refs = self
+            .refs
+            .iter()
+            .map(|r| {
+                format!(
+                    "    {{\"name\": {}, \"kind\": \"{}\", \"commit\": \"{}\"}}",
+                    json_quote(&r.name),
+                    r.kind(),
+                    r.commit
+                )
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&json_list("refs", &refs));
+        out.push_str(",\n");
+
+        let commits = self
+            .commits
+            .iter()
+            .map(|node| {
+                let parents = node
+                    .parents
+                    .iter()
+                    .map(|parent| format!("\"{parent}\""))
+                    .collect::<Vec<_>>();
+                format!(
+                    "    {{\"oid\": \"{}\", \"subject\": {}, \"parents\": [{}]}}",
+                    node.oid,
+                    json_quote(&node.subject),
+                    parents.join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&json_list("commits", &commits));
+        out.push_str("\n}\n");
+        out
+    }
+
+    fn svg(&self) -> String {
+        const ROW: usize = 24;
+        const COLUMN: usize = 16;
+        const MARGIN: usize = 12;
+        const RADIUS: usize = 4;
+        const COLORS: [&str; 6] = [
+            "#d73a49", "#2188ff", "#28a745", "#6f42c1", "#e36209", "#0596a8",
+        ];
+
+        let layout = Layout::new(&self.commits);
+        let x = |column: usize| MARGIN + column * COLUMN + COLUMN / 2;
+        let y = |row: usize| MARGIN + row * ROW + ROW / 2;
+        let text_x = x(layout.width) + COLUMN / 2;
+
+        // labelled as `log --decorate` does, HEAD first
+        let mut labels = HashMap::<&str, Vec<String>>::new();
+        for r in &self.refs {
+            let entry = labels.entry(&r.commit).or_default();
+            match r.kind() {
+                _ if self.head_branch.as_ref() == Some(&r.name) => {
+                    entry.insert(0, format!("HEAD -> {}", r.short_name()))
+                }
+                "tag" => entry.push(format!("tag: {}", r.short_name())),
+                _ => entry.push(r.short_name().to_string()),
+            }
+        }
+        if let Some((false, oid)) = self.head_target() {
+            labels
+                .entry(oid)
+                .or_default()
+                .insert(0, String::from("HEAD"));
+        }
+
+        let longest = self
+            .commits
+            .iter()
+            .map(|node| {
+                let refs = labels
+                    .get(node.oid.as_str())
+                    .map(|l| l.join(", ").len() + 3);
+                node.name.len() + node.subject.len() + refs.unwrap_or(0) + 1
+            })
+            .max()
+            .unwrap_or(0);
+        let width = text_x + longest * 8 + MARGIN;
+        let height = 2 * MARGIN + self.commits.len() * ROW;
+
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             font-family=\"monospace\" font-size=\"13\">\n"
+        );
+        out.push_str(&format!(
+            "<rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>\n"
+        ));
+
+        // an edge leaves its child into the lane it runs down, and turns
+        // into its parent's column on the row above the parent
+        for edge in &layout.edges {
+            let (child, parent) = (&layout.rows[edge.child], &layout.rows[edge.parent]);
+            let mut points = vec![(x(child.column), y(edge.child))];
+            if edge.lane != child.column {
+                points.push((x(edge.lane), y(edge.child) + ROW / 2));
+            }
+            if edge.lane != parent.column {
+                points.push((x(edge.lane), y(edge.parent) - ROW / 2));
+            }
+            points.push((x(parent.column), y(edge.parent)));
+            let points = points
+                .iter()
+                .map(|(x, y)| format!("{x},{y}"))
+                .collect::<Vec<_>>();
+            out.push_str(&format!(
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>\n",
+                points.join(" "),
+                COLORS[edge.lane % COLORS.len()]
+            ));
+        }
+
+        for (row, node) in self.commits.iter().enumerate() {
+            let (cx, cy) = (x(layout.rows[row].column), y(row));
+            let color = COLORS[layout.rows[row].column % COLORS.len()];
+            // merges are drawn hollow
+            let fill = if node.parents.len() > 1 {
+                "white"
+            } else {
+                color
+            };
+            out.push_str(&format!(
+                "<circle cx=\"{cx}\" cy=\"{cy}\" r=\"{RADIUS}\" fill=\"{fill}\" \
+                 stroke=\"{color}\" stroke-width=\"2\"><title>{}</title></circle>\n",
+                node.oid
+            ));
+
+            out.push_str(&format!(
+                "<text x=\"{text_x}\" y=\"{}\"><tspan fill=\"#b08800\">{}</tspan>",
+                cy + 4,
+                node.name
+            ));
+            if let Some(labels) = labels.get(node.oid.as_str()) {
+                out.push_str(&format!(
+                    " <tspan fill=\"#22863a\" font-weight=\"bold\">({})</tspan>",
+                    xml_escape(&labels.join(", "))
+                ));
+            }
+            out.push_str(&format!(" {}</text>\n", xml_escape(&node.subject)));
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+// where each commit of the SVG drawing goes, assigning lines of history to
+// lanes the way `log --graph` assigns them to columns
+struct Layout {
+    rows: Vec<Row>,
+    edges: Vec<Edge>,
+    // the number of lanes ever used
+    width: usize,
+}
+
+struct Row {
+    column: usize,
+}
+
+struct Edge {
+    child: usize,
+    parent: usize,
+    lane: usize,
+}
+
+impl Layout {
+    fn new(commits: &[Node]) -> Layout {
+        let index = commits
+            .iter()
+            .enumerate()
+            .map(|(row, node)| (node.oid.as_str(), row))
+            .collect::<HashMap<_, _>>();
+
+        // the commit each lane is waiting for
+        let mut lanes: Vec<Option<&str>> = vec![];
+        let mut rows = vec![];
+        let mut edges = vec![];
+        for (row, node) in commits.iter().enumerate() {
+            let oid = Some(node.oid.as_str());
+            let column = match lanes.iter().position(|lane| *lane == oid) {
+                Some(column) => column,
+                None => free_lane(&mut lanes),
+            };
+            for lane in lanes.iter_mut().filter(|lane| **lane == oid) {
+                *lane = None;
+            }
+
+            for (i, parent) in node.parents.iter().enumerate() {
+                let Some(&parent_row) = index.get(parent.as_str()) else {
+                    continue;
+                };
+                let waiting = lanes.iter().position(|lane| *lane == Some(parent));
+                let lane = match waiting {
+                    // the first parent carries on the commit's own lane,
+                    // unless a line left of it already leads there
+                    _ if i == 0 => waiting.map_or(column, |lane| lane.min(column)),
+                    Some(lane) => lane,
+                    None => free_lane(&mut lanes),
+                };
+                lanes[lane] = Some(parent);
+                edges.push(Edge {
+                    child: row,
+                    parent: parent_row,
+                    lane,
+                });
+            }
+            rows.push(Row { column });
+        }
+
+        let width = rows
+            .iter()
+            .map(|row| row.column)
+            .chain(edges.iter().map(|edge| edge.lane))
+            .max()
+            .map_or(0, |last| last + 1);
+        Layout { rows, edges, width }
+    }
+}
+
+// the first lane nothing is waiting on, adding one if they are all taken
+fn free_lane(lanes: &mut Vec<Option<&str>>) -> usize {
+    match lanes.iter().position(Option::is_none) {
+        Some(lane) => lane,
+        None => {
+            lanes.push(None);
+            lanes.len() - 1
+        }
+    }
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Mermaid labels can't escape quotes with backslashes, only as entities
+fn mermaid_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "#quot;"))
+}
+
+fn json_quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_list(key: &str, items: &[String]) -> String {
+    match items.is_empty() {
+        true => format!("  \"{key}\": []"),
+        false => format!("  \"{key}\": [\n{}\n  ]", items.join(",\n")),
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::RefValue;
+    use crate::testing::TestRepo;
+
+    //   a - b - m     main, HEAD
+    //    \     /
+    //     c ---       side
+    fn history() -> (TestRepo, [String; 4]) {
+        let repo = TestRepo::new();
+        let a = repo.commit(&[], &[("f", "a")], 100, "a");
+        let b = repo.commit(&[&a], &[("f", "b")], 200, "b");
+        let c = repo.commit(&[&a], &[("g", "c")], 300, "say \"c\"");
+        let m = repo.commit(&[&b, &c], &[("f", "b"), ("g", "c")], 400, "m");
+        repo.branch("main", &m, 400);
+        repo.branch("side", &c, 300);
+        let head = RefValue {
+            symbolic: true,
+            value: Some("refs/heads/main".to_string()),
+        };
+        data::set_ref(&repo, "HEAD", head, false).unwrap();
+        (repo, [a, b, c, m])
+    }
+
+    #[test]
+    fn dot_dashes_merged_parents() {
+        let (repo, [a, b, c, m]) = history();
+        let dot = History::load(&repo).unwrap().render(Format::Dot);
+        assert!(dot.contains(&format!("  \"{m}\" -> \"{b}\";\n")));
+        assert!(dot.contains(&format!("  \"{m}\" -> \"{c}\" [style=dashed];\n")));
+        assert!(dot.contains(&format!("  \"{b}\" -> \"{a}\";\n")));
+        let label = format!("[label=\"{} say \\\"c\\\"\"]", &c[..ABBREV]);
+        assert!(dot.contains(&label));
+        assert!(dot.contains("  \"HEAD\" -> \"refs/heads/main\";\n"));
+    }
+
+    #[test]
+    fn mermaid_quotes_as_entities() {
+        let (repo, [_, _, c, _]) = history();
+        let mermaid = History::load(&repo).unwrap().render(Format::Mermaid);
+        let node = format!("  c{c}[\"{} say #quot;c#quot;\"]\n", &c[..ABBREV]);
+        assert!(mermaid.contains(&node));
+        assert!(mermaid.contains("  HEAD --> r0\n"));
+    }
+
+    #[test]
+    fn json_on_an_unborn_branch() {
+        let repo = TestRepo::new();
+        let branch = data::resolve_ref(&repo, "HEAD").unwrap();
+        let json = History::load(&repo).unwrap().render(Format::Json);
+        let expected = format!(
+            "{{\n  \"head\": {{\"branch\": \"{branch}\", \"commit\": null}},\n  \
+             \"refs\": [],\n  \"commits\": []\n}}\n"
+        );
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn svg_lanes_follow_log_graph_columns() {
+        let (repo, [a, b, c, m]) = history();
+        let history = History::load(&repo).unwrap();
+        let order = history
+            .commits
+            .iter()
+            .map(|node| &node.oid)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [&m, &c, &b, &a]);
+
+        // *   m
+        // |\
+        // | * c
+        // * | b
+        // |/
+        // * a
+        let layout = Layout::new(&history.commits);
+        let columns = layout.rows.iter().map(|row| row.column).collect::<Vec<_>>();
+        assert_eq!(columns, [0, 1, 0, 0]);
+        let edges = layout
+            .edges
+            .iter()
+            .map(|edge| (edge.child, edge.parent, edge.lane))
+            .collect::<Vec<_>>();
+        assert_eq!(edges, [(0, 2, 0), (0, 1, 1), (1, 3, 1), (2, 3, 0)]);
+        assert_eq!(layout.width, 2);
+
+        // c's line turns back into a's column on the row above a
+        let svg = history.render(Format::Svg);
+        assert!(svg.contains("<polyline points=\"36,48 36,84 20,96\""));
+        assert!(svg.contains("(HEAD -&gt; main)</tspan> m</text>"));
+    }
+}
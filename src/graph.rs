@@ -0,0 +1,642 @@
+//! The text history graph of `log --graph`, laid out the way git draws it:
+//! one column per line of history, merges fanning out with `\` and lines
+//! joining again with `/`.
+//!
+//! Every commit takes one or more rows. The row showing the commit itself
+//! may be preceded by rows that make room for octopus merges, and followed
+//! by rows that route its parents into their columns and collapse lines
+//! that reach the same commit.
+
+/// Which kind of row comes next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Only the lines of history, unchanged; also whether the commit is done.
+    Padding,
+    /// `...`, when the previous commit did not finish its rows.
+    Skip,
+    /// Rows widening the space around an octopus merge.
+    PreCommit,
+    /// The row with the commit's `*`.
+    Commit,
+    /// The row fanning out the parents of a merge.
+    PostMerge,
+    /// Rows moving lines left into their columns.
+    Collapsing,
+}
+
+/// The graph drawn so far; `update` moves it to the next commit, whose rows
+/// are then taken one by one.
+#[derive(Debug, Clone)]
+pub struct Graph {
+    commit: Option<String>,
+    // the parents of the commit that are part of the walk
+    parents: Vec<String>,
+    // how many characters every row of the commit is padded to
+    width: usize,
+    expansion_row: usize,
+    state: State,
+    prev_state: State,
+    commit_index: usize,
+    prev_commit_index: usize,
+    // -1 until the first parent of a merge is placed, then 0 if it sits
+    // left of the merge and 1 otherwise
+    merge_layout: isize,
+    // how many more columns the commit's parents need than the commit
+    edges_added: isize,
+    prev_edges_added: isize,
+    // the commits each line of history leads to, before and after the
+    // current commit
+    columns: Vec<String>,
+    new_columns: Vec<String>,
+    // for each character position of the current row, the column of
+    // `new_columns` the line there is heading for, or -1
+    mapping: Vec<isize>,
+    old_mapping: Vec<isize>,
+    mapping_size: usize,
+}
+
+impl Default for Graph {
+    fn default() -> Graph {
+        Graph::new()
+    }
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            commit: None,
+            parents: vec![],
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: vec![],
+            new_columns: vec![],
+            mapping: vec![],
+            old_mapping: vec![],
+            mapping_size: 0,
+        }
+    }
+
+    /// Starts the rows of `commit`, which has `parents` in the walk.
+    pub fn update(&mut self, commit: &str, parents: &[String]) {
+        self.commit = Some(commit.to_string());
+        // a parent listed twice is drawn once
+        self.parents.clear();
+        for parent in parents {
+            if !self.parents.contains(parent) {
+                self.parents.push(parent.clone());
+            }
+        }
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+
+        // not through set_state: no row of the new state was drawn yet
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    /// How many characters the rows of the current commit take.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Whether every row of the current commit has been taken.
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    /// Appends the rows up to and including the one with the commit, which
+    /// is left without a newline so the commit's text can follow it.
+    pub fn show_commit(&mut self, out: &mut String) {
+        if self.is_commit_finished() {
+            out.push_str(&self.padding_line());
+            return;
+        }
+        while !self.is_commit_finished() {
+            let (line, is_commit) = self.next_line();
+            out.push_str(&line);
+            if is_commit {
+                break;
+            }
+            out.push('\n');
+        }
+    }
+
+    /// Appends `text` with the next row in front of each line after the
+    /// first, then the rows still left for the commit.
+    pub fn show_commit_msg(&mut self, out: &mut String, text: &str) {
+        let mut lines = text.split_inclusive('\n').peekable();
+        while let Some(line) = lines.next() {
+            out.push_str(line);
+            if lines.peek().is_some() {
+                out.push_str(&self.next_line().0);
+            }
+        }
+
+        if !self.is_commit_finished() {
+            let terminated = text.ends_with('\n');
+            if !terminated {
+                out.push('\n');
+            }
+            self.show_remainder(out);
+            if terminated {
+                out.push('\n');
+            }
+        }
+    }
+
+    // appends the rows left for the commit, separated by newlines
+    fn show_remainder(&mut self, out: &mut String) {
+        while !self.is_commit_finished() {
+            out.push_str(&self.next_line().0);
+            if !self.is_commit_finished() {
+                out.push('\n');
+            }
+        }
+    }
+
+    /// A row to put in front of text that goes below the commit, such as
+    /// its changes or the blank line between entries.
+    pub fn padding_line(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+
+        let mut line = String::new();
+        for column in &self.columns {
+            line.push('|');
+            if Some(column) == self.commit.as_ref() && self.parents.len() > 2 {
+                line.push_str(&" ".repeat((self.parents.len() - 2) * 2));
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad(&mut line);
+        self.prev_state = State::Padding;
+        line
+    }
+
+    /// The next row of the commit, and whether it is the one with the
+    /// commit itself.
+    pub fn next_line(&mut self) -> (String, bool) {
+        if self.commit.is_none() {
+            return (String::new(), false);
+        }
+
+        let mut line = String::new();
+        let state = self.state;
+        match state {
+            State::Padding => {
+                for _ in &self.new_columns {
+                    line.push_str("| ");
+                }
+            }
+            State::Skip => self.skip_line(&mut line),
+            State::PreCommit => self.pre_commit_line(&mut line),
+            State::Commit => self.commit_line(&mut line),
+            State::PostMerge => self.post_merge_line(&mut line),
+            State::Collapsing => self.collapsing_line(&mut line),
+        }
+        self.pad(&mut line);
+
+        (line, state == State::Commit)
+    }
+
+    fn set_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    // pads `line` so all rows of a commit line up the text next to them
+    fn pad(&self, line: &mut String) {
+        let len = line.chars().count();
+        if len < self.width {
+            line.push_str(&" ".repeat(self.width - len));
+        }
+    }
+
+    fn is_current(&self, column: &str) -> bool {
+        self.commit.as_deref() == Some(column)
+    }
+
+    fn find_new_column(&self, commit: &str) -> Option<usize> {
+        self.new_columns.iter().position(|c| c == commit)
+    }
+
+    fn mapping_at(&self, i: usize) -> isize {
+        self.mapping.get(i).copied().unwrap_or(-1)
+    }
+
+    // Works out the columns below the current commit, and where each line
+    // of history above it is heading.
+    fn update_columns(&mut self) {
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+        // The commit row still looks at where the lines were headed in the
+        // row before it. As in git, only the part of the mapping in use is
+        // reset; the commit row may also read past it.
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping_size = 2 * max_new_columns;
+        let len = self.mapping_size.max(self.old_mapping.len());
+        self.mapping.resize(len, -1);
+        self.old_mapping.resize(len, -1);
+        for slot in &mut self.mapping[..self.mapping_size] {
+            *slot = -1;
+        }
+
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column.clone(),
+                None if seen_this => break,
+                None => self.commit.clone().unwrap_or_default(),
+            };
+
+            if self.is_current(&column) {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    self.insert_into_new_columns(&parent, Some(i));
+                }
+                // the commit takes up two characters even without parents
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(&column, None);
+            }
+        }
+
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    // Adds `commit` to the columns below the current commit if it is not
+    // there yet, and maps the next character position to it. `index` is
+    // the column of the current commit when `commit` is one of its parents.
+    fn insert_into_new_columns(&mut self, commit: &str, index: Option<usize>) {
+        let i = match self.find_new_column(commit) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(commit.to_string());
+                self.new_columns.len() - 1
+            }
+        } as isize;
+
+        let mapping_index;
+        match index {
+            Some(index) if self.parents.len() > 1 && self.merge_layout == -1 => {
+                // the first parent of a merge decides whether the merge
+                // leans left, onto a line already to its left, or right
+                let distance = index as isize - i;
+                let shift = if distance > 1 { 2 * distance - 3 } else { 1 };
+                self.merge_layout = if distance > 0 { 0 } else { 1 };
+                self.edges_added = self.parents.len() as isize + self.merge_layout - 2;
+                mapping_index = self.width as isize + (self.merge_layout - 1) * shift;
+                self.width += 2 * self.merge_layout as usize;
+            }
+            _ if self.edges_added > 0
+                && self.width >= 2
+                && i == self.mapping_at(self.width - 2) =>
+            {
+                // the line added by a merge joins the last existing one
+                // right away
+                mapping_index = self.width as isize - 2;
+                self.edges_added = -1;
+            }
+            _ => {
+                mapping_index = self.width as isize;
+                self.width += 2;
+            }
+        }
+
+        self.mapping[mapping_index.max(0) as usize] = i;
+    }
+
+    fn dashed_parents(&self) -> isize {
+        self.parents.len() as isize + self.merge_layout - 3
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && self.commit_index + 1 < self.columns.len()
+            && (self.expansion_row as isize) < self.dashed_parents() * 2
+    }
+
+    // whether every line already sits in its column, or one to the right
+    // of it where the `/` drawn there takes it home
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, &target)| target < 0 || target == (i / 2) as isize)
+    }
+
+    fn skip_line(&mut self, line: &mut String) {
+        line.push_str("...");
+        match self.needs_pre_commit_line() {
+            true => self.set_state(State::PreCommit),
+            false => self.set_state(State::Commit),
+        }
+    }
+
+    // widens the space right of an octopus merge, two rows per dashed parent
+    fn pre_commit_line(&mut self, line: &mut String) {
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if self.is_current(column) {
+                seen_this = true;
+                line.push('|');
+                line.push_str(&" ".repeat(self.expansion_row));
+            } else if seen_this && self.expansion_row == 0 {
+                // lines that were `\` after a previous merge stay that way
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if seen_this {
+                line.push('\\');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.set_state(State::Commit);
+        }
+    }
+
+    fn commit_line(&mut self, line: &mut String) {
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let is_current = match self.columns.get(i) {
+                Some(column) => self.is_current(column),
+                None if seen_this => break,
+                None => true,
+            };
+
+            if is_current {
+                seen_this = true;
+                line.push('*');
+                if self.parents.len() > 2 {
+                    // the dashes of an octopus merge
+                    let dashed = self.dashed_parents().max(0);
+                    for j in 0..dashed {
+                        line.push('-');
+                        line.push(if j == dashed - 1 { '.' } else { '-' });
+                    }
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push('\\');
+            } else if seen_this && self.edges_added == 1 {
+                // the first row of a merge leaning right; a line that was
+                // `\` after the previous merge stays that way
+                if self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping.get(2 * i + 1).copied() == Some(i as isize)
+                && self.mapping_at(2 * i) < i as isize
+            {
+                line.push('/');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        if self.parents.len() > 1 {
+            self.set_state(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.set_state(State::Padding);
+        } else {
+            self.set_state(State::Collapsing);
+        }
+    }
+
+    fn post_merge_line(&mut self, line: &mut String) {
+        const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+        let first_parent = self.parents.first().cloned();
+        let mut seen_this = false;
+        let mut parent_column = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column.clone(),
+                None if seen_this => break,
+                None => self.commit.clone().unwrap_or_default(),
+            };
+
+            if self.is_current(&column) {
+                seen_this = true;
+                let mut index = self.merge_layout.max(0) as usize;
+                for j in 0..self.parents.len() {
+                    line.push(MERGE_CHARS[index]);
+                    if index == 2 {
+                        if self.edges_added > 0 || j + 1 < self.parents.len() {
+                            line.push(' ');
+                        }
+                    } else {
+                        index += 1;
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                line.push(if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push('|');
+                if self.merge_layout != 0 || i + 1 != self.commit_index {
+                    line.push(if parent_column { '_' } else { ' ' });
+                }
+            }
+
+            if first_parent.as_ref() == Some(&column) {
+                parent_column = true;
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.set_state(State::Padding);
+        } else {
+            self.set_state(State::Collapsing);
+        }
+    }
+
+    // moves every line one step closer to its column, with `_` for the
+    // first line that has to cross several others
+    fn collapsing_line(&mut self, line: &mut String) {
+        let mut used_horizontal = false;
+        let mut horizontal_edge = -1isize;
+        let mut horizontal_edge_target = -1isize;
+
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        for slot in &mut self.mapping[..self.mapping_size] {
+            *slot = -1;
+        }
+
+        for i in 0..self.mapping_size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+
+            if (target * 2) as usize == i {
+                // already in its column
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                // nothing to the left, so move left by one
+                self.mapping[i - 1] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge = i as isize;
+                    horizontal_edge_target = target;
+                    let mut j = target * 2 + 3;
+                    while j < i as isize - 2 {
+                        self.mapping[j as usize] = target;
+                        j += 2;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // the line to the left goes to the same commit, so they merge
+            } else {
+                // cross over the line to the left
+                self.mapping[i - 2] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge_target = target;
+                    horizontal_edge = i as isize - 1;
+                    let mut j = target * 2 + 3;
+                    while j < i as isize - 2 {
+                        self.mapping[j as usize] = target;
+                        j += 2;
+                    }
+                }
+            }
+        }
+
+        if self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+            if target < 0 {
+                line.push(' ');
+            } else if (target * 2) as usize == i {
+                line.push('|');
+            } else if target == horizontal_edge_target && i as isize != horizontal_edge - 1 {
+                // only the first segment of a horizontal line continues
+                // into the next row
+                if i as isize != target * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.push('_');
+            } else {
+                if used_horizontal && (i as isize) < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                line.push('/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.set_state(State::Padding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data;
+    use crate::revision::Tip;
+    use crate::testing::TestRepo;
+    use crate::walk::{self, Order, WalkOptions};
+
+    // `log --graph --format=%s` of `tip`
+    fn log_graph(repo: &TestRepo, tip: &str) -> String {
+        let tips = [Tip {
+            oid: tip.to_string(),
+            excluded: false,
+        }];
+        let options = WalkOptions {
+            order: Order::Topo,
+            ..WalkOptions::default()
+        };
+        let walk = walk::commits(repo, &tips, &options).unwrap();
+        let mut graph = Graph::new();
+        let mut out = String::new();
+        for oid in &walk.commits {
+            let subject = data::get_commit(repo, oid).unwrap().message;
+            graph.update(oid, &walk.parents[oid]);
+            graph.show_commit(&mut out);
+            graph.show_commit_msg(&mut out, subject.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    #[test]
+    fn merges_fan_out_and_join() {
+        let repo = TestRepo::new();
+        let time = 1_700_000_000;
+        let a = repo.commit(&[], &[("f", "a")], time, "a");
+        let b = repo.commit(&[&a], &[("f", "b")], time + 10, "b");
+        let c = repo.commit(&[&a], &[("g", "c")], time + 20, "c");
+        let d = repo.commit(&[&b], &[("f", "d")], time + 30, "d");
+        let m = repo.commit(&[&d, &c], &[("f", "d"), ("g", "c")], time + 40, "m");
+        let e = repo.commit(&[&b], &[("h", "e")], time + 50, "e");
+        let n = repo.commit(
+            &[&m, &e],
+            &[("f", "d"), ("g", "c"), ("h", "e")],
+            time + 60,
+            "n",
+        );
+        // as git draws it, trailing spaces included
+        let expected = concat!(
+            "*   n\n",
+            "|\\  \n",
+            "| * e\n",
+            "* |   m\n",
+            "|\\ \\  \n",
+            "| * | c\n",
+            "* | | d\n",
+            "| |/  \n",
+            "|/|   \n",
+            "* | b\n",
+            "|/  \n",
+            "* a\n",
+        );
+        assert_eq!(log_graph(&repo, &n), expected);
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::Repository;
+
+pub const IGNORE_FILE: &str = ".rgitignore";
+
+/// One line of an ignore file, with gitignore semantics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// The file the pattern came from, for `check-ignore -v`.
+    pub source: String,
+    pub line: usize,
+    /// The pattern as written, including any `!`.
+    pub text: String,
+    pub negated: bool,
+    dir_only: bool,
+    /// Patterns containing a `/` match the whole path below `base`,
+    /// the others only the last path component.
+    anchored: bool,
+    glob: String,
+    /// Directory holding the ignore file, relative to the work tree.
+    base: String,
+}
+
+impl Pattern {
+    fn parse(line: &str, source: &str, number: usize, base: &str) -> Option<Pattern> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let text = line.to_string();
+        let (negated, mut glob) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                line.strip_prefix('\\')
+                    .filter(|r| r.starts_with(['#', '!']))
+                    .unwrap_or(line),
+            ),
+        };
+
+        let dir_only = glob.ends_with('/');
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            source: source.to_string(),
+            line: number,
+            text,
+            negated,
+            dir_only,
+            anchored,
+            glob: glob.to_string(),
+            base: base.to_string(),
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(&self.base) {
+                Some(rest) if rest.starts_with('/') => &rest[1..],
+                _ => return false,
+            }
+        };
+
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+// trailing spaces are dropped unless escaped with a backslash
+fn trim_trailing_spaces(line: &str) -> &str {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let mut end = line.len();
+    while end > 0 && line.as_bytes()[end - 1] == b' ' {
+        if end > 1 && line.as_bytes()[end - 2] == b'\\' {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// The exclude rules that apply to a work tree: every `.rgitignore`, then
+/// `.rgit/info/exclude`, then the user's global excludes file
+/// (`core.excludesFile`).
+pub struct Ignore {
+    work_tree: PathBuf,
+    /// Global excludes followed by `info/exclude`, lowest precedence first.
+    base_patterns: Vec<Pattern>,
+    /// Patterns of each directory's `.rgitignore`, loaded on first use.
+    dir_patterns: HashMap<String, Vec<Pattern>>,
+}
+
+impl Ignore {
+    pub fn new(repo: &Repository) -> Result<Ignore> {
+        let work_tree = repo.work_tree()?.to_path_buf();
+        let mut base_patterns = vec![];
+
+        let config = Config::load(Some(repo))?;
+        let global = config
+            .get_path("core.excludesFile")
+            .or_else(global_excludes_file);
+        if let Some(global) = global {
+            base_patterns.extend(read_patterns(&global, &global.to_string_lossy(), "")?);
+        }
+
+        let exclude = repo.git_dir().join("info/exclude");
+        let source = match exclude.strip_prefix(&work_tree) {
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            Err(_) => exclude.to_string_lossy().into_owned(),
+        };
+        base_patterns.extend(read_patterns(&exclude, &source, "")?);
+
+        Ok(Ignore {
+            work_tree,
+            base_patterns,
+            dir_patterns: HashMap::new(),
+        })
+    }
+
+    /// The pattern deciding whether `path` is ignored, if any matches.
+    /// A path inside an ignored directory is reported with the directory's pattern.
+    pub fn check(&mut self, path: &str, is_dir: bool) -> Result<Option<Pattern>> {
+        let mut prefix = String::new();
+        for component in path.split('/').collect::<Vec<_>>().split_last().unwrap().1 {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(component);
+
+            if let Some(pattern) = self.last_match(&prefix, true)? {
+                if !pattern.negated {
+                    return Ok(Some(pattern));
+                }
+            }
+        }
+
+        self.last_match(path, is_dir)
+    }
+
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> Result<bool> {
+        Ok(self.check(path, is_dir)?.is_some_and(|p| !p.negated))
+    }
+
+    /// Checks `path` itself, assuming its parent directories are not ignored.
+    pub fn last_match(&mut self, path: &str, is_dir: bool) -> Result<Option<Pattern>> {
+        let mut dirs = vec![String::new()];
+        let mut dir = String::new();
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            for component in parent.split('/') {
+                if !dir.is_empty() {
+                    dir.push('/');
+                }
+                dir.push_str(component);
+                dirs.push(dir.clone());
+            }
+        }
+
+        for dir in &dirs {
+            self.load_dir(dir)?;
+        }
+
+        // later patterns take precedence, and deeper files override shallower ones
+        let mut patterns = self
+            .base_patterns
+            .iter()
+            .chain(dirs.iter().flat_map(|d| &self.dir_patterns[d]));
+
+        Ok(patterns.rfind(|p| p.matches(path, is_dir)).cloned())
+    }
+
+    fn load_dir(&mut self, dir: &str) -> Result<()> {
+        if self.dir_patterns.contains_key(dir) {
+            return Ok(());
+        }
+
+        let source = if dir.is_empty() {
+            IGNORE_FILE.to_string()
+        } else {
+            format!("{dir}/{IGNORE_FILE}")
+        };
+        let patterns = read_patterns(&self.work_tree.join(&source), &source, dir)?;
+        self.dir_patterns.insert(dir.to_string(), patterns);
+
+        Ok(())
+    }
+}
+
+fn read_patterns(file: &Path, source: &str, base: &str) -> Result<Vec<Pattern>> {
+    let contents = match fs::read(file) {
+        Ok(contents) => contents,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+            return Ok(vec![])
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(String::from_utf8_lossy(&contents)
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, source, i + 1, base))
+        .collect())
+}
+
+// `$XDG_CONFIG_HOME/rgit/ignore`, falling back to `~/.config/rgit/ignore`
+fn global_excludes_file() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("rgit/ignore")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/rgit/ignore")),
+    }
+}
+
+/// Matches `text` against a glob where `*` and `?` stop at `/`, `**`
+/// spans directories, and `[...]` is a character class.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            b'?' => {
+                if t >= text.len() || text[t] == b'/' {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'*' => {
+                let mut end = p;
+                while end < pattern.len() && pattern[end] == b'*' {
+                    end += 1;
+                }
+                let rest = &pattern[end..];
+
+                let whole_component = (p == 0 || pattern[p - 1] == b'/')
+                    && (end == pattern.len() || pattern[end] == b'/');
+                if end - p >= 2 && whole_component {
+                    // trailing "**" matches everything left
+                    if rest.is_empty() {
+                        return true;
+                    }
+                    // "**/" matches zero or more directories
+                    let rest = &rest[1..];
+                    return (t..=text.len())
+                        .filter(|&k| k == t || text[k - 1] == b'/')
+                        .any(|k| wildmatch(rest, &text[k..]));
+                }
+
+                let mut k = t;
+                loop {
+                    if wildmatch(rest, &text[k..]) {
+                        return true;
+                    }
+                    if k >= text.len() || text[k] == b'/' {
+                        return false;
+                    }
+                    k += 1;
+                }
+            }
+            b'[' => match match_class(&pattern[p..], text.get(t).copied()) {
+                Some((true, len)) => {
+                    p += len;
+                    t += 1;
+                }
+                Some((false, _)) => return false,
+                // an unterminated class is a literal '['
+                None => {
+                    if text.get(t) != Some(&b'[') {
+                        return false;
+                    }
+                    p += 1;
+                    t += 1;
+                }
+            },
+            c => {
+                if text.get(t) != Some(&c) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+
+    t == text.len()
+}
+
+/// Matches `c` against the class at the start of `pattern`, returning
+/// whether it matched and how long the class is.
+fn match_class(pattern: &[u8], c: Option<u8>) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut lo = *pattern.get(i)?;
+        if lo == b']' && !first {
+            break;
+        }
+        first = false;
+
+        // `[:digit:]` and the like, inside the brackets of a class
+        if lo == b'[' && pattern.get(i + 1) == Some(&b':') {
+            let len = pattern[i..].iter().position(|&b| b == b']')?;
+            if let Some(name) = pattern[i + 2..i + len].strip_suffix(b":") {
+                let Some(member) = posix_class(name) else {
+                    // like git, an unknown class name matches nothing
+                    return Some((false, 0));
+                };
+                matched |= c.is_some_and(|c| member(&c));
+                i += len + 1;
+                continue;
+            }
+        }
+
+        if lo == b'\\' {
+            i += 1;
+            lo = *pattern.get(i)?;
+        }
+        let mut hi = lo;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&h| h != b']') {
+            hi = pattern[i + 2];
+            i += 2;
+        }
+        if c.is_some_and(|c| lo <= c && c <= hi) {
+            matched = true;
+        }
+        i += 1;
+    }
+
+    let matched = c.is_some_and(|c| c != b'/') && matched != negated;
+    Some((matched, i + 1))
+}
+
+// the members of a POSIX class such as `alpha`, by git's definitions
+fn posix_class(name: &[u8]) -> Option<fn(&u8) -> bool> {
+    Some(match name {
+        b"alnum" => u8::is_ascii_alphanumeric,
+        b"alpha" => u8::is_ascii_alphabetic,
+        b"blank" => |c| matches!(c, b' ' | b'\t'),
+        b"cntrl" => u8::is_ascii_control,
+        b"digit" => u8::is_ascii_digit,
+        b"graph" => u8::is_ascii_graphic,
+        b"lower" => u8::is_ascii_lowercase,
+        b"print" => |c| c.is_ascii_graphic() || *c == b' ',
+        b"punct" => u8::is_ascii_punctuation,
+        b"space" => |c| matches!(c, b' ' | b'\t' | b'\n' | b'\r'),
+        b"upper" => u8::is_ascii_uppercase,
+        b"xdigit" => u8::is_ascii_hexdigit,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wildmatch;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        wildmatch(pattern.as_bytes(), text.as_bytes())
+    }
+
+    #[test]
+    fn double_star_spans_directories() {
+        assert!(matches("**/foo", "foo"));
+        assert!(matches("**/foo", "a/b/foo"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(matches("a/**", "a/x/y"));
+        assert!(!matches("a/**/b", "ab"));
+        assert!(!matches("**/foo", "afoo"));
+    }
+
+    #[test]
+    fn double_star_inside_a_component_is_a_single_star() {
+        assert!(matches("a**b", "axyb"));
+        assert!(!matches("a**b", "a/b"));
+        assert!(!matches("**foo", "a/foo"));
+    }
+
+    #[test]
+    fn single_star_and_question_mark_stop_at_slashes() {
+        assert!(matches("*.c", "main.c"));
+        assert!(!matches("*.c", "src/main.c"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "a/c"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("[abc]", "b"));
+        assert!(!matches("[abc]", "d"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(matches("[!a-c]", "d"));
+        assert!(matches("[^a-c]", "d"));
+        assert!(!matches("[!a-c]", "b"));
+        // a leading ']' is a member, a trailing '-' is literal
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[\\]]", "]"));
+        // negated classes never match a slash
+        assert!(!matches("a[!x]b", "a/b"));
+    }
+
+    #[test]
+    fn posix_classes() {
+        assert!(matches("v[[:digit:]]", "v1"));
+        assert!(!matches("v[[:digit:]]", "vx"));
+        assert!(matches("[[:alpha:][:digit:]_]*", "_x9"));
+        assert!(matches("a[[:space:]]b", "a b"));
+        assert!(matches("[[:upper:]][[:lower:]]", "Ab"));
+        assert!(matches("[![:alnum:]]", "-"));
+        assert!(!matches("[^[:alnum:]]", "a"));
+        assert!(matches("[[:xdigit:]][[:punct:]]", "f."));
+        // without the closing ':' it is a plain class
+        assert!(matches("[[:x]", ":"));
+        // an unknown class matches nothing, not even itself
+        assert!(!matches("*[[:nope:]]", "[:nope:]"));
+    }
+
+    #[test]
+    fn unterminated_class_is_literal() {
+        assert!(matches("[ab", "[ab"));
+        assert!(!matches("[ab", "a"));
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
+use std::io::ErrorKind;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+use crate::base::{self, TreeFiles};
+use crate::error::{Error, Result};
+use crate::object::{self, MODE_BLOB, MODE_EXECUTABLE, MODE_SYMLINK};
+use crate::Repository;
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const VERSION: u32 = 2;
+// ctime, mtime, dev, ino, mode, uid, gid, size, oid and flags
+const ENTRY_HEADER_LEN: usize = 62;
+
+/// One staged file, laid out like an entry of git's version 2 index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub oid: String,
+    pub stage: u8,
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// An entry without stat data, so the work tree file is always re-checked.
+    pub fn new(path: &str, mode: u32, oid: &str) -> IndexEntry {
+        IndexEntry {
+            mode,
+            oid: oid.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_metadata(path: &str, mode: u32, oid: &str, meta: &Metadata) -> IndexEntry {
+        let mut entry = IndexEntry::new(path, mode, oid);
+        entry.update_stat(meta);
+        entry
+    }
+
+    /// Whether `meta` still matches the stat data recorded when the entry was
+    /// staged, in which case the file is assumed to be unchanged.
+    pub fn stat_matches(&self, meta: &Metadata) -> bool {
+        self.mode == file_mode(meta)
+            && self.mtime == (meta.mtime() as u32, meta.mtime_nsec() as u32)
+            && self.ctime == (meta.ctime() as u32, meta.ctime_nsec() as u32)
+            && self.ino == meta.ino() as u32
+            && self.size == meta.size() as u32
+    }
+
+    pub fn update_stat(&mut self, meta: &Metadata) {
+        self.ctime = (meta.ctime() as u32, meta.ctime_nsec() as u32);
+        self.mtime = (meta.mtime() as u32, meta.mtime_nsec() as u32);
+        self.dev = meta.dev() as u32;
+        self.ino = meta.ino() as u32;
+        self.uid = meta.uid();
+        self.gid = meta.gid();
+        self.size = meta.size() as u32;
+    }
+}
+
+/// The staging area stored in `.rgit/index`, keyed by path and merge stage.
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    entries: BTreeMap<(String, u8), IndexEntry>,
+    /// mtime of the index file when it was read.
+    timestamp: Option<(u32, u32)>,
+}
+
+impl Index {
+    /// Reads the index, or returns an empty one if it has never been written.
+    pub fn read(repo: &Repository) -> Result<Index> {
+        let path = repo.git_dir().join("index");
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Index::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut index = Index::parse(&data)?;
+        let meta = fs::metadata(path)?;
+        index.timestamp = Some((meta.mtime() as u32, meta.mtime_nsec() as u32));
+
+        Ok(index)
+    }
+
+    fn parse(data: &[u8]) -> Result<Index> {
+        let corrupt = |reason: &str| Error::CorruptIndex(reason.to_string());
+
+        if data.len() < 12 + 20 || &data[..4] != SIGNATURE {
+            return Err(corrupt("bad signature"));
+        }
+        let (body, checksum) = data.split_at(data.len() - 20);
+        if sha1_smol::Sha1::from(body).digest().bytes() != checksum {
+            return Err(corrupt("bad checksum"));
+        }
+        if read_u32(data, 4) != VERSION {
+            return Err(corrupt("unsupported version"));
+        }
+
+        let count = read_u32(data, 8) as usize;
+        let mut index = Index::default();
+        let mut pos = 12;
+
+        for _ in 0..count {
+            if pos + ENTRY_HEADER_LEN > body.len() {
+                return Err(corrupt("truncated entry"));
+            }
+
+            let field = |i: usize| read_u32(body, pos + i * 4);
+            let flags = u16::from_be_bytes([body[pos + 60], body[pos + 61]]);
+            let name_start = pos + ENTRY_HEADER_LEN;
+            let name_len = body[name_start..]
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| corrupt("unterminated path"))?;
+            let path = String::from_utf8(body[name_start..name_start + name_len].to_vec())
+                .map_err(|_| corrupt("path is not UTF-8"))?;
+
+            let entry = IndexEntry {
+                ctime: (field(0), field(1)),
+                mtime: (field(2), field(3)),
+                dev: field(4),
+                ino: field(5),
+                mode: field(6),
+                uid: field(7),
+                gid: field(8),
+                size: field(9),
+                oid: hex::encode(&body[pos + 40..pos + 60]),
+                stage: ((flags >> 12) & 0x3) as u8,
+                path,
+            };
+            index.insert(entry);
+
+            pos += entry_len(name_len);
+        }
+
+        Ok(index)
+    }
+
+    pub fn write(&self, repo: &Repository) -> Result<()> {
+        let mut out = SIGNATURE.to_vec();
+        out.extend_from_slice(&VERSION.to_be_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        for e in self.entries.values() {
+            let start = out.len();
+            for field in [
+                e.ctime.0, e.ctime.1, e.mtime.0, e.mtime.1, e.dev, e.ino, e.mode, e.uid, e.gid,
+                e.size,
+            ] {
+                out.extend_from_slice(&field.to_be_bytes());
+            }
+            out.extend_from_slice(&object::raw_oid(&e.oid)?);
+
+            let name_len = e.path.len().min(0xfff) as u16;
+            let flags = (u16::from(e.stage) << 12) | name_len;
+            out.extend_from_slice(&flags.to_be_bytes());
+            out.extend_from_slice(e.path.as_bytes());
+            out.resize(start + entry_len(e.path.len()), 0);
+        }
+
+        let checksum = sha1_smol::Sha1::from(&out).digest().bytes();
+        out.extend_from_slice(&checksum);
+
+        // write through a lock file so a crash never leaves a half-written index
+        let lock = repo.git_dir().join("index.lock");
+        fs::write(&lock, &out)?;
+        fs::rename(lock, repo.git_dir().join("index"))?;
+
+        Ok(())
+    }
+
+    /// Builds an index holding every file of `tree`, without stat data.
+    pub fn from_tree(repo: &Repository, tree: &str) -> Result<Index> {
+        let mut index = Index::default();
+        for (path, (mode, oid)) in base::flatten_tree(repo, tree)? {
+            index.add(IndexEntry::new(&path, mode, &oid));
+        }
+
+        Ok(index)
+    }
+
+    fn insert(&mut self, entry: IndexEntry) {
+        self.entries
+            .insert((entry.path.clone(), entry.stage), entry);
+    }
+
+    /// Stages `entry`, replacing whatever was recorded for its path.
+    pub fn add(&mut self, entry: IndexEntry) {
+        self.remove(&entry.path);
+        self.insert(entry);
+    }
+
+    /// Records one side of a conflicted path, keeping its other stages but
+    /// dropping the merged stage 0 entry.
+    pub fn add_unmerged(&mut self, entry: IndexEntry) {
+        self.entries.remove(&(entry.path.clone(), 0));
+        self.insert(entry);
+    }
+
+    /// Paths with unresolved conflicts and the stages recorded for each
+    /// (1 for the base, 2 for ours and 3 for theirs).
+    pub fn unmerged(&self) -> BTreeMap<String, Vec<u8>> {
+        let mut unmerged = BTreeMap::<String, Vec<u8>>::new();
+        for (path, stage) in self.entries.keys().filter(|(_, s)| *s != 0) {
+            unmerged.entry(path.clone()).or_default().push(*stage);
+        }
+        unmerged
+    }
+
+    /// Unstages every stage of `path`; returns whether anything was removed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|(p, _), _| p != path);
+        before != self.entries.len()
+    }
+
+    /// Whether the work tree file described by `meta` can be trusted to still
+    /// hold the entry's contents without re-hashing it.
+    pub fn is_up_to_date(&self, entry: &IndexEntry, meta: &Metadata) -> bool {
+        // A file modified in the same instant the index was written could
+        // still have matching stat data, so such "racy" entries are re-checked.
+        let racy = self.timestamp.is_some_and(|ts| entry.mtime >= ts);
+        entry.stat_matches(meta) && !racy
+    }
+
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries.get(&(path.to_string(), 0))
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut IndexEntry> {
+        self.entries.get_mut(&(path.to_string(), 0))
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.keys().any(|(p, _)| p == path)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &IndexEntry> {
+        self.entries.values()
+    }
+
+    /// Paths equal to `prefix` or inside the directory it names.
+    pub fn paths_under(&self, prefix: &str) -> Vec<String> {
+        let mut paths = self
+            .entries
+            .keys()
+            .map(|(p, _)| p)
+            .filter(|p| is_under(p, prefix))
+            .cloned()
+            .collect::<Vec<String>>();
+        paths.dedup();
+        paths
+    }
+
+    /// Stage 0 entries as a path -> (mode, oid) map.
+    pub fn files(&self) -> TreeFiles {
+        self.entries
+            .values()
+            .filter(|e| e.stage == 0)
+            .map(|e| (e.path.clone(), (e.mode, e.oid.clone())))
+            .collect()
+    }
+
+    pub fn write_tree(&self, repo: &Repository) -> Result<String> {
+        base::build_tree(repo, &self.files())
+    }
+}
+
+/// The tree mode recording a work tree file, from its `symlink_metadata`.
+/// Only the executable bit of regular files is kept, as in git.
+pub fn file_mode(meta: &Metadata) -> u32 {
+    if meta.file_type().is_symlink() {
+        MODE_SYMLINK
+    } else if meta.permissions().mode() & 0o100 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_BLOB
+    }
+}
+
+pub fn is_under(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+// entries are NUL-padded to a multiple of eight bytes
+fn entry_len(name_len: usize) -> usize {
+    (ENTRY_HEADER_LEN + name_len + 8) & !7
+}
@@ -0,0 +1,22 @@
+pub mod base;
+pub mod config;
+pub mod data;
+pub mod date;
+pub mod diff;
+mod error;
+pub mod export;
+pub mod graph;
+pub mod ignore;
+pub mod index;
+pub mod merge;
+pub mod object;
+pub mod pretty;
+mod repository;
+pub mod revision;
+#[cfg(test)]
+mod testing;
+pub mod walk;
+pub mod xdiff;
+
+pub use error::{Error, Result};
+pub use repository::Repository;
@@ -1,13 +1,17 @@
-mod base;
-mod data;
-mod diff;
-
-use clap::{Parser, Subcommand};
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{stdout, Read, Write};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use rgit::config::{self, Config, ConfigFile, Level};
+use rgit::diff::{FileChange, Renames, Source, Unmerged};
+use rgit::ignore::Ignore;
+use rgit::index::Index;
+use rgit::object::Object;
+use rgit::{
+    base, data, date, diff, export, merge, pretty, revision, walk, xdiff, Error, Repository,
+};
+use std::env;
+use std::fs;
+use std::io::{stdout, IsTerminal, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process;
 
 // 本地仓库
 
@@ -17,6 +21,14 @@ struct Cli {
     /// Optional name to operate on
     name: Option<String>,
 
+    /// Use this directory as the work tree instead of the repository root
+    #[arg(long, global = true, env = "RGIT_WORK_TREE")]
+    work_tree: Option<PathBuf>,
+
+    /// Use this rgit directory instead of searching for `.rgit`
+    #[arg(long, global = true, env = "RGIT_DIR")]
+    git_dir: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -24,7 +36,12 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// creates a new empty repository
-    Init,
+    Init {
+        directory: Option<PathBuf>,
+        /// create a repository without a work tree
+        #[arg(long)]
+        bare: bool,
+    },
     HashObject {
         #[arg(short, long)]
         filename: String,
@@ -39,11 +56,42 @@ enum Commands {
         tree: String,
     },
     Commit {
+        /// the message, which a `merge --squash` saves otherwise
         #[arg(short, long)]
-        message: String,
+        message: Option<String>,
+        /// override the author, given as "Name <email>"
+        #[arg(long)]
+        author: Option<String>,
+        /// override the author date
+        #[arg(long)]
+        date: Option<String>,
     },
     Log {
-        oid: Option<String>,
+        /// the revisions to start from, HEAD by default
+        revs: Vec<String>,
+        /// follow the history of a single file across renames
+        #[arg(long, requires = "paths")]
+        follow: bool,
+        /// only show commits that changed these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        format: FormatArgs,
+        #[command(flatten)]
+        diff: DiffArgs,
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+    /// list the commits reachable from some revisions but not others
+    RevList {
+        /// `^A` and `A..B` exclude A
+        #[arg(required_unless_present = "not")]
+        revs: Vec<String>,
+        /// only list commits that changed these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        walk: WalkArgs,
     },
     Checkout {
         #[arg(short, long)]
@@ -53,149 +101,735 @@ enum Commands {
         name: String,
         oid: Option<String>,
     },
-    K,
+    /// export the history of all refs and HEAD as a graph
+    K {
+        /// dot, mermaid, json or svg
+        #[arg(long, default_value = "dot")]
+        format: String,
+        /// write the graph to this file instead of stdout
+        #[arg(short, long, value_name = "file")]
+        output: Option<PathBuf>,
+    },
     Branch {
         name: Option<String>,
         start_point: Option<String>,
     },
     Status,
+    /// stage the current contents of files
+    Add {
+        /// also add ignored files
+        #[arg(short, long)]
+        force: bool,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// remove files from the index and the work tree
+    Rm {
+        /// only remove from the index
+        #[arg(long)]
+        cached: bool,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// show which ignore rule matches each path
+    CheckIgnore {
+        /// print the matching rule and where it comes from
+        #[arg(short, long)]
+        verbose: bool,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// restore work tree files from the index, or the index from HEAD
+    Restore {
+        /// restore the index instead of the work tree
+        #[arg(long)]
+        staged: bool,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
     Reset {
         #[arg(short, long)]
         commit: String,
     },
     Show {
         oid: Option<String>,
+        #[command(flatten)]
+        diff: DiffArgs,
     },
+    /// show changes between the index and the work tree, or between revisions
     Diff {
-        oid: Option<String>,
+        /// compare the index with HEAD, or with the given revision
+        #[arg(long, alias = "staged")]
+        cached: bool,
+        /// one revision to compare with the work tree (or the index with
+        /// --cached), or two, also written A..B, to compare with each other
+        #[arg(num_args = 0..=2)]
+        revs: Vec<String>,
+        /// only show changes to these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        diff: DiffArgs,
     },
     Merge {
+        #[arg(short, long, required_unless_present_any = ["continue_merge", "abort"])]
+        branch: Option<String>,
+        /// resolve conflicting changes in favor of our or their side
+        #[arg(short = 'X', long = "strategy-option", value_parser = ["ours", "theirs"])]
+        favor: Option<String>,
+        /// commit a conflicted merge once its conflicts are resolved
+        #[arg(long = "continue", conflicts_with_all = ["branch", "abort"])]
+        continue_merge: bool,
+        /// give up on a conflicted merge and restore the pre-merge state
+        #[arg(long, conflicts_with = "branch")]
+        abort: bool,
+        /// refuse to merge unless HEAD can be fast-forwarded
+        #[arg(long, group = "fast_forward")]
+        ff_only: bool,
+        /// create a merge commit even when HEAD could be fast-forwarded
+        #[arg(long, group = "fast_forward")]
+        no_ff: bool,
+        /// stage the merged result without committing or recording the merge
+        #[arg(long, conflicts_with_all = ["no_ff", "message"])]
+        squash: bool,
+        /// the merge commit message
         #[arg(short, long)]
-        branch: String,
+        message: Option<String>,
+    },
+    /// merge the changes two files made to a common base into the first
+    MergeFile {
+        /// print the result instead of overwriting <current>
+        #[arg(short = 'p', long)]
+        stdout: bool,
+        /// show the base version in conflicts as well
+        #[arg(long, conflicts_with = "zdiff3")]
+        diff3: bool,
+        /// like --diff3, keeping lines both sides agree on out of the conflict
+        #[arg(long)]
+        zdiff3: bool,
+        /// resolve conflicts with the current version
+        #[arg(long, group = "favor")]
+        ours: bool,
+        /// resolve conflicts with the other version
+        #[arg(long, group = "favor")]
+        theirs: bool,
+        /// resolve conflicts with both versions, current first
+        #[arg(long, group = "favor")]
+        union: bool,
+        /// myers, patience or histogram
+        #[arg(long, value_name = "algorithm")]
+        diff_algorithm: Option<String>,
+        /// names for the current, base and other version in conflict markers
+        #[arg(short = 'L', value_name = "label", num_args = 1, action = ArgAction::Append)]
+        labels: Vec<String>,
+        current: PathBuf,
+        base: PathBuf,
+        other: PathBuf,
+    },
+    /// find the best common ancestors of two commits
+    MergeBase {
+        /// print every merge base instead of just one
+        #[arg(short, long)]
+        all: bool,
+        /// exit with 0 if the first commit is an ancestor of the second, 1 if not
+        #[arg(long, conflicts_with = "all")]
+        is_ancestor: bool,
+        #[arg(num_args = 2, required = true)]
+        commits: Vec<String>,
+    },
+    /// print the object ids that revisions name
+    RevParse {
+        /// expect exactly one revision naming an existing object
+        #[arg(long)]
+        verify: bool,
+        /// abbreviate object ids to at least N hex digits
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "7", require_equals = true)]
+        short: Option<usize>,
+        #[arg(required = true, allow_hyphen_values = true)]
+        revs: Vec<String>,
+    },
+    /// get and set repository or global options
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
     },
 }
 
+#[derive(Args)]
+struct DiffArgs {
+    /// show <n> lines of context around each change
+    #[arg(short = 'U', long = "unified", value_name = "n")]
+    unified: Option<usize>,
+    /// myers, patience or histogram
+    #[arg(long, value_name = "algorithm")]
+    diff_algorithm: Option<String>,
+    /// shorthand for --diff-algorithm=patience
+    #[arg(long, conflicts_with_all = ["diff_algorithm", "histogram"])]
+    patience: bool,
+    /// shorthand for --diff-algorithm=histogram
+    #[arg(long, conflicts_with = "diff_algorithm")]
+    histogram: bool,
+    /// detect renames, pairing files at least <n> similar (50% by default)
+    #[arg(
+        short = 'M',
+        long = "find-renames",
+        value_name = "n",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "50%"
+    )]
+    find_renames: Option<String>,
+    /// detect copies as well as renames
+    #[arg(
+        short = 'C',
+        long = "find-copies",
+        value_name = "n",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "50%"
+    )]
+    find_copies: Option<String>,
+    /// turn off rename detection
+    #[arg(long, conflicts_with_all = ["find_renames", "find_copies"])]
+    no_renames: bool,
+    /// show a histogram of changed lines per file instead of a patch
+    #[arg(long, group = "summary")]
+    stat: bool,
+    /// show added and deleted line counts per file, tab separated
+    #[arg(long, group = "summary")]
+    numstat: bool,
+    /// only show the totals line of --stat
+    #[arg(long, group = "summary")]
+    shortstat: bool,
+    /// only show the names of changed files
+    #[arg(long, group = "summary")]
+    name_only: bool,
+    /// show the names and status letters of changed files
+    #[arg(long, group = "summary")]
+    name_status: bool,
+}
+
+impl DiffArgs {
+    /// Options from the command line, falling back to `diff.context` and
+    /// `diff.algorithm`.
+    fn options(&self, repo: &Repository) -> rgit::Result<xdiff::Options> {
+        let config = repo.config()?;
+        let algorithm = if self.patience {
+            Some("patience")
+        } else if self.histogram {
+            Some("histogram")
+        } else {
+            self.diff_algorithm
+                .as_deref()
+                .or(config.get("diff.algorithm"))
+        };
+
+        let mut options = xdiff::Options::default();
+        if let Some(algorithm) = algorithm {
+            options.algorithm = algorithm.parse()?;
+        }
+        match self.unified {
+            Some(context) => options.context = context,
+            None => {
+                if let Some(context) = config.get_int("diff.context")? {
+                    options.context = context.max(0) as usize;
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn summary(&self) -> Option<diff::Summary> {
+        [
+            (self.stat, diff::Summary::Stat),
+            (self.numstat, diff::Summary::Numstat),
+            (self.shortstat, diff::Summary::Shortstat),
+            (self.name_only, diff::Summary::NameOnly),
+            (self.name_status, diff::Summary::NameStatus),
+        ]
+        .into_iter()
+        .find_map(|(set, summary)| set.then_some(summary))
+    }
+
+    /// Prints `changes` as a patch, or in the short form asked for.
+    fn print(&self, repo: &Repository, changes: &[diff::TreeChange]) -> rgit::Result<()> {
+        let options = self.options(repo)?;
+        match self.summary() {
+            Some(summary) => base::print_summary(repo, changes, summary, &options),
+            None => base::print_commit(repo, changes, &options),
+        }
+    }
+
+    /// Rename detection from the command line, falling back to `diff.renames`,
+    /// which is a boolean or `copies`. Renames are detected by default.
+    fn renames(&self, repo: &Repository) -> rgit::Result<Renames> {
+        if self.no_renames {
+            return Ok(Renames::OFF);
+        }
+        if let Some(score) = &self.find_copies {
+            return Ok(Renames {
+                threshold: Some(diff::parse_score(score)?),
+                copies: true,
+            });
+        }
+        if let Some(score) = &self.find_renames {
+            return Ok(Renames {
+                threshold: Some(diff::parse_score(score)?),
+                copies: false,
+            });
+        }
+
+        let config = repo.config()?;
+        Ok(match config.get("diff.renames") {
+            Some("copies" | "copy") => Renames {
+                copies: true,
+                ..Renames::default()
+            },
+            Some(_) if config.get_bool("diff.renames")? == Some(false) => Renames::OFF,
+            _ => Renames::default(),
+        })
+    }
+}
+
+#[derive(Args)]
+struct WalkArgs {
+    /// leave out the commits reachable from these revisions
+    #[arg(long = "not", value_name = "rev", num_args = 1..)]
+    not: Vec<String>,
+    /// stop after <n> commits
+    #[arg(short = 'n', long, value_name = "n")]
+    max_count: Option<usize>,
+    /// leave out the first <n> commits
+    #[arg(long, value_name = "n", default_value_t = 0)]
+    skip: usize,
+    /// only follow the first parent of merges
+    #[arg(long)]
+    first_parent: bool,
+    /// only show commits descending from the excluded ones
+    #[arg(long)]
+    ancestry_path: bool,
+    /// show no parent before its children, keeping branches together
+    #[arg(long, group = "order")]
+    topo_order: bool,
+    /// show no parent before its children, otherwise by commit date
+    #[arg(long, group = "order")]
+    date_order: bool,
+    /// show the oldest commits first
+    #[arg(long)]
+    reverse: bool,
+    /// only show commits made after a date such as 2024-05-01 or "2 weeks ago"
+    #[arg(long, visible_alias = "after", value_name = "date")]
+    since: Option<String>,
+    /// only show commits made before a date
+    #[arg(long, visible_alias = "before", value_name = "date")]
+    until: Option<String>,
+    /// only show commits whose author matches a regex
+    #[arg(long, value_name = "pattern")]
+    author: Vec<String>,
+    /// only show commits whose message matches a regex
+    #[arg(long, value_name = "pattern")]
+    grep: Vec<String>,
+    /// match --author and --grep regardless of case
+    #[arg(short = 'i', long)]
+    regexp_ignore_case: bool,
+    /// only show commits changing how many times a string occurs in a file
+    #[arg(short = 'S', value_name = "string")]
+    pickaxe_string: Option<String>,
+    /// only show commits adding or removing lines that match a regex
+    #[arg(short = 'G', value_name = "regex", conflicts_with = "pickaxe_string")]
+    pickaxe_regex: Option<String>,
+}
+
+impl WalkArgs {
+    /// The commits `revs` and `--not` start the walk from.
+    fn tips(&self, repo: &Repository, revs: &[String]) -> rgit::Result<Vec<revision::Tip>> {
+        let mut args = revs.to_vec();
+        if !self.not.is_empty() {
+            args.push(String::from("--not"));
+            args.extend(self.not.iter().cloned());
+        }
+        revision::parse_revisions(repo, &args)
+    }
+
+    /// The walk options, keeping only commits that changed one of `paths`
+    /// unless it is empty.
+    fn options(&self, paths: &[String]) -> rgit::Result<walk::WalkOptions> {
+        let regex = |pattern: &String| {
+            regex::RegexBuilder::new(pattern)
+                .case_insensitive(self.regexp_ignore_case)
+                .build()
+                .map_err(|e| Error::InvalidArgument(e.to_string()))
+        };
+        let pickaxe = match (&self.pickaxe_string, &self.pickaxe_regex) {
+            (Some(string), _) => Some(diff::Pickaxe::Count(string.clone())),
+            (_, Some(pattern)) => Some(diff::Pickaxe::Lines(
+                regex::bytes::Regex::new(pattern)
+                    .map_err(|e| Error::InvalidArgument(e.to_string()))?,
+            )),
+            (None, None) => None,
+        };
+
+        Ok(walk::WalkOptions {
+            order: if self.topo_order {
+                walk::Order::Topo
+            } else if self.date_order {
+                walk::Order::Date
+            } else {
+                walk::Order::Walk
+            },
+            reverse: self.reverse,
+            max_count: self.max_count,
+            skip: self.skip,
+            first_parent: self.first_parent,
+            ancestry_path: self.ancestry_path,
+            paths: paths.to_vec(),
+            follow: None,
+            since: self.since.as_deref().map(date::parse_approx).transpose()?,
+            until: self.until.as_deref().map(date::parse_approx).transpose()?,
+            author: self.author.iter().map(regex).collect::<rgit::Result<_>>()?,
+            grep: self.grep.iter().map(regex).collect::<rgit::Result<_>>()?,
+            pickaxe,
+        })
+    }
+}
+
+#[derive(Args)]
+struct FormatArgs {
+    /// shorthand for --pretty=oneline
+    #[arg(long, group = "style")]
+    oneline: bool,
+    /// oneline, short, medium, full, raw, or format:<template>
+    #[arg(
+        long,
+        value_name = "format",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "medium",
+        group = "style"
+    )]
+    pretty: Option<String>,
+    /// a named format, or a template of placeholders such as %h and %s
+    #[arg(long, value_name = "format", group = "style")]
+    format: Option<String>,
+    /// show the refs pointing at each commit: short, full, auto or no
+    #[arg(
+        long,
+        value_name = "style",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "short"
+    )]
+    decorate: Option<String>,
+    /// don't show the refs pointing at each commit
+    #[arg(long, conflicts_with = "decorate")]
+    no_decorate: bool,
+    /// name commits by abbreviated oids; implied by --oneline
+    #[arg(long)]
+    abbrev_commit: bool,
+    /// name commits by full oids, even with --oneline
+    #[arg(long, conflicts_with = "abbrev_commit")]
+    no_abbrev_commit: bool,
+    /// draw the history graph next to the commits
+    #[arg(long, conflicts_with = "reverse")]
+    graph: bool,
+}
+
+impl FormatArgs {
+    fn format(&self) -> rgit::Result<pretty::Format> {
+        match (&self.pretty, &self.format) {
+            _ if self.oneline => Ok(pretty::Format::Oneline),
+            (Some(format), _) | (_, Some(format)) => format.parse(),
+            (None, None) => Ok(pretty::Format::default()),
+        }
+    }
+
+    /// How refs are named, falling back to `log.decorate`. By default they
+    /// are only shown when writing to a terminal.
+    fn decorate(&self, repo: &Repository) -> rgit::Result<Option<pretty::Decorate>> {
+        let config = repo.config()?;
+        let value = match (&self.decorate, self.no_decorate) {
+            (_, true) => "no",
+            (Some(value), false) => value.as_str(),
+            (None, false) => config.get("log.decorate").unwrap_or("auto"),
+        };
+
+        Ok(match value {
+            "short" => Some(pretty::Decorate::Short),
+            "full" => Some(pretty::Decorate::Full),
+            "auto" => stdout().is_terminal().then_some(pretty::Decorate::Short),
+            _ => match config::parse_bool(value) {
+                Some(true) => Some(pretty::Decorate::Short),
+                Some(false) => None,
+                None => {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid --decorate option: {value}"
+                    )))
+                }
+            },
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// print the value of a key; exits with 1 if it is not set
+    Get {
+        /// print every value of a multi-valued key
+        #[arg(long)]
+        all: bool,
+        /// check and normalize the value as a bool, int or path
+        #[arg(long = "type", value_parser = ["bool", "int", "path"])]
+        kind: Option<String>,
+        #[command(flatten)]
+        scope: ConfigScope,
+        key: String,
+    },
+    /// set a key, in the repository config unless another level is given
+    Set {
+        /// add a value to a multi-valued key instead of replacing it
+        #[arg(long)]
+        add: bool,
+        #[command(flatten)]
+        scope: ConfigScope,
+        key: String,
+        value: String,
+    },
+    /// remove a key; exits with 5 if it is not set
+    Unset {
+        /// remove every value of a multi-valued key
+        #[arg(long)]
+        all: bool,
+        #[command(flatten)]
+        scope: ConfigScope,
+        key: String,
+    },
+    /// print every setting
+    List {
+        /// print the file each setting comes from
+        #[arg(long)]
+        show_origin: bool,
+        #[command(flatten)]
+        scope: ConfigScope,
+    },
+}
+
+#[derive(Args)]
+struct ConfigScope {
+    /// use ~/.rgitconfig
+    #[arg(long, conflicts_with_all = ["system", "local"])]
+    global: bool,
+    /// use the system-wide config file
+    #[arg(long, conflicts_with = "local")]
+    system: bool,
+    /// use the repository config file
+    #[arg(long)]
+    local: bool,
+}
+
+impl ConfigScope {
+    fn level(&self) -> Option<Level> {
+        if self.global {
+            Some(Level::Global)
+        } else if self.system {
+            Some(Level::System)
+        } else if self.local {
+            Some(Level::Local)
+        } else {
+            None
+        }
+    }
+}
+
 fn main() {
-    let cli = Cli::parse();
+    let cli = Cli::parse_from(env::args().map(attach_score));
 
     // You can check the value provided by positional arguments, or option arguments
     if let Some(name) = cli.name.as_deref() {
         println!("Value for name: {name}");
     }
 
-    match &cli.command {
-        Some(Commands::Init) => {
-            if File::open(data::GIT_DIR).is_ok() {
+    if let Err(e) = run(&cli) {
+        if let Error::MergeConflict = e {
+            println!("{e}");
+            process::exit(1);
+        }
+        eprintln!("fatal: {e}");
+        process::exit(128);
+    }
+}
+
+// git accepts `-M90%` as well as `-M=90%`; clap only the latter
+fn attach_score(arg: String) -> String {
+    match arg.strip_prefix("-M").or_else(|| arg.strip_prefix("-C")) {
+        Some(score) if score.starts_with(|c: char| c.is_ascii_digit()) => {
+            format!("{}={score}", &arg[..2])
+        }
+        _ => arg,
+    }
+}
+
+fn run(cli: &Cli) -> rgit::Result<()> {
+    let Some(command) = &cli.command else {
+        return Ok(());
+    };
+
+    if let Commands::Init { directory, bare } = command {
+        let directory = directory.clone().unwrap_or_else(|| PathBuf::from("."));
+        let repo = if *bare {
+            Repository::init_bare(directory)
+        } else {
+            fs::create_dir_all(&directory)?;
+            Repository::init(directory)
+        };
+
+        match repo {
+            Ok(repo) => {
+                let path = repo.git_dir().display();
+                println!("Initialized empty rgit repository in {path}");
+            }
+            Err(Error::AlreadyInitialized(_)) => {
                 println!("Already initialized rgit repository! Please don't again.");
-                return;
             }
+            Err(e) => return Err(e),
+        }
+        return Ok(());
+    }
 
-            if base::init().is_ok() {
-                let mut rgit_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-                rgit_path.push(data::GIT_DIR);
-                println!("Initialized empty rgit repository in {:#?}", rgit_path);
-            }
+    // global and system settings can be used outside of a repository
+    if let Commands::Config { action } = command {
+        return run_config(open_repository(cli).ok().as_ref(), action);
+    }
+    if let Commands::MergeFile { .. } = command {
+        return run_merge_file(open_repository(cli).ok().as_ref(), command);
+    }
+
+    let repo = open_repository(cli)?;
+
+    match command {
+        Commands::Init { .. } | Commands::Config { .. } | Commands::MergeFile { .. } => {
+            unreachable!("handled above")
         }
-        Some(Commands::HashObject { filename }) => {
-            let mut file = File::open(filename).expect("input file not exist");
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).unwrap();
-            let oid = data::hash_object(&contents, "blob");
+        Commands::HashObject { filename } => {
+            let contents = fs::read(filename)?;
+            let oid = repo.hash_object(&contents)?;
             println!("{oid}");
         }
-        Some(Commands::CatFile { object }) => {
-            let object = base::get_oid(object);
-            let out_str = data::get_object(&object, None);
-            stdout().flush().unwrap();
-            print!("{out_str}");
+        Commands::CatFile { object } => {
+            let object = repo.rev_parse(object)?;
+            match data::read_object(&repo, &object)? {
+                Object::Tree(tree) => {
+                    for e in tree.entries {
+                        let kind = if e.is_tree() { "tree" } else { "blob" };
+                        println!("{:06o} {kind} {}\t{}", e.mode, e.oid, e.name);
+                    }
+                }
+                other => {
+                    stdout().write_all(&other.serialize()?)?;
+                    stdout().flush()?;
+                }
+            }
         }
-        Some(Commands::WriteTree) => {
-            let oid = base::write_tree();
+        Commands::WriteTree => {
+            let oid = repo.write_tree()?;
             println!("{oid}");
         }
-        Some(Commands::ReadTree { tree }) => {
-            let tree = base::get_oid(tree);
-            base::read_tree(&tree)
+        Commands::ReadTree { tree } => {
+            let tree = repo.rev_parse(tree)?;
+            base::read_tree(&repo, &tree)?;
         }
-        Some(Commands::Commit { message }) => {
-            let commit_oid = base::commit(message);
+        Commands::Commit {
+            message,
+            author,
+            date,
+        } => {
+            let message = match message {
+                Some(message) => message.clone(),
+                None => base::squash_msg(&repo)?.ok_or_else(|| {
+                    Error::InvalidArgument(String::from("no commit message given; use -m"))
+                })?,
+            };
+            let commit_oid = base::commit(&repo, &message, author.as_deref(), date.as_deref())?;
             println!("{commit_oid}");
         }
-        Some(Commands::Log { oid }) => match oid {
-            Some(oid) => {
-                let oid = base::get_oid(oid);
-                base::get_commit(&oid);
+        Commands::Log {
+            revs,
+            follow,
+            paths,
+            format,
+            diff: diff_args,
+            walk: walk_args,
+        } => {
+            let revs = match &revs[..] {
+                [] if walk_args.not.is_empty() => vec![String::from("HEAD")],
+                revs => revs.to_vec(),
+            };
+            let tips = walk_args.tips(&repo, &revs)?;
+            let paths = relative_paths(&repo, paths)?;
+            let options = base::LogOptions {
+                format: format.format()?,
+                decorate: format.decorate(&repo)?,
+                graph: format.graph,
+                abbrev_commit: (format.abbrev_commit || format.oneline) && !format.no_abbrev_commit,
+                summary: diff_args.summary(),
+                paths: match follow {
+                    true => vec![],
+                    false => paths.clone(),
+                },
+                renames: diff_args.renames(&repo)?,
+                diff: diff_args.options(&repo)?,
+            };
+            if *follow && paths.len() != 1 {
+                return Err(Error::InvalidArgument(String::from(
+                    "--follow requires exactly one path",
+                )));
             }
-            None => {
-                let oid = data::get_ref("HEAD", true).value.unwrap();
-                base::get_commit(&oid);
-            }
-        },
-        Some(Commands::Checkout { commit }) => {
-            base::checkout(commit);
-        }
-        Some(Commands::Tag { name, oid }) => match oid {
-            Some(oid) => {
-                let oid = base::get_oid(oid);
-                base::create_tag(name, &oid);
+            let mut walk_options = walk_args.options(&paths)?;
+            if *follow {
+                walk_options.follow = Some(options.renames);
             }
-            None => {
-                let oid = data::get_ref("HEAD", true).value.unwrap();
-                base::create_tag(name, &oid);
+            // the graph needs every child shown before its parents
+            if format.graph && walk_options.order == walk::Order::Walk {
+                walk_options.order = walk::Order::Topo;
             }
-        },
-        Some(Commands::K) => {
-            let mut sides = HashSet::new();
-            let mut dot = String::from("digraph commits {\n");
-            let entries = data::iter_refs();
-
-            for (f, oids) in entries {
-                for oid in oids.windows(2) {
-                    let arrow_oid = format!("  {} -> {}\n", oid[0], oid[1]);
-                    if sides.insert(arrow_oid.clone()) {
-                        dot.push_str(&arrow_oid);
-                    }
-                }
-                let f_arrow = format!("  {f} -> {}\n", oids[0]);
-                dot.push_str(&f_arrow);
-                let f_note = format!("  {f} [shape=note]\n");
-                dot.push_str(&f_note);
+            base::log(
+                &repo,
+                &walk::commits(&repo, &tips, &walk_options)?,
+                &options,
+            )?
+        }
+        Commands::RevList {
+            revs,
+            paths,
+            walk: walk_args,
+        } => {
+            let tips = walk_args.tips(&repo, revs)?;
+            let options = walk_args.options(&relative_paths(&repo, paths)?)?;
+            for oid in walk::commits(&repo, &tips, &options)?.commits {
+                println!("{oid}");
             }
-            dot.push('}');
-
-            let mut child = Command::new("dot")
-                .arg("-Tjpeg")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("failed to spawn child process");
-
-            if let Some(mut stdin) = child.stdin.take() {
-                stdin.write_all(dot.as_bytes()).unwrap();
+        }
+        Commands::Checkout { commit } => {
+            repo.checkout(commit)?;
+        }
+        Commands::Tag { name, oid } => {
+            repo.tag(name, oid.as_deref())?;
+        }
+        Commands::K { format, output } => {
+            let graph = export::History::load(&repo)?.render(format.parse()?);
+            match output {
+                Some(path) => fs::write(path, graph)?,
+                None => print!("{graph}"),
             }
-
-            Command::new("open")
-                .args(["-a", "Preview.app", "-f"])
-                .stdin(Stdio::from(child.stdout.unwrap()))
-                .spawn()
-                .unwrap();
         }
-        Some(Commands::Branch { name, start_point }) => match name {
-            Some(name) => match start_point {
-                Some(sp) => {
-                    let oid = base::get_oid(sp);
-                    base::create_branch(name, &oid);
-                    println!("Branch {name} created at {:?}", &sp[0..10]);
-                }
-                None => {
-                    let oid = data::get_ref("HEAD", true).value.unwrap();
-                    base::create_branch(name, &oid);
-                    println!("Branch {name} created at {:?}", &oid[0..10]);
-                }
-            },
-            None => match base::get_status_name() {
+        Commands::Branch { name, start_point } => match name {
+            Some(name) => {
+                let oid = repo.branch(name, start_point.as_deref())?;
+                println!("Branch {name} created at {:?}", &oid[0..10]);
+            }
+            None => match base::get_status_name(&repo)? {
                 Some(branch_name) => {
-                    for name in base::iter_branch_names() {
+                    for name in base::iter_branch_names(&repo)? {
                         if name == branch_name {
                             println!("*{branch_name}");
                         } else {
@@ -204,52 +838,480 @@ fn main() {
                     }
                 }
                 None => {
-                    let head = base::get_oid("@");
+                    let head = repo.rev_parse("@")?;
                     println!("HEAD detached at{}", &head[0..10]);
                 }
             },
         },
-        Some(Commands::Status) => {
-            let head = base::get_oid("@");
-            let branch = base::get_status_name();
-            match branch {
-                Some(branch_name) => println!("On branch {}", branch_name),
-                None => println!("HEAD detached at{}", &head[0..10]),
+        Commands::Status => {
+            match base::get_status_name(&repo)? {
+                Some(branch_name) => println!("On branch {branch_name}"),
+                None => {
+                    let head = repo.rev_parse("@")?;
+                    println!("HEAD detached at {}", &head[0..10]);
+                }
             }
 
-            let oid = data::get_ref("HEAD", true).value.unwrap();
-            diff::get_working_tree_diff_simple_version(&oid);
+            let status = repo.status()?;
+            if base::merge_head(&repo)?.is_some() {
+                if status.unmerged.is_empty() {
+                    println!("All conflicts fixed but you are still merging.");
+                } else {
+                    println!("You have unmerged paths.");
+                }
+            }
+            print_status(&status);
         }
-        Some(Commands::Reset { commit }) => {
-            base::reset(commit);
+        Commands::Add { force, paths } => {
+            base::add(&repo, &relative_paths(&repo, paths)?, *force)?;
         }
-        Some(Commands::Show { oid }) => match oid {
-            Some(oid) => {
-                let oid = base::get_oid(oid);
-                let modi_contents = diff::compare_trees(&oid);
-                base::print_commit(&modi_contents);
+        Commands::CheckIgnore { verbose, paths } => {
+            let index = Index::read(&repo)?;
+            let mut ignore = Ignore::new(&repo)?;
+            let mut any_ignored = false;
+
+            for (arg, path) in paths.iter().zip(relative_paths(&repo, paths)?) {
+                // tracked files are never subject to ignore rules
+                if index.contains(&path) {
+                    continue;
+                }
+
+                let is_dir = repo.work_tree()?.join(&path).is_dir();
+                let Some(pattern) = ignore.check(&path, is_dir)? else {
+                    continue;
+                };
+                if *verbose {
+                    let (source, line, text) = (&pattern.source, pattern.line, &pattern.text);
+                    println!("{source}:{line}:{text}\t{}", arg.display());
+                } else if !pattern.negated {
+                    println!("{}", arg.display());
+                }
+                any_ignored |= !pattern.negated;
             }
-            None => {
-                let oid = data::get_ref("HEAD", true).value.unwrap();
-                let modi_contents = diff::compare_trees(&oid);
-                base::print_commit(&modi_contents);
+
+            if !any_ignored {
+                process::exit(1);
             }
-        },
-        Some(Commands::Diff { oid }) => match oid {
-            Some(oid) => {
-                let oid = base::get_oid(oid);
-                let modi_contents = diff::get_working_tree_diff(&oid);
-                base::print_commit(&modi_contents);
+        }
+        Commands::Rm { cached, paths } => {
+            base::remove(&repo, &relative_paths(&repo, paths)?, *cached)?;
+        }
+        Commands::Restore { staged, paths } => {
+            base::restore(&repo, &relative_paths(&repo, paths)?, *staged)?;
+        }
+        Commands::Reset { commit } => {
+            let oid = repo.rev_parse(commit)?;
+            base::reset(&repo, &oid)?;
+        }
+        Commands::Show {
+            oid,
+            diff: diff_args,
+        } => {
+            let oid = repo.rev_parse(oid.as_deref().unwrap_or("HEAD"))?;
+            let commit = data::get_commit(&repo, &oid)?;
+            let format = pretty::Format::default();
+            println!(
+                "{}",
+                pretty::format_commit(&repo, &oid, &commit, &format, None, false)?
+            );
+
+            let modi_contents = diff::compare_trees(&repo, &oid, &[], &diff_args.renames(&repo)?)?;
+            diff_args.print(&repo, &modi_contents)?;
+        }
+        Commands::Diff {
+            cached,
+            revs,
+            paths,
+            diff: diff_args,
+        } => {
+            let (old, new) = diff_sources(&repo, *cached, revs)?;
+            let paths = relative_paths(&repo, paths)?;
+            let renames = diff_args.renames(&repo)?;
+            let changes = diff::diff_sources(&repo, &old, &new, &paths, &renames)?;
+            diff_args.print(&repo, &changes)?;
+        }
+        Commands::Merge {
+            branch,
+            favor,
+            continue_merge,
+            abort,
+            ff_only,
+            no_ff,
+            squash,
+            message,
+        } => {
+            if *continue_merge {
+                base::continue_merge(&repo)?;
+            } else if *abort {
+                base::abort_merge(&repo)?;
+            } else if let Some(branch) = branch {
+                let options = base::MergeOptions {
+                    favor: favor.as_deref().map(str::parse).transpose()?,
+                    fast_forward: if *ff_only {
+                        Some(base::FastForward::Only)
+                    } else if *no_ff {
+                        Some(base::FastForward::Never)
+                    } else {
+                        None
+                    },
+                    squash: *squash,
+                    message: message.clone(),
+                };
+                match base::read_tree_merged(&repo, "HEAD", branch, &options)? {
+                    base::MergeOutcome::UpToDate => println!("Already up to date."),
+                    base::MergeOutcome::FastForward(old, new) => {
+                        println!("Updating {}..{}", &old[..7], &new[..7]);
+                        println!("Fast-forward");
+                    }
+                    base::MergeOutcome::Squashed => {
+                        println!("Squash commit -- not updating HEAD")
+                    }
+                    base::MergeOutcome::Merged(_) => {
+                        println!("Merge made by the 'recursive' strategy.")
+                    }
+                }
             }
-            None => {
-                let oid = data::get_ref("HEAD", true).value.unwrap();
-                let modi_contents = diff::get_working_tree_diff(&oid);
-                base::print_commit(&modi_contents);
+        }
+        Commands::RevParse {
+            verify,
+            short,
+            revs,
+        } => {
+            let tips = if *verify {
+                let [rev] = &revs[..] else {
+                    return Err(Error::InvalidArgument(String::from(
+                        "Needed a single revision",
+                    )));
+                };
+                let oid = base::get_oid(&repo, rev)?;
+                if !data::has_object(&repo, &oid) {
+                    return Err(Error::ObjectNotFound(oid));
+                }
+                vec![revision::Tip {
+                    oid,
+                    excluded: false,
+                }]
+            } else {
+                let mut tips = vec![];
+                for rev in revs {
+                    tips.extend(revision::parse_range(&repo, rev)?);
+                }
+                tips
+            };
+
+            for tip in tips {
+                let oid = match short {
+                    Some(len) => revision::abbreviate(&repo, &tip.oid, *len)?,
+                    None => tip.oid,
+                };
+                let prefix = if tip.excluded { "^" } else { "" };
+                println!("{prefix}{oid}");
+            }
+        }
+        Commands::MergeBase {
+            all,
+            is_ancestor,
+            commits,
+        } => {
+            let ours = repo.rev_parse(&commits[0])?;
+            let theirs = repo.rev_parse(&commits[1])?;
+            if *is_ancestor {
+                let code = if merge::is_ancestor(&repo, &ours, &theirs)? {
+                    0
+                } else {
+                    1
+                };
+                process::exit(code);
             }
+
+            let bases = merge::merge_bases(&repo, &ours, &theirs)?;
+            if bases.is_empty() {
+                process::exit(1);
+            }
+            let count = if *all { bases.len() } else { 1 };
+            for base in &bases[..count] {
+                println!("{base}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn open_repository(cli: &Cli) -> rgit::Result<Repository> {
+    let repo = match &cli.git_dir {
+        Some(git_dir) => Repository::open_git_dir(git_dir)?,
+        None => Repository::discover(env::current_dir()?)?,
+    };
+    match &cli.work_tree {
+        Some(work_tree) => repo.with_work_tree(work_tree),
+        None => Ok(repo),
+    }
+}
+
+fn run_config(repo: Option<&Repository>, action: &ConfigAction) -> rgit::Result<()> {
+    // writes go to the repository config unless told otherwise
+    let file_for = |level: Option<Level>| -> rgit::Result<ConfigFile> {
+        let level = level.unwrap_or(Level::Local);
+        match level.path(repo) {
+            Some(path) => ConfigFile::read(&path),
+            None if level == Level::Local => Err(Error::NotARepository(env::current_dir()?)),
+            None => Err(Error::BadConfig(format!("no {level} config file"))),
+        }
+    };
+    let load = |level: Option<Level>| match level {
+        Some(level) => Config::load_level(repo, level),
+        None => Config::load(repo),
+    };
+
+    match action {
+        ConfigAction::Get {
+            all,
+            kind,
+            scope,
+            key,
+        } => {
+            let config = load(scope.level())?;
+            let values = if *all {
+                config.get_all(key)
+            } else {
+                config.get(key).into_iter().collect()
+            };
+            if values.is_empty() {
+                process::exit(1);
+            }
+
+            for value in values {
+                let bad = || Error::BadConfig(format!("bad value '{value}' for '{key}'"));
+                match kind.as_deref() {
+                    Some("bool") => println!("{}", config::parse_bool(value).ok_or_else(bad)?),
+                    Some("int") => println!("{}", config::parse_int(value).ok_or_else(bad)?),
+                    Some("path") => println!("{}", config::expand_path(value).display()),
+                    _ => println!("{value}"),
+                }
+            }
+        }
+        ConfigAction::Set {
+            add,
+            scope,
+            key,
+            value,
+        } => {
+            let mut file = file_for(scope.level())?;
+            file.set(key, value, *add)?;
+            file.write()?;
+        }
+        ConfigAction::Unset { all, scope, key } => {
+            let mut file = file_for(scope.level())?;
+            if !file.unset(key, *all)? {
+                process::exit(5);
+            }
+            file.write()?;
+        }
+        ConfigAction::List { show_origin, scope } => {
+            for entry in load(scope.level())?.entries() {
+                if *show_origin {
+                    print!("file:{}\t", entry.origin.display());
+                }
+                match &entry.value {
+                    Some(value) => println!("{}={value}", entry.key),
+                    None => println!("{}", entry.key),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Exits with the number of conflicts left in the merged file.
+fn run_merge_file(repo: Option<&Repository>, command: &Commands) -> rgit::Result<()> {
+    let Commands::MergeFile {
+        stdout: to_stdout,
+        diff3,
+        zdiff3,
+        ours,
+        theirs,
+        union,
+        diff_algorithm,
+        labels,
+        current,
+        base,
+        other,
+    } = command
+    else {
+        unreachable!("only called for merge-file")
+    };
+    if labels.len() > 3 {
+        return Err(Error::InvalidArgument(String::from(
+            "too many labels, expected at most three",
+        )));
+    }
+
+    let config = Config::load(repo)?;
+    let style = if *diff3 {
+        xdiff::ConflictStyle::Diff3
+    } else if *zdiff3 {
+        xdiff::ConflictStyle::Zdiff3
+    } else {
+        match config.get("merge.conflictStyle") {
+            Some(style) => style.parse()?,
+            None => xdiff::ConflictStyle::Merge,
+        }
+    };
+    let favor = [
+        (*ours, xdiff::Favor::Ours),
+        (*theirs, xdiff::Favor::Theirs),
+        (*union, xdiff::Favor::Union),
+    ]
+    .into_iter()
+    .find_map(|(set, favor)| set.then_some(favor));
+    let algorithm = match diff_algorithm {
+        Some(algorithm) => algorithm.parse()?,
+        None => xdiff::Algorithm::default(),
+    };
+    // files are named after their paths unless labeled
+    let label = |i: usize, path: &PathBuf| match labels.get(i) {
+        Some(label) => label.clone(),
+        None => path.display().to_string(),
+    };
+    let options = xdiff::MergeOptions {
+        style,
+        favor,
+        algorithm,
+        labels: [label(0, current), label(1, base), label(2, other)],
+    };
+
+    let result = xdiff::merge3(
+        &fs::read(base)?,
+        &fs::read(current)?,
+        &fs::read(other)?,
+        &options,
+    );
+    if *to_stdout {
+        stdout().write_all(&result.content)?;
+        stdout().flush()?;
+    } else {
+        fs::write(current, &result.content)?;
+    }
+
+    process::exit(result.conflicts.min(127) as i32);
+}
+
+/// Picks the two sides `rgit diff` compares from its arguments.
+fn diff_sources(
+    repo: &Repository,
+    cached: bool,
+    revs: &[String],
+) -> rgit::Result<(Source, Source)> {
+    let tree = |rev: &str| diff::rev_tree(repo, rev).map(|tree| Source::Tree(Some(tree)));
+
+    let or_head = |rev: &str| {
+        if rev.is_empty() {
+            String::from("HEAD")
+        } else {
+            rev.to_string()
+        }
+    };
+
+    // "A..B" is the same as "A B", with HEAD standing in for a missing side;
+    // "A...B" shows what B changed since it forked from A
+    let revs = match revs {
+        [range] if range.contains("...") => {
+            let (old, new) = range.split_once("...").unwrap();
+            let (old, new) = (
+                repo.rev_parse(&or_head(old))?,
+                repo.rev_parse(&or_head(new))?,
+            );
+            let Some(base) = merge::merge_bases(repo, &old, &new)?.into_iter().next() else {
+                return Err(Error::InvalidArgument(format!("{range}: no merge base")));
+            };
+            vec![base, new]
+        }
+        [range] if range.contains("..") => {
+            let (old, new) = range.split_once("..").unwrap();
+            vec![or_head(old), or_head(new)]
+        }
+        _ => revs.to_vec(),
+    };
+
+    Ok(match (cached, &revs[..]) {
+        (false, []) => (Source::Index, Source::WorkTree),
+        (false, [rev]) => (tree(rev)?, Source::WorkTree),
+        (true, []) => match repo.head()? {
+            Some(_) => (tree("HEAD")?, Source::Index),
+            None => (Source::Tree(None), Source::Index),
         },
-        Some(Commands::Merge { branch }) => {
-            base::read_tree_merged("HEAD", branch);
+        (true, [rev]) => (tree(rev)?, Source::Index),
+        (false, [old, new]) => (tree(old)?, tree(new)?),
+        (true, [_, _]) => {
+            return Err(Error::InvalidArgument(String::from(
+                "--cached compares the index with one revision, not two",
+            )))
+        }
+        (_, _) => {
+            return Err(Error::InvalidArgument(String::from(
+                "diff compares at most two revisions",
+            )))
+        }
+    })
+}
+
+fn relative_paths(repo: &Repository, paths: &[PathBuf]) -> rgit::Result<Vec<String>> {
+    paths.iter().map(|p| repo.relative_path(p)).collect()
+}
+
+fn print_status(status: &diff::Status) {
+    let label = |change: &FileChange| match change {
+        FileChange::Added => "new file:   ",
+        FileChange::Modified => "modified:   ",
+        FileChange::Deleted => "deleted:    ",
+        FileChange::Renamed => "renamed:    ",
+        FileChange::Copied => "copied:     ",
+    };
+
+    if !status.staged.is_empty() {
+        println!("\nChanges to be committed:");
+        for (change, path) in &status.staged {
+            println!("\t{}{path}", label(change));
+        }
+    }
+
+    if !status.unmerged.is_empty() {
+        println!("\nUnmerged paths:");
+        for (unmerged, path) in &status.unmerged {
+            let label = match unmerged {
+                Unmerged::BothModified => "both modified:   ",
+                Unmerged::BothAdded => "both added:      ",
+                Unmerged::BothDeleted => "both deleted:    ",
+                Unmerged::AddedByUs => "added by us:     ",
+                Unmerged::AddedByThem => "added by them:   ",
+                Unmerged::DeletedByUs => "deleted by us:   ",
+                Unmerged::DeletedByThem => "deleted by them: ",
+            };
+            println!("\t{label}{path}");
+        }
+    }
+
+    if !status.unstaged.is_empty() {
+        println!("\nChanges not staged for commit:");
+        for (change, path) in &status.unstaged {
+            println!("\t{}{path}", label(change));
+        }
+    }
+
+    if !status.untracked.is_empty() {
+        println!("\nUntracked files:");
+        for path in &status.untracked {
+            println!("\t{path}");
+        }
+    }
+
+    if status.staged.is_empty() && status.unmerged.is_empty() && status.unstaged.is_empty() {
+        if status.untracked.is_empty() {
+            println!("\nnothing to commit, working tree clean");
+        } else {
+            println!("\nnothing added to commit but untracked files present");
         }
-        None => {}
     }
 }
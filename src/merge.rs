@@ -0,0 +1,478 @@
+//! Merge bases and three-way merges of trees.
+
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::fmt;
+
+use crate::base::{self, TreeFiles};
+use crate::diff::{self, FileChange, Renames};
+use crate::object::MODE_SYMLINK;
+use crate::xdiff::{self, Favor, MergeOptions};
+use crate::{data, Repository, Result};
+
+// which side of a merge reached a commit while looking for merge bases
+const OURS: u8 = 1;
+const THEIRS: u8 = 2;
+const STALE: u8 = 4;
+
+/// The best common ancestors of two commits: those reachable from both that
+/// are not ancestors of another such commit. Usually there is only one, but
+/// criss-cross merges can leave several. Newest first.
+pub fn merge_bases(repo: &Repository, ours: &str, theirs: &str) -> Result<Vec<String>> {
+    if ours == theirs {
+        return Ok(vec![ours.to_string()]);
+    }
+
+    // walk both histories newest first, marking commits with the sides that
+    // reach them; the first commits reached from both sides are candidates,
+    // and everything below them is stale
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for (oid, side) in [(ours, OURS), (theirs, THEIRS)] {
+        flags.insert(oid.to_string(), side);
+        queue.push((commit_time(repo, oid)?, oid.to_string()));
+    }
+
+    let mut candidates = vec![];
+    while queue.iter().any(|(_, oid)| flags[oid] & STALE == 0) {
+        let (_, oid) = queue.pop().unwrap();
+        let mut mark = flags[&oid];
+        if mark & (OURS | THEIRS) == OURS | THEIRS {
+            if mark & STALE == 0 {
+                candidates.push(oid.clone());
+            }
+            mark |= STALE;
+            flags.insert(oid.clone(), mark);
+        }
+
+        for parent in data::get_commit(repo, &oid)?.parents {
+            let parent_mark = flags.entry(parent.clone()).or_default();
+            if *parent_mark & mark == mark {
+                continue;
+            }
+            *parent_mark |= mark;
+            queue.push((commit_time(repo, &parent)?, parent));
+        }
+    }
+
+    // a candidate found early can still be an ancestor of a later one
+    let mut bases = vec![];
+    for (i, candidate) in candidates.iter().enumerate() {
+        let mut redundant = false;
+        for (j, other) in candidates.iter().enumerate() {
+            if i != j && is_ancestor(repo, candidate, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant {
+            bases.push(candidate.clone());
+        }
+    }
+
+    Ok(bases)
+}
+
+/// Whether `ancestor` is reachable from `descendant`, counting a commit as
+/// its own ancestor. The whole history is searched rather than stopping at
+/// older commits, since commit dates can be skewed or set by hand.
+pub fn is_ancestor(repo: &Repository, ancestor: &str, descendant: &str) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![descendant.to_string()];
+
+    while let Some(oid) = pending.pop() {
+        if oid == ancestor {
+            return Ok(true);
+        }
+        if !seen.insert(oid.clone()) {
+            continue;
+        }
+        pending.extend(data::get_commit(repo, &oid)?.parents);
+    }
+
+    Ok(false)
+}
+
+fn commit_time(repo: &Repository, oid: &str) -> Result<i64> {
+    Ok(base::commit_time(&data::get_commit(repo, oid)?))
+}
+
+/// Why a path could not be merged cleanly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides changed the same lines of a file.
+    Content,
+    /// Both sides added different files at the same path.
+    AddAdd,
+    /// Both sides changed a binary file or a symlink, which cannot be merged
+    /// line by line; our version is kept unless a side is favored.
+    Binary,
+    /// Both sides changed the mode of a file, each in its own way; the
+    /// merged contents are kept with our mode.
+    Mode,
+    /// One side deleted a file the other changed; the changed version is kept.
+    ModifyDelete {
+        deleted_in: String,
+        modified_in: String,
+    },
+    /// One side added a file where the other has a directory; the file is
+    /// moved aside to `moved_to`.
+    FileDirectory { added_in: String, moved_to: String },
+}
+
+/// A path left unmerged, with the `(mode, oid)` it has in the base and on
+/// each side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: String,
+    pub kind: ConflictKind,
+    pub base: Option<(u32, String)>,
+    pub ours: Option<(u32, String)>,
+    pub theirs: Option<(u32, String)>,
+}
+
+/// The `CONFLICT (...)` line git prints for the conflict.
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match &self.kind {
+            ConflictKind::Content | ConflictKind::Binary => {
+                write!(f, "CONFLICT (content): Merge conflict in {path}")
+            }
+            ConflictKind::AddAdd => write!(f, "CONFLICT (add/add): Merge conflict in {path}"),
+            ConflictKind::Mode => write!(f, "CONFLICT (mode): both changed mode of {path}"),
+            ConflictKind::ModifyDelete {
+                deleted_in,
+                modified_in,
+            } => write!(
+                f,
+                "CONFLICT (modify/delete): {path} deleted in {deleted_in} and modified in \
+                 {modified_in}.  Version {modified_in} of {path} left in tree."
+            ),
+            ConflictKind::FileDirectory { added_in, moved_to } => write!(
+                f,
+                "CONFLICT (file/directory): directory in the way of {path} from {added_in}; \
+                 moving it to {moved_to} instead."
+            ),
+        }
+    }
+}
+
+/// The outcome of merging two trees. Conflicted files are part of `tree`
+/// with conflict markers in their contents, or as our version where that
+/// is not possible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeMerge {
+    pub tree: String,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merges the trees of two commits using their merge base. Several merge
+/// bases are first merged into a virtual one, conflicts and all, the way
+/// git's recursive strategy does. The base label of `options` is replaced
+/// with the abbreviated merge base.
+pub fn merge_commits(
+    repo: &Repository,
+    ours: &str,
+    theirs: &str,
+    options: &MergeOptions,
+) -> Result<TreeMerge> {
+    let bases = merge_bases(repo, ours, theirs)?;
+    let base = merged_base_tree(repo, &bases)?;
+    let ours = data::get_commit(repo, ours)?.tree;
+    let theirs = data::get_commit(repo, theirs)?.tree;
+
+    let mut options = options.clone();
+    options.labels[1] = match &bases[..] {
+        [base] => base[..7].to_string(),
+        _ => String::from("merged common ancestors"),
+    };
+    merge_trees(repo, base.as_deref(), &ours, &theirs, &options)
+}
+
+// the tree to use as merge base, or None for unrelated histories
+fn merged_base_tree(repo: &Repository, bases: &[String]) -> Result<Option<String>> {
+    let Some((first, rest)) = bases.split_first() else {
+        return Ok(None);
+    };
+
+    let mut tree = data::get_commit(repo, first)?.tree;
+    for other in rest {
+        let inner = merged_base_tree(repo, &merge_bases(repo, first, other)?)?;
+        let other = data::get_commit(repo, other)?.tree;
+        let options = MergeOptions {
+            labels: [
+                String::from("Temporary merge branch 1"),
+                String::new(),
+                String::from("Temporary merge branch 2"),
+            ],
+            ..MergeOptions::default()
+        };
+        tree = merge_trees(repo, inner.as_deref(), &tree, &other, &options)?.tree;
+    }
+
+    Ok(Some(tree))
+}
+
+/// Three-way merges the trees `ours` and `theirs` against `base`, where
+/// `None` stands for the empty tree. A file changed on one side only takes
+/// that change, a file changed on both is merged line by line, and a file
+/// renamed on one side takes the changes the other side made under its old
+/// name.
+pub fn merge_trees(
+    repo: &Repository,
+    base: Option<&str>,
+    ours: &str,
+    theirs: &str,
+    options: &MergeOptions,
+) -> Result<TreeMerge> {
+    let flatten = |tree: Option<&str>| match tree {
+        Some(tree) => base::flatten_tree(repo, tree),
+        None => Ok(TreeFiles::new()),
+    };
+    let mut base_files = flatten(base)?;
+    let mut ours_files = flatten(Some(ours))?;
+    let mut theirs_files = flatten(Some(theirs))?;
+
+    let our_renames = renamed_paths(repo, &base_files, &ours_files)?;
+    let their_renames = renamed_paths(repo, &base_files, &theirs_files)?;
+    follow_renames(&mut base_files, &mut theirs_files, &our_renames);
+    follow_renames(&mut base_files, &mut ours_files, &their_renames);
+
+    let paths = base_files
+        .keys()
+        .chain(ours_files.keys())
+        .chain(theirs_files.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let mut merged = TreeFiles::new();
+    let mut conflicts = vec![];
+    for path in paths {
+        let sides = (
+            base_files.get(&path),
+            ours_files.get(&path),
+            theirs_files.get(&path),
+        );
+        let (entry, kind) = merge_entry(repo, sides, options)?;
+        if let Some(entry) = entry {
+            merged.insert(path.clone(), entry);
+        }
+        if let Some(kind) = kind {
+            conflicts.push(Conflict {
+                path,
+                kind,
+                base: sides.0.cloned(),
+                ours: sides.1.cloned(),
+                theirs: sides.2.cloned(),
+            });
+        }
+    }
+
+    let labels = (options.labels[0].as_str(), options.labels[2].as_str());
+    move_files_out_of_the_way(&mut merged, &ours_files, labels, &mut conflicts);
+
+    Ok(TreeMerge {
+        tree: base::build_tree(repo, &merged)?,
+        conflicts,
+    })
+}
+
+type Entry = (u32, String);
+
+// the merged entry of one path, if it is kept, and why it conflicts, if it does
+fn merge_entry(
+    repo: &Repository,
+    (base, ours, theirs): (Option<&Entry>, Option<&Entry>, Option<&Entry>),
+    options: &MergeOptions,
+) -> Result<(Option<Entry>, Option<ConflictKind>)> {
+    if ours == theirs || theirs == base {
+        return Ok((ours.cloned(), None));
+    }
+    if ours == base {
+        return Ok((theirs.cloned(), None));
+    }
+
+    let (ours, theirs) = match (ours, theirs) {
+        (Some(ours), Some(theirs)) => (ours, theirs),
+        (Some(kept), None) | (None, Some(kept)) => {
+            let [ours_label, _, theirs_label] = &options.labels;
+            let (deleted_in, modified_in) = if theirs.is_none() {
+                (theirs_label, ours_label)
+            } else {
+                (ours_label, theirs_label)
+            };
+            let kind = ConflictKind::ModifyDelete {
+                deleted_in: deleted_in.to_string(),
+                modified_in: modified_in.to_string(),
+            };
+            return Ok((Some(kept.clone()), Some(kind)));
+        }
+        (None, None) => unreachable!("equal sides are handled above"),
+    };
+
+    // an executable bit flipped on one side survives a content change on
+    // the other, but two different new modes conflict
+    let (mode, mode_conflict) = match base {
+        Some((mode, _)) if *mode == ours.0 => (theirs.0, false),
+        Some((mode, _)) if *mode == theirs.0 => (ours.0, false),
+        _ => (ours.0, ours.0 != theirs.0),
+    };
+    let mode_conflict = mode_conflict.then_some(ConflictKind::Mode);
+    if ours.1 == theirs.1 {
+        return Ok((Some((mode, ours.1.clone())), mode_conflict));
+    }
+
+    let read = |entry: Option<&Entry>| match entry {
+        Some((_, oid)) => data::get_object(repo, oid, Some("blob")),
+        None => Ok(vec![]),
+    };
+    let (base_contents, ours_contents, theirs_contents) =
+        (read(base)?, read(Some(ours))?, read(Some(theirs))?);
+    let unmergeable = [ours.0, theirs.0].contains(&MODE_SYMLINK)
+        || [&base_contents, &ours_contents, &theirs_contents]
+            .iter()
+            .any(|contents| xdiff::is_binary(contents));
+    if unmergeable {
+        return Ok(match options.favor {
+            Some(Favor::Theirs) => (Some(theirs.clone()), None),
+            Some(_) => (Some(ours.clone()), None),
+            None => (Some(ours.clone()), Some(ConflictKind::Binary)),
+        });
+    }
+
+    let result = xdiff::merge3(&base_contents, &ours_contents, &theirs_contents, options);
+    let oid = data::hash_object(repo, &result.content, "blob")?;
+    let kind = match (result.conflicts, base) {
+        (0, _) => mode_conflict,
+        (_, None) => Some(ConflictKind::AddAdd),
+        (_, Some(_)) => Some(ConflictKind::Content),
+    };
+
+    Ok((Some((mode, oid)), kind))
+}
+
+// old path -> new path for every file `side` renamed
+fn renamed_paths(
+    repo: &Repository,
+    base: &TreeFiles,
+    side: &TreeFiles,
+) -> Result<Vec<(String, String)>> {
+    let changes = diff::diff_files(base, side);
+    Ok(diff::detect_renames(repo, changes, &Renames::default())?
+        .into_iter()
+        .filter(|c| c.change == FileChange::Renamed)
+        .map(|c| (c.old_path.unwrap(), c.path))
+        .collect())
+}
+
+// Moves the base and `other` version of each file the renaming side moved
+// to its new path, so that what `other` did to it merges into the renamed
+// file. Files `other` deleted or renamed as well are left alone.
+fn follow_renames(base: &mut TreeFiles, other: &mut TreeFiles, renames: &[(String, String)]) {
+    for (old_path, new_path) in renames {
+        if !other.contains_key(old_path)
+            || other.contains_key(new_path)
+            || base.contains_key(new_path)
+        {
+            continue;
+        }
+        let entry = other.remove(old_path).unwrap();
+        other.insert(new_path.clone(), entry);
+        if let Some(entry) = base.remove(old_path) {
+            base.insert(new_path.clone(), entry);
+        }
+    }
+}
+
+// a file cannot share its path with a directory, so such files are renamed
+// to `path~side` like git does
+fn move_files_out_of_the_way(
+    merged: &mut TreeFiles,
+    ours: &TreeFiles,
+    labels: (&str, &str),
+    conflicts: &mut Vec<Conflict>,
+) {
+    let in_the_way = merged
+        .keys()
+        .filter(|path| {
+            let dir = format!("{path}/");
+            merged
+                .range(dir.clone()..)
+                .next()
+                .is_some_and(|(p, _)| p.starts_with(&dir))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for path in in_the_way {
+        let entry = merged.remove(&path).unwrap();
+        let ours_entry = ours.get(&path) == Some(&entry);
+        let label = if ours_entry { labels.0 } else { labels.1 };
+        let moved_to = format!("{path}~{}", label.replace('/', "_"));
+        merged.insert(moved_to.clone(), entry.clone());
+
+        conflicts.retain(|c| c.path != path);
+        conflicts.push(Conflict {
+            path,
+            kind: ConflictKind::FileDirectory {
+                added_in: label.to_string(),
+                moved_to,
+            },
+            base: None,
+            ours: ours_entry.then(|| entry.clone()),
+            theirs: (!ours_entry).then_some(entry),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn criss_cross_merges_have_two_bases() {
+        // root - a1 - a2 - a3
+        //           \/
+        //      - b1 - b2 - b3
+        let repo = TestRepo::new();
+        let root = repo.commit(&[], &[("f", "0\n")], 1, "root");
+        let a1 = repo.commit(&[&root], &[("f", "a\n")], 2, "a1");
+        let b1 = repo.commit(&[&root], &[("f", "b\n")], 3, "b1");
+        let a2 = repo.commit(&[&a1, &b1], &[("f", "a\n")], 4, "a2");
+        let b2 = repo.commit(&[&b1, &a1], &[("f", "b\n")], 5, "b2");
+        let a3 = repo.commit(&[&a2], &[("f", "a3\n")], 6, "a3");
+        let b3 = repo.commit(&[&b2], &[("f", "b3\n")], 7, "b3");
+
+        assert_eq!(merge_bases(&repo, &a3, &b3).unwrap(), [b1.as_str(), &a1]);
+        assert_eq!(merge_bases(&repo, &a2, &b1).unwrap(), [b1.as_str()]);
+        assert_eq!(merge_bases(&repo, &a1, &b1).unwrap(), [root]);
+        assert_eq!(merge_bases(&repo, &a3, &a3).unwrap(), [a3]);
+    }
+
+    #[test]
+    fn bases_below_other_candidates_are_left_out() {
+        // the side branch merges main twice; only the newer merge point counts
+        let repo = TestRepo::new();
+        let m1 = repo.commit(&[], &[("f", "1\n")], 1, "m1");
+        let m2 = repo.commit(&[&m1], &[("f", "2\n")], 2, "m2");
+        let s1 = repo.commit(&[&m1], &[("g", "1\n")], 3, "s1");
+        let s2 = repo.commit(&[&s1, &m2], &[("g", "2\n")], 4, "s2");
+        let m3 = repo.commit(&[&m2], &[("f", "3\n")], 5, "m3");
+
+        assert_eq!(merge_bases(&repo, &m3, &s2).unwrap(), [m2]);
+    }
+
+    #[test]
+    fn ancestry_ignores_skewed_dates() {
+        let repo = TestRepo::new();
+        let old = repo.commit(&[], &[("f", "1\n")], 100, "old");
+        let skewed = repo.commit(&[&old], &[("f", "2\n")], 10, "skewed");
+        let tip = repo.commit(&[&skewed], &[("f", "3\n")], 200, "tip");
+
+        assert!(is_ancestor(&repo, &old, &tip).unwrap());
+        assert!(is_ancestor(&repo, &tip, &tip).unwrap());
+        assert!(!is_ancestor(&repo, &tip, &old).unwrap());
+        assert_eq!(merge_bases(&repo, &old, &tip).unwrap(), [old]);
+    }
+}
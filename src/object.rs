@@ -0,0 +1,322 @@
+use std::fmt;
+use std::str;
+
+use crate::error::{Error, Result};
+
+pub const MODE_BLOB: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+pub const MODE_TREE: u32 = 0o40000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    pub fn parse(kind: &str, data: &[u8]) -> Result<Object> {
+        Ok(match kind {
+            "blob" => Object::Blob(Blob::parse(data)),
+            "tree" => Object::Tree(Tree::parse(data)?),
+            "commit" => Object::Commit(Commit::parse(data)?),
+            "tag" => Object::Tag(Tag::parse(data)?),
+            _ => return Err(corrupt(format!("unknown object type {kind}"))),
+        })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Object::Blob(_) => "blob",
+            Object::Tree(_) => "tree",
+            Object::Commit(_) => "commit",
+            Object::Tag(_) => "tag",
+        }
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            Object::Blob(blob) => blob.serialize(),
+            Object::Tree(tree) => tree.serialize()?,
+            Object::Commit(commit) => commit.serialize(),
+            Object::Tag(tag) => tag.serialize(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub data: Vec<u8>,
+}
+
+impl Blob {
+    pub fn parse(data: &[u8]) -> Blob {
+        Blob {
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub oid: String,
+}
+
+impl TreeEntry {
+    pub fn is_tree(&self) -> bool {
+        self.mode == MODE_TREE
+    }
+
+    // git orders entries as if directory names ended with '/'
+    fn sort_key(&self) -> Vec<u8> {
+        let mut key = self.name.as_bytes().to_vec();
+        if self.is_tree() {
+            key.push(b'/');
+        }
+        key
+    }
+}
+
+/// Entries are stored in git's binary layout: `<octal mode> <name>\0<20-byte oid>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>,
+}
+
+impl Tree {
+    pub fn new(mut entries: Vec<TreeEntry>) -> Tree {
+        entries.sort_by_key(|e| e.sort_key());
+        Tree { entries }
+    }
+
+    pub fn parse(mut data: &[u8]) -> Result<Tree> {
+        let mut entries = vec![];
+
+        while !data.is_empty() {
+            let space = data.iter().position(|&b| b == b' ');
+            let nul = data.iter().position(|&b| b == b'\x00');
+            let (space, nul) = match (space, nul) {
+                (Some(space), Some(nul)) if space < nul && data.len() >= nul + 21 => (space, nul),
+                _ => return Err(corrupt("truncated tree entry")),
+            };
+
+            let mode = str::from_utf8(&data[..space])
+                .ok()
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .ok_or_else(|| corrupt("bad tree entry mode"))?;
+            let name = str::from_utf8(&data[space + 1..nul])
+                .map_err(|_| corrupt("tree entry name is not UTF-8"))?
+                .to_string();
+            let oid = hex::encode(&data[nul + 1..nul + 21]);
+
+            entries.push(TreeEntry { mode, name, oid });
+            data = &data[nul + 21..];
+        }
+
+        Ok(Tree { entries })
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        for e in &self.entries {
+            out.extend_from_slice(format!("{:o} {}\x00", e.mode, e.name).as_bytes());
+            out.extend_from_slice(&raw_oid(&e.oid)?);
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the unix epoch.
+    pub time: i64,
+    /// Offset from UTC in minutes.
+    pub offset: i32,
+}
+
+impl Signature {
+    pub fn parse(line: &str) -> Result<Signature> {
+        let (lt, gt) = match (line.find('<'), line.rfind('>')) {
+            (Some(lt), Some(gt)) if lt < gt => (lt, gt),
+            _ => return Err(corrupt(format!("bad signature '{line}'"))),
+        };
+        let mut rest = line[gt + 1..].split_whitespace();
+
+        let time = rest.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+        let offset = rest.next().map_or(0, parse_offset);
+
+        Ok(Signature {
+            name: line[..lt].trim_end().to_string(),
+            email: line[lt + 1..gt].to_string(),
+            time,
+            offset,
+        })
+    }
+}
+
+fn parse_offset(zone: &str) -> i32 {
+    let sign = if zone.starts_with('-') { -1 } else { 1 };
+    let digits = zone.trim_start_matches(['+', '-']);
+    let value: i32 = digits.parse().unwrap_or(0);
+    sign * (value / 100 * 60 + value % 100)
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(
+            f,
+            "{} <{}> {} {sign}{:02}{:02}",
+            self.name,
+            self.email,
+            self.time,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+/// Splits `key value` header lines from the message that follows the first blank line.
+fn parse_headers(data: &[u8]) -> Result<(Vec<(String, String)>, String)> {
+    let text = str::from_utf8(data).map_err(|_| corrupt("object is not valid UTF-8"))?;
+    let (head, message) = match text.find("\n\n") {
+        Some(i) => (&text[..i], &text[i + 2..]),
+        None => (text.trim_end_matches('\n'), ""),
+    };
+
+    let headers = head
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    Ok((headers, message.to_string()))
+}
+
+/// The 20 bytes of a hex `oid`, as trees and the index store it.
+pub fn raw_oid(oid: &str) -> Result<Vec<u8>> {
+    match hex::decode(oid) {
+        Ok(raw) if raw.len() == 20 => Ok(raw),
+        _ => Err(corrupt(format!("invalid object id '{oid}'"))),
+    }
+}
+
+fn corrupt(reason: impl Into<String>) -> Error {
+    Error::CorruptObject(reason.into())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Option<Signature>,
+    pub committer: Option<Signature>,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn parse(data: &[u8]) -> Result<Commit> {
+        let (headers, message) = parse_headers(data)?;
+        let mut commit = Commit {
+            tree: String::new(),
+            parents: vec![],
+            author: None,
+            committer: None,
+            message,
+        };
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" => commit.tree = value,
+                "parent" => commit.parents.push(value),
+                "author" => commit.author = Some(Signature::parse(&value)?),
+                "committer" => commit.committer = Some(Signature::parse(&value)?),
+                _ => {}
+            }
+        }
+        if commit.tree.is_empty() {
+            return Err(corrupt("commit has no tree"));
+        }
+
+        Ok(commit)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = format!("tree {}\n", self.tree);
+        for parent in &self.parents {
+            out.push_str(&format!("parent {parent}\n"));
+        }
+        if let Some(author) = &self.author {
+            out.push_str(&format!("author {author}\n"));
+        }
+        if let Some(committer) = &self.committer {
+            out.push_str(&format!("committer {committer}\n"));
+        }
+        out.push('\n');
+        out.push_str(&self.message);
+
+        out.into_bytes()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub object: String,
+    pub kind: String,
+    pub tag: String,
+    pub tagger: Option<Signature>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn parse(data: &[u8]) -> Result<Tag> {
+        let (headers, message) = parse_headers(data)?;
+        let mut tag = Tag {
+            object: String::new(),
+            kind: String::new(),
+            tag: String::new(),
+            tagger: None,
+            message,
+        };
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "object" => tag.object = value,
+                "type" => tag.kind = value,
+                "tag" => tag.tag = value,
+                "tagger" => tag.tagger = Some(Signature::parse(&value)?),
+                _ => {}
+            }
+        }
+        if tag.object.is_empty() {
+            return Err(corrupt("tag has no object"));
+        }
+
+        Ok(tag)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = format!(
+            "object {}\ntype {}\ntag {}\n",
+            self.object, self.kind, self.tag
+        );
+        if let Some(tagger) = &self.tagger {
+            out.push_str(&format!("tagger {tagger}\n"));
+        }
+        out.push('\n');
+        out.push_str(&self.message);
+
+        out.into_bytes()
+    }
+}
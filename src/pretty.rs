@@ -0,0 +1,336 @@
+//! How `log` and `show` print commits: the built-in `--pretty` formats,
+//! `--format` placeholders and ref decorations.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::object::{Commit, Signature};
+use crate::{data, date, revision, Repository};
+
+// how many hex digits abbreviated oids get at least
+const ABBREV: usize = 7;
+
+/// One of git's `--pretty` formats.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The abbreviated oid and the subject on one line.
+    Oneline,
+    /// The author and the subject.
+    Short,
+    /// The author, date and full message.
+    #[default]
+    Medium,
+    /// The author, committer and full message.
+    Full,
+    /// The commit headers as they are stored, and the full message.
+    Raw,
+    /// `%`-placeholders expanded; `terminated` ends every entry with a
+    /// newline (`tformat:`) rather than separating entries (`format:`).
+    Template { template: String, terminated: bool },
+}
+
+impl Format {
+    /// Whether each entry ends with a newline instead of entries being
+    /// separated by one.
+    pub fn uses_terminator(&self) -> bool {
+        matches!(
+            self,
+            Format::Oneline
+                | Format::Template {
+                    terminated: true,
+                    ..
+                }
+        )
+    }
+
+    /// Whether entries print nothing, as with `--format=` to show only
+    /// their changes.
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Format::Template { template, .. } if template.is_empty())
+    }
+}
+
+/// A `--pretty` or `--format` value: a format name, `format:<template>`,
+/// `tformat:<template>`, or a template with at least one placeholder.
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        let template = |template: &str, terminated| Format::Template {
+            template: template.to_string(),
+            terminated,
+        };
+
+        Ok(match s {
+            "oneline" => Format::Oneline,
+            "short" => Format::Short,
+            "medium" => Format::Medium,
+            "full" => Format::Full,
+            "raw" => Format::Raw,
+            _ => match (s.strip_prefix("format:"), s.strip_prefix("tformat:")) {
+                (Some(format), _) => template(format, false),
+                (_, Some(format)) => template(format, true),
+                _ if s.contains('%') || s.is_empty() => template(s, true),
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid --pretty format: {s}"
+                    )))
+                }
+            },
+        })
+    }
+}
+
+/// How `--decorate` names refs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decorate {
+    /// `master` and `tag: v1.0`.
+    Short,
+    /// `refs/heads/master` and `tag: refs/tags/v1.0`.
+    Full,
+}
+
+/// The refs pointing at each commit, in the order `--decorate` lists them:
+/// HEAD and the branch it is on first, then the rest by name, descending.
+#[derive(Debug, Clone, Default)]
+pub struct Decorations(HashMap<String, Vec<String>>);
+
+impl Decorations {
+    pub fn load(repo: &Repository, style: Decorate) -> Result<Decorations> {
+        let name = |full: &str| match style {
+            Decorate::Full => full.to_string(),
+            Decorate::Short => ["refs/heads/", "refs/tags/", "refs/remotes/"]
+                .iter()
+                .find_map(|prefix| full.strip_prefix(prefix))
+                .unwrap_or(full)
+                .to_string(),
+        };
+
+        let head = data::get_ref(repo, "HEAD", false)?;
+        let head_branch = match head.symbolic {
+            true => Some(data::resolve_ref(repo, "HEAD")?),
+            false => None,
+        };
+
+        let mut labels = HashMap::<String, Vec<String>>::new();
+        for (full, oid) in data::list_refs(repo)?.into_iter().rev() {
+            if head_branch.as_ref() == Some(&full) {
+                continue;
+            }
+            let label = match full.starts_with("refs/tags/") {
+                true => format!("tag: {}", name(&full)),
+                false => name(&full),
+            };
+            // tags on annotated tag objects decorate the tagged commit
+            let oid = revision::peel(repo, &oid, "")?;
+            labels.entry(oid).or_default().push(label);
+        }
+
+        if let Some(oid) = data::get_ref(repo, "HEAD", true)?.value {
+            let label = match &head_branch {
+                Some(branch) => format!("HEAD -> {}", name(branch)),
+                None => String::from("HEAD"),
+            };
+            labels
+                .entry(oid.trim().to_string())
+                .or_default()
+                .insert(0, label);
+        }
+
+        Ok(Decorations(labels))
+    }
+
+    /// The labels of `oid` joined with commas, or None if no ref points at it.
+    pub fn get(&self, oid: &str) -> Option<String> {
+        self.0.get(oid).map(|labels| labels.join(", "))
+    }
+}
+
+/// Formats `commit` for `log` or `show`, naming it by an abbreviated oid
+/// if `abbrev_commit` is set. Only the template and oneline formats leave
+/// out the final newline.
+pub fn format_commit(
+    repo: &Repository,
+    oid: &str,
+    commit: &Commit,
+    format: &Format,
+    decorations: Option<&Decorations>,
+    abbrev_commit: bool,
+) -> Result<String> {
+    let name = match abbrev_commit {
+        true => revision::abbreviate(repo, oid, ABBREV)?,
+        false => oid.to_string(),
+    };
+    let decoration = decorations
+        .and_then(|decorations| decorations.get(oid))
+        .map(|labels| format!(" ({labels})"))
+        .unwrap_or_default();
+
+    let (header, message) = match format {
+        Format::Oneline => {
+            return Ok(format!("{name}{decoration} {}", subject(commit)));
+        }
+        Format::Template { template, .. } => {
+            return expand(repo, oid, commit, template, decorations);
+        }
+        Format::Raw => {
+            let mut header = format!("commit {name}{decoration}\ntree {}\n", commit.tree);
+            for parent in &commit.parents {
+                header.push_str(&format!("parent {parent}\n"));
+            }
+            if let Some(author) = &commit.author {
+                header.push_str(&format!("author {author}\n"));
+            }
+            if let Some(committer) = &commit.committer {
+                header.push_str(&format!("committer {committer}\n"));
+            }
+            (header, commit.message.as_str())
+        }
+        Format::Short | Format::Medium | Format::Full => {
+            let mut header = format!("commit {name}{decoration}\n");
+            if commit.parents.len() > 1 {
+                let parents = commit
+                    .parents
+                    .iter()
+                    .map(|parent| revision::abbreviate(repo, parent, ABBREV))
+                    .collect::<Result<Vec<_>>>()?;
+                header.push_str(&format!("Merge: {}\n", parents.join(" ")));
+            }
+            if let Some(author) = &commit.author {
+                header.push_str(&format!("Author: {}\n", ident(author)));
+            }
+            match (format, &commit.author, &commit.committer) {
+                (Format::Medium, Some(author), _) => {
+                    let date = date::format(author.time, author.offset);
+                    header.push_str(&format!("Date:   {date}\n"));
+                }
+                (Format::Full, _, Some(committer)) => {
+                    header.push_str(&format!("Commit: {}\n", ident(committer)));
+                }
+                _ => {}
+            }
+            let message = match format {
+                Format::Short => title(&commit.message),
+                _ => commit.message.as_str(),
+            };
+            (header, message)
+        }
+    };
+
+    let mut out = header;
+    out.push('\n');
+    for line in message.trim_end().lines() {
+        out.push_str(&format!("    {line}\n"));
+    }
+    Ok(out)
+}
+
+fn ident(signature: &Signature) -> String {
+    format!("{} <{}>", signature.name, signature.email)
+}
+
+// the lines of the first paragraph of a message
+fn title(message: &str) -> &str {
+    let message = message.trim_start_matches('\n');
+    match message.find("\n\n") {
+        Some(end) => &message[..end + 1],
+        None => message,
+    }
+}
+
+/// The first paragraph of the message, joined into one line.
+pub fn subject(commit: &Commit) -> String {
+    title(&commit.message)
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// the message after its first paragraph
+fn body(commit: &Commit) -> String {
+    let message = commit.message.trim_start_matches('\n');
+    let body = match message.find("\n\n") {
+        Some(end) => message[end..].trim_start_matches('\n').trim_end(),
+        None => "",
+    };
+    match body {
+        "" => String::new(),
+        body => format!("{body}\n"),
+    }
+}
+
+// Expands the placeholders of a `--format` template. Unknown placeholders
+// are kept as they are.
+fn expand(
+    repo: &Repository,
+    oid: &str,
+    commit: &Commit,
+    template: &str,
+    decorations: Option<&Decorations>,
+) -> Result<String> {
+    let abbrev = |oid: &str| revision::abbreviate(repo, oid, ABBREV);
+    let labels = || decorations.and_then(|decorations| decorations.get(oid));
+    let person = |signature: Option<&Signature>, field: char| {
+        signature.map(|s| match field {
+            'n' => s.name.clone(),
+            'e' => s.email.clone(),
+            'd' => date::format(s.time, s.offset),
+            _ => s.time.to_string(),
+        })
+    };
+
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(percent) = rest.find('%') {
+        out.push_str(&rest[..percent]);
+        rest = &rest[percent + 1..];
+
+        let mut chars = rest.chars();
+        let (value, len) = match (chars.next(), chars.next()) {
+            (Some('%'), _) => (Some(String::from("%")), 1),
+            (Some('n'), _) => (Some(String::from("\n")), 1),
+            (Some('H'), _) => (Some(oid.to_string()), 1),
+            (Some('h'), _) => (Some(abbrev(oid)?), 1),
+            (Some('T'), _) => (Some(commit.tree.clone()), 1),
+            (Some('t'), _) => (Some(abbrev(&commit.tree)?), 1),
+            (Some('P'), _) => (Some(commit.parents.join(" ")), 1),
+            (Some('p'), _) => {
+                let parents = commit
+                    .parents
+                    .iter()
+                    .map(|parent| abbrev(parent))
+                    .collect::<Result<Vec<_>>>()?;
+                (Some(parents.join(" ")), 1)
+            }
+            (Some('s'), _) => (Some(subject(commit)), 1),
+            (Some('b'), _) => (Some(body(commit)), 1),
+            (Some('B'), _) => (Some(commit.message.clone()), 1),
+            (Some('d'), _) => (
+                Some(labels().map(|l| format!(" ({l})")).unwrap_or_default()),
+                1,
+            ),
+            (Some('D'), _) => (Some(labels().unwrap_or_default()), 1),
+            (Some('a'), Some(field @ ('n' | 'e' | 'd' | 't'))) => (
+                Some(person(commit.author.as_ref(), field).unwrap_or_default()),
+                2,
+            ),
+            (Some('c'), Some(field @ ('n' | 'e' | 'd' | 't'))) => (
+                Some(person(commit.committer.as_ref(), field).unwrap_or_default()),
+                2,
+            ),
+            _ => (None, 0),
+        };
+        match value {
+            Some(value) => {
+                out.push_str(&value);
+                rest = &rest[len..];
+            }
+            None => out.push('%'),
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
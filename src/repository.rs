@@ -0,0 +1,228 @@
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{Config, Level};
+use crate::data::{self, GIT_DIR};
+use crate::error::{Error, Result};
+use crate::{base, diff};
+
+/// Handle to an rgit repository on disk.
+#[derive(Debug, Clone)]
+pub struct Repository {
+    git_dir: PathBuf,
+    /// `None` for bare repositories.
+    work_tree: Option<PathBuf>,
+}
+
+impl Repository {
+    /// Creates a new empty repository in `path`.
+    pub fn init<P: AsRef<Path>>(path: P) -> Result<Repository> {
+        let root = fs::canonicalize(path)?;
+        Repository::create(Repository {
+            git_dir: root.join(GIT_DIR),
+            work_tree: Some(root),
+        })
+    }
+
+    /// Creates a new empty bare repository: `path` itself becomes the rgit directory.
+    pub fn init_bare<P: AsRef<Path>>(path: P) -> Result<Repository> {
+        let path = path.as_ref();
+        if !path.exists() {
+            fs::create_dir_all(path)?;
+        }
+
+        Repository::create(Repository {
+            git_dir: fs::canonicalize(path)?,
+            work_tree: None,
+        })
+    }
+
+    fn create(repo: Repository) -> Result<Repository> {
+        if repo.git_dir.join("HEAD").exists() {
+            return Err(Error::AlreadyInitialized(repo.git_dir));
+        }
+
+        base::init(&repo)?;
+        Ok(repo)
+    }
+
+    /// Opens the repository whose `.rgit` directory lives in `path`.
+    /// That directory is also the work tree unless overridden.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Repository> {
+        let root = fs::canonicalize(path.as_ref())?;
+        let git_dir = root.join(GIT_DIR);
+        if !is_git_dir(&git_dir) {
+            return Err(Error::NotARepository(path.as_ref().to_path_buf()));
+        }
+
+        Ok(Repository {
+            git_dir,
+            work_tree: Some(root),
+        })
+    }
+
+    /// Opens an rgit directory given directly, as with `--git-dir` or `RGIT_DIR`.
+    /// A directory named `.rgit` gets its parent as work tree unless `core.bare`
+    /// says otherwise; anything else is bare.
+    pub fn open_git_dir<P: AsRef<Path>>(git_dir: P) -> Result<Repository> {
+        let git_dir = fs::canonicalize(git_dir.as_ref())
+            .map_err(|_| Error::NotARepository(git_dir.as_ref().to_path_buf()))?;
+        if !is_git_dir(&git_dir) {
+            return Err(Error::NotARepository(git_dir));
+        }
+
+        let mut repo = Repository {
+            git_dir,
+            work_tree: None,
+        };
+        let bare = Config::load_level(Some(&repo), Level::Local)?.get_bool("core.bare")?;
+        if bare != Some(true) && repo.git_dir.file_name().is_some_and(|name| name == GIT_DIR) {
+            repo.work_tree = repo.git_dir.parent().map(Path::to_path_buf);
+        }
+
+        Ok(repo)
+    }
+
+    /// Finds the repository containing `start` by checking it and each of its
+    /// ancestors for a `.rgit` directory, or for being a bare repository.
+    pub fn discover<P: AsRef<Path>>(start: P) -> Result<Repository> {
+        let start = fs::canonicalize(start.as_ref())?;
+
+        for dir in start.ancestors() {
+            if is_git_dir(&dir.join(GIT_DIR)) {
+                return Repository::open(dir);
+            }
+            if is_git_dir(dir) {
+                return Repository::open_git_dir(dir);
+            }
+        }
+
+        Err(Error::NotARepository(start))
+    }
+
+    /// Uses `path` as the work tree instead of the directory holding `.rgit`.
+    pub fn with_work_tree<P: AsRef<Path>>(mut self, path: P) -> Result<Repository> {
+        self.work_tree = Some(fs::canonicalize(path)?);
+        Ok(self)
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    pub fn work_tree(&self) -> Result<&Path> {
+        self.work_tree.as_deref().ok_or(Error::BareRepository)
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.work_tree.is_none()
+    }
+
+    /// Turns a path given on the command line, relative to the current
+    /// directory, into a `/`-separated path relative to the work tree.
+    pub fn relative_path<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let path = path.as_ref();
+        let absolute = env::current_dir()?.join(path);
+
+        let mut normal = PathBuf::new();
+        for component in absolute.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normal.pop();
+                }
+                c => normal.push(c),
+            }
+        }
+
+        let relative = normal
+            .strip_prefix(self.work_tree()?)
+            .map_err(|_| Error::PathOutsideRepository(path.to_path_buf()))?;
+
+        Ok(relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    /// The merged system, global and repository configuration.
+    pub fn config(&self) -> Result<Config> {
+        Config::load(Some(self))
+    }
+
+    pub fn head(&self) -> Result<Option<String>> {
+        Ok(data::get_ref(self, "HEAD", true)?.value)
+    }
+
+    pub fn rev_parse(&self, name: &str) -> Result<String> {
+        base::get_oid(self, name)
+    }
+
+    pub fn hash_object(&self, contents: &[u8]) -> Result<String> {
+        data::hash_object(self, contents, "blob")
+    }
+
+    pub fn write_tree(&self) -> Result<String> {
+        base::write_tree(self)
+    }
+
+    pub fn add(&self, paths: &[String]) -> Result<()> {
+        base::add(self, paths, false)
+    }
+
+    pub fn status(&self) -> Result<diff::Status> {
+        diff::status(self)
+    }
+
+    pub fn commit(&self, message: &str) -> Result<String> {
+        base::commit(self, message, None, None)
+    }
+
+    pub fn checkout(&self, name: &str) -> Result<()> {
+        base::checkout(self, name)
+    }
+
+    /// Creates branch `name` at `start_point` (HEAD by default) and returns its oid.
+    pub fn branch(&self, name: &str, start_point: Option<&str>) -> Result<String> {
+        let oid = self.rev_parse(start_point.unwrap_or("HEAD"))?;
+        base::create_branch(self, name, &oid)?;
+        Ok(oid)
+    }
+
+    /// Creates tag `name` at `rev` (HEAD by default) and returns its oid.
+    pub fn tag(&self, name: &str, rev: Option<&str>) -> Result<String> {
+        let oid = self.rev_parse(rev.unwrap_or("HEAD"))?;
+        base::create_tag(self, name, &oid)?;
+        Ok(oid)
+    }
+
+    /// Returns the files that differ between `rev` (HEAD by default) and the work tree.
+    pub fn diff(&self, rev: Option<&str>) -> Result<Vec<diff::TreeChange>> {
+        let tree = diff::rev_tree(self, rev.unwrap_or("HEAD"))?;
+        diff::diff_sources(
+            self,
+            &diff::Source::Tree(Some(tree)),
+            &diff::Source::WorkTree,
+            &[],
+            &diff::Renames::default(),
+        )
+    }
+
+    /// Returns the files that differ between two revisions.
+    pub fn diff_revs(&self, old: &str, new: &str) -> Result<Vec<diff::TreeChange>> {
+        let old = diff::Source::Tree(Some(diff::rev_tree(self, old)?));
+        let new = diff::Source::Tree(Some(diff::rev_tree(self, new)?));
+        diff::diff_sources(self, &old, &new, &[], &diff::Renames::default())
+    }
+
+    /// Merges `branch` into HEAD, fast-forwarding when possible.
+    pub fn merge(&self, branch: &str) -> Result<base::MergeOutcome> {
+        base::read_tree_merged(self, "HEAD", branch, &base::MergeOptions::default())
+    }
+}
+
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
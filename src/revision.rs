@@ -0,0 +1,570 @@
+//! Revision syntax: names like `HEAD~2`, `topic@{1}`, `v1.0^{tree}` or
+//! `HEAD:src/lib.rs` resolved to the objects they name.
+
+use std::collections::{BinaryHeap, HashSet};
+
+use regex::Regex;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::index::Index;
+use crate::object::Object;
+use crate::{base, data, date, merge, Repository};
+
+// shortest hex prefix taken as an abbreviated oid
+const MIN_ABBREV: usize = 4;
+
+/// Resolves a single revision to the oid of the object it names. On top of
+/// ref names and (abbreviated) oids this understands:
+///
+/// - `<rev>~N` and `<rev>^N`: the Nth first-parent ancestor and the Nth parent
+/// - `<rev>^{tree}`, `^{commit}`, `^{}` and `^{/regex}`: peeling and search
+/// - `<ref>@{N}`, `@{<date>}`, `@{-N}` and `@{upstream}`: reflog entries,
+///   previously checked out branches and the upstream branch
+/// - `:/regex`: the youngest commit reachable from any ref with a matching message
+/// - `<rev>:<path>` and `:[N:]<path>`: an entry of a tree or of the index
+pub fn resolve(repo: &Repository, spec: &str) -> Result<String> {
+    if let Some(pattern) = spec.strip_prefix(":/") {
+        let mut tips = data::list_refs(repo)?
+            .into_iter()
+            .map(|(_, oid)| oid)
+            .collect::<Vec<_>>();
+        tips.extend(data::get_ref(repo, "HEAD", true)?.value);
+        return search_message(repo, tips, pattern)?.ok_or_else(|| bad(spec));
+    }
+    if let Some(path) = spec.strip_prefix(':') {
+        return index_path(repo, path);
+    }
+    if let Some((rev, path)) = split_path(spec) {
+        let tree = peel(repo, &resolve_rev(repo, rev)?, "tree")?;
+        return tree_path(repo, &tree, path)?.ok_or_else(|| {
+            Error::InvalidArgument(format!("path '{path}' does not exist in '{rev}'"))
+        });
+    }
+
+    resolve_rev(repo, spec)
+}
+
+/// A commit given on the command line once ranges are expanded; `A..B`
+/// becomes an included B and an excluded A.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tip {
+    pub oid: String,
+    pub excluded: bool,
+}
+
+impl Tip {
+    fn new(oid: String, excluded: bool) -> Tip {
+        Tip { oid, excluded }
+    }
+}
+
+/// Expands a revision that may stand for several commits: `^A` excludes A,
+/// `A..B` is B without A, `A...B` is either side without their merge bases,
+/// `A^@` is the parents of A and `A^!` is A without its parents. Missing
+/// ends of a range default to HEAD.
+pub fn parse_range(repo: &Repository, spec: &str) -> Result<Vec<Tip>> {
+    let or_head = |rev: &str| if rev.is_empty() { "HEAD" } else { rev }.to_string();
+
+    if spec.starts_with(':') {
+        return Ok(vec![Tip::new(resolve(repo, spec)?, false)]);
+    }
+    if let Some(rev) = spec.strip_prefix('^') {
+        return Ok(vec![Tip::new(resolve(repo, rev)?, true)]);
+    }
+    if let Some(rev) = spec.strip_suffix("^@") {
+        let commit = data::get_commit(repo, &peel(repo, &resolve(repo, rev)?, "commit")?)?;
+        return Ok(commit
+            .parents
+            .into_iter()
+            .map(|oid| Tip::new(oid, false))
+            .collect());
+    }
+    if let Some(rev) = spec.strip_suffix("^!") {
+        let oid = peel(repo, &resolve(repo, rev)?, "commit")?;
+        let mut tips = vec![Tip::new(oid.clone(), false)];
+        for parent in data::get_commit(repo, &oid)?.parents {
+            tips.push(Tip::new(parent, true));
+        }
+        return Ok(tips);
+    }
+    if let Some((left, right)) = spec.split_once("...") {
+        let left = peel(repo, &resolve(repo, &or_head(left))?, "commit")?;
+        let right = peel(repo, &resolve(repo, &or_head(right))?, "commit")?;
+        let mut tips = vec![
+            Tip::new(right.clone(), false),
+            Tip::new(left.clone(), false),
+        ];
+        for base in merge::merge_bases(repo, &left, &right)? {
+            tips.push(Tip::new(base, true));
+        }
+        return Ok(tips);
+    }
+    if let Some((left, right)) = spec.split_once("..") {
+        return Ok(vec![
+            Tip::new(resolve(repo, &or_head(right))?, false),
+            Tip::new(resolve(repo, &or_head(left))?, true),
+        ]);
+    }
+
+    Ok(vec![Tip::new(resolve(repo, spec)?, false)])
+}
+
+/// Expands command-line revisions with `parse_range`; `--not` flips whether
+/// the revisions after it are included or excluded.
+pub fn parse_revisions(repo: &Repository, args: &[String]) -> Result<Vec<Tip>> {
+    let mut not = false;
+    let mut tips = vec![];
+    for arg in args {
+        if arg == "--not" {
+            not = !not;
+            continue;
+        }
+        for mut tip in parse_range(repo, arg)? {
+            tip.excluded ^= not;
+            tips.push(tip);
+        }
+    }
+
+    Ok(tips)
+}
+
+/// The shortest prefix of `oid`, at least `min_len` long, that no other
+/// stored object shares.
+pub fn abbreviate(repo: &Repository, oid: &str, min_len: usize) -> Result<String> {
+    let mut len = min_len.clamp(MIN_ABBREV, oid.len());
+    while len < oid.len() && data::find_objects(repo, &oid[..len])?.len() > 1 {
+        len += 1;
+    }
+
+    Ok(oid[..len].to_string())
+}
+
+fn bad(spec: &str) -> Error {
+    Error::BadRevision(spec.to_string())
+}
+
+// "HEAD:src/main.rs", ignoring colons in "@{...}" dates and "^{/...}" searches
+fn split_path(spec: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 => return Some((&spec[..i], &spec[i + 1..])),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// a name with its reflog selector, followed by any "~N", "^N" and "^{...}"
+fn resolve_rev(repo: &Repository, spec: &str) -> Result<String> {
+    let end = spec.find(['~', '^']).unwrap_or(spec.len());
+    let (name, mut ops) = spec.split_at(end);
+    let mut oid = match name.find("@{") {
+        Some(at) if name.ends_with('}') => {
+            reflog_entry(repo, &name[..at], &name[at + 2..name.len() - 1])?
+        }
+        _ => resolve_name(repo, name)?,
+    };
+
+    while let Some(op) = ops.chars().next() {
+        ops = &ops[1..];
+        if op == '^' && ops.starts_with('{') {
+            let close = closing_brace(ops).ok_or_else(|| bad(spec))?;
+            let kind = &ops[1..close];
+            ops = &ops[close + 1..];
+            oid = match kind.strip_prefix('/') {
+                Some(pattern) => {
+                    let commit = peel(repo, &oid, "commit")?;
+                    search_message(repo, vec![commit], pattern)?.ok_or_else(|| bad(spec))?
+                }
+                None => peel(repo, &oid, kind)?,
+            };
+            continue;
+        }
+
+        let digits = ops.len() - ops.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let n = match digits {
+            0 => 1,
+            _ => ops[..digits].parse().map_err(|_| bad(spec))?,
+        };
+        ops = &ops[digits..];
+
+        let mut commit = peel(repo, &oid, "commit")?;
+        if op == '~' {
+            for _ in 0..n {
+                let parents = data::get_commit(repo, &commit)?.parents;
+                commit = parents.into_iter().next().ok_or_else(|| bad(spec))?;
+            }
+        } else if n > 0 {
+            let parents = data::get_commit(repo, &commit)?.parents;
+            commit = parents.into_iter().nth(n - 1).ok_or_else(|| bad(spec))?;
+        }
+        oid = commit;
+    }
+
+    Ok(oid)
+}
+
+// the "}" balancing the "{" that `ops` starts with, so that "^{/re}"
+// patterns may hold braces; an unbalanced pattern runs to the last "}"
+fn closing_brace(ops: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in ops.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    ops.rfind('}')
+}
+
+// a ref, or a full or abbreviated oid
+fn resolve_name(repo: &Repository, name: &str) -> Result<String> {
+    if let Some(rgit_ref) = full_ref_name(repo, name)? {
+        if let Some(oid) = ref_oid(repo, &rgit_ref)? {
+            return Ok(oid);
+        }
+    }
+
+    let is_hex = name.bytes().all(|b| b.is_ascii_hexdigit());
+    if is_hex && name.len() == 40 {
+        return Ok(name.to_lowercase());
+    }
+    if is_hex && name.len() >= MIN_ABBREV {
+        let oids = data::find_objects(repo, &name.to_lowercase())?;
+        match oids.len() {
+            0 => {}
+            1 => return Ok(oids[0].clone()),
+            _ => return Err(Error::AmbiguousRevision(name.to_string())),
+        }
+    }
+
+    Err(bad(name))
+}
+
+// "master" -> "refs/heads/master", trying the places git looks in order;
+// only all-caps names such as HEAD or ORIG_HEAD are taken as they are
+fn full_ref_name(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let name = if name == "@" { "HEAD" } else { name };
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    let mut candidates = vec![];
+    if name.starts_with("refs/") || name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_') {
+        candidates.push(name.to_string());
+    }
+    for prefix in ["refs/", "refs/tags/", "refs/heads/", "refs/remotes/"] {
+        candidates.push(format!("{prefix}{name}"));
+    }
+    candidates.push(format!("refs/remotes/{name}/HEAD"));
+
+    for candidate in candidates {
+        if data::get_ref(repo, &candidate, false)?.value.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+fn ref_oid(repo: &Repository, rgit_ref: &str) -> Result<Option<String>> {
+    let value = data::get_ref(repo, rgit_ref, true)?.value;
+    Ok(value.map(|oid| oid.trim().to_string()))
+}
+
+// the part of "<name>@{<selector>}" inside the braces
+fn reflog_entry(repo: &Repository, name: &str, selector: &str) -> Result<String> {
+    if selector.eq_ignore_ascii_case("u") || selector.eq_ignore_ascii_case("upstream") {
+        return upstream(repo, name);
+    }
+    if let Some(n) = selector.strip_prefix('-') {
+        let n: usize = n.parse().map_err(|_| bad(selector))?;
+        return resolve_name(repo, &previous_branch(repo, n)?);
+    }
+
+    // a bare "@{N}" reads the current branch's log
+    let rgit_ref = match name {
+        "" => data::resolve_ref(repo, "HEAD")?,
+        _ => full_ref_name(repo, name)?.ok_or_else(|| bad(name))?,
+    };
+    let entries = data::read_reflog(repo, &rgit_ref)?;
+    let short = rgit_ref.strip_prefix("refs/heads/").unwrap_or(&rgit_ref);
+
+    let entry = match selector.parse::<usize>() {
+        Ok(n) => entries.len().checked_sub(n + 1).map(|i| &entries[i]),
+        Err(_) => {
+            let time = date::parse_approx(selector)?;
+            entries
+                .iter()
+                .rev()
+                .find(|entry| entry.committer.time <= time)
+                .or(entries.first())
+        }
+    };
+    match entry {
+        Some(entry) => Ok(entry.new.clone()),
+        None => Err(Error::InvalidArgument(format!(
+            "log for '{short}' only has {} entries",
+            entries.len()
+        ))),
+    }
+}
+
+/// The branch, or detached commit, that `@{-N}` names: what was checked
+/// out before the Nth most recent checkout. None for other revisions.
+pub fn previous_checkout(repo: &Repository, spec: &str) -> Result<Option<String>> {
+    let Some(n) = spec
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.strip_suffix('}'))
+    else {
+        return Ok(None);
+    };
+    let n = n.parse().map_err(|_| bad(spec))?;
+    previous_branch(repo, n).map(Some)
+}
+
+// the branch checked out before the Nth most recent checkout
+fn previous_branch(repo: &Repository, n: usize) -> Result<String> {
+    let entries = data::read_reflog(repo, "HEAD")?;
+    let mut checkouts = entries
+        .iter()
+        .rev()
+        .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+        .filter_map(|moved| moved.split_once(" to "))
+        .map(|(from, _)| from.to_string());
+    n.checked_sub(1)
+        .and_then(|i| checkouts.nth(i))
+        .ok_or_else(|| bad(&format!("@{{-{n}}}")))
+}
+
+// what branch.<name>.remote and branch.<name>.merge point the branch at
+fn upstream(repo: &Repository, name: &str) -> Result<String> {
+    let branch = match name {
+        "" | "@" | "HEAD" => base::get_status_name(repo)?.ok_or_else(|| {
+            Error::InvalidArgument(String::from("HEAD does not point to a branch"))
+        })?,
+        _ => name.trim_start_matches("refs/heads/").to_string(),
+    };
+
+    let config = Config::load(Some(repo))?;
+    let remote = config.get(&format!("branch.{branch}.remote"));
+    let merge = config.get(&format!("branch.{branch}.merge"));
+    let (Some(remote), Some(merge)) = (remote, merge) else {
+        return Err(Error::NoUpstream(branch));
+    };
+
+    let tracking = match (remote, merge.strip_prefix("refs/heads/")) {
+        (".", _) | (_, None) => merge.to_string(),
+        (remote, Some(merged)) => format!("refs/remotes/{remote}/{merged}"),
+    };
+    ref_oid(repo, &tracking)?.ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "upstream branch '{merge}' not stored as a remote-tracking branch"
+        ))
+    })
+}
+
+/// Follows tags, and commits to their trees, until reaching an object of
+/// type `kind`. An empty `kind` peels tags only; "object" takes anything.
+pub fn peel(repo: &Repository, oid: &str, kind: &str) -> Result<String> {
+    let mut oid = oid.to_string();
+    loop {
+        if kind == "object" {
+            return Ok(oid);
+        }
+
+        let object = data::read_object(repo, &oid)?;
+        if object.kind() == kind {
+            return Ok(oid);
+        }
+        oid = match object {
+            Object::Tag(tag) => tag.object,
+            Object::Commit(commit) if kind == "tree" => commit.tree,
+            _ if kind.is_empty() => return Ok(oid),
+            object => {
+                return Err(Error::WrongObjectType {
+                    oid,
+                    expected: kind.to_string(),
+                    actual: object.kind().to_string(),
+                })
+            }
+        };
+    }
+}
+
+// the youngest commit reachable from `tips` whose message matches `pattern`
+fn search_message(repo: &Repository, tips: Vec<String>, pattern: &str) -> Result<Option<String>> {
+    let regex = Regex::new(pattern).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+    for oid in tips {
+        let oid = peel(repo, &oid, "")?;
+        if seen.insert(oid.clone()) {
+            let commit = data::get_commit(repo, &oid)?;
+            queue.push((base::commit_time(&commit), oid));
+        }
+    }
+
+    while let Some((_, oid)) = queue.pop() {
+        let commit = data::get_commit(repo, &oid)?;
+        if regex.is_match(&commit.message) {
+            return Ok(Some(oid));
+        }
+        for parent in commit.parents {
+            if seen.insert(parent.clone()) {
+                let time = base::commit_time(&data::get_commit(repo, &parent)?);
+                queue.push((time, parent));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// ":path" or ":N:path" for the entry at stage N
+fn index_path(repo: &Repository, spec: &str) -> Result<String> {
+    let (stage, path) = match spec.split_once(':') {
+        Some((n @ ("0" | "1" | "2" | "3"), path)) => (n.parse().unwrap_or(0), path),
+        _ => (0, spec),
+    };
+
+    let index = Index::read(repo)?;
+    let entry = index
+        .entries()
+        .find(|entry| entry.path == path && entry.stage == stage);
+    match entry {
+        Some(entry) => Ok(entry.oid.clone()),
+        None if stage == 0 => Err(Error::InvalidArgument(format!(
+            "path '{path}' is not in the index"
+        ))),
+        None => Err(Error::InvalidArgument(format!(
+            "path '{path}' is not in the index at stage {stage}"
+        ))),
+    }
+}
+
+fn tree_path(repo: &Repository, tree: &str, path: &str) -> Result<Option<String>> {
+    let mut oid = tree.to_string();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        let tree = match data::read_object(repo, &oid)? {
+            Object::Tree(tree) => tree,
+            _ => return Ok(None),
+        };
+        match tree.entries.into_iter().find(|entry| entry.name == name) {
+            Some(entry) => oid = entry.oid,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(oid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::RefValue;
+    use crate::testing::TestRepo;
+
+    // c1 - c2 - c3 on main, moved there at 1000, 2000 and 3000, and
+    // c1 - side on side
+    fn history() -> (TestRepo, [String; 4]) {
+        let repo = TestRepo::new();
+        let c1 = repo.commit(&[], &[("f", "1\n")], 100, "first");
+        let c2 = repo.commit(&[&c1], &[("f", "2\n")], 200, "fix: bug {1}");
+        let c3 = repo.commit(&[&c2], &[("f", "3\n")], 300, "third");
+        let side = repo.commit(&[&c1], &[("g", "1\n")], 250, "side");
+        for (oid, time) in [(&c1, 1000), (&c2, 2000), (&c3, 3000)] {
+            repo.branch("main", oid, time);
+        }
+        repo.branch("side", &side, 2500);
+        let head = RefValue {
+            symbolic: true,
+            value: Some("refs/heads/main".to_string()),
+        };
+        data::set_ref(&repo, "HEAD", head, false).unwrap();
+
+        (repo, [c1, c2, c3, side])
+    }
+
+    #[test]
+    fn reflog_entries_by_count() {
+        let (repo, [c1, c2, c3, _]) = history();
+        assert_eq!(resolve(&repo, "main@{0}").unwrap(), c3);
+        assert_eq!(resolve(&repo, "main@{1}").unwrap(), c2);
+        assert_eq!(resolve(&repo, "@{2}").unwrap(), c1);
+        assert_eq!(resolve(&repo, "main@{1}~1").unwrap(), c1);
+        assert!(resolve(&repo, "main@{3}").is_err());
+    }
+
+    #[test]
+    fn reflog_entries_by_date() {
+        let (repo, [c1, c2, c3, _]) = history();
+        assert_eq!(resolve(&repo, "main@{@2500}").unwrap(), c2);
+        assert_eq!(resolve(&repo, "main@{@3000}").unwrap(), c3);
+        // before the log starts, the oldest entry
+        assert_eq!(resolve(&repo, "main@{@10}").unwrap(), c1);
+        assert_eq!(
+            resolve(&repo, "main@{1970-01-01 00:25:00 +0000}").unwrap(),
+            c1
+        );
+        assert_eq!(resolve(&repo, "main@{now}").unwrap(), c3);
+    }
+
+    #[test]
+    fn message_search() {
+        let (repo, [c1, c2, c3, side]) = history();
+        assert_eq!(resolve(&repo, "main^{/bug}").unwrap(), c2);
+        assert_eq!(resolve(&repo, "main^{/^th}").unwrap(), c3);
+        assert_eq!(resolve(&repo, "side^{/first}").unwrap(), c1);
+        // braces in the pattern are matched, and suffixes still apply
+        assert_eq!(resolve(&repo, "main^{/bug {1}}").unwrap(), c2);
+        assert_eq!(resolve(&repo, "main^{/bug {1}}~1").unwrap(), c1);
+        assert_eq!(resolve(&repo, ":/side").unwrap(), side);
+        assert!(resolve(&repo, "main^{/nothing}").is_err());
+    }
+
+    #[test]
+    fn ranges() {
+        let (repo, [c1, _, c3, side]) = history();
+        let tip = |oid: &str, excluded| Tip::new(oid.to_string(), excluded);
+
+        assert_eq!(
+            parse_range(&repo, "main...side").unwrap(),
+            [tip(&side, false), tip(&c3, false), tip(&c1, true)]
+        );
+        assert_eq!(
+            parse_range(&repo, "...side").unwrap(),
+            parse_range(&repo, "HEAD...side").unwrap()
+        );
+        assert_eq!(
+            parse_range(&repo, "main..side").unwrap(),
+            [tip(&side, false), tip(&c3, true)]
+        );
+        assert_eq!(
+            parse_revisions(&repo, &["side".into(), "--not".into(), "main".into()]).unwrap(),
+            [tip(&side, false), tip(&c3, true)]
+        );
+    }
+
+    #[test]
+    fn checking_out_the_previous_branch() {
+        let (repo, [_, _, c3, side]) = history();
+        base::read_tree(&repo, &data::get_commit(&repo, &c3).unwrap().tree).unwrap();
+        base::checkout(&repo, "side").unwrap();
+        assert_eq!(previous_checkout(&repo, "@{-1}").unwrap().unwrap(), "main");
+        assert_eq!(previous_checkout(&repo, "side").unwrap(), None);
+
+        base::checkout(&repo, "@{-1}").unwrap();
+        assert_eq!(data::resolve_ref(&repo, "HEAD").unwrap(), "refs/heads/main");
+        // the reflog names the branch, so @{-1} is side again
+        assert_eq!(previous_checkout(&repo, "@{-1}").unwrap().unwrap(), "side");
+        assert_eq!(resolve(&repo, "@{-1}").unwrap(), side);
+    }
+}
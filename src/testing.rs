@@ -0,0 +1,99 @@
+//! Throwaway repositories for unit tests.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::base::{self, TreeFiles};
+use crate::data::{self, RefValue, ReflogEntry};
+use crate::object::{Commit, Object, Signature, MODE_BLOB};
+use crate::Repository;
+
+/// A repository in its own temporary directory, removed when dropped.
+pub struct TestRepo {
+    repo: Repository,
+    dir: PathBuf,
+}
+
+impl TestRepo {
+    pub fn new() -> TestRepo {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "rgit-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir).unwrap();
+        TestRepo { repo, dir }
+    }
+
+    /// Writes a commit of `files`, as path and contents pairs, made at
+    /// `time` on top of `parents`, and returns its oid.
+    pub fn commit(
+        &self,
+        parents: &[&str],
+        files: &[(&str, &str)],
+        time: i64,
+        message: &str,
+    ) -> String {
+        let mut tree = TreeFiles::new();
+        for (path, contents) in files {
+            let oid = data::hash_object(&self.repo, contents.as_bytes(), "blob").unwrap();
+            tree.insert(path.to_string(), (MODE_BLOB, oid));
+        }
+        let commit = Commit {
+            tree: base::build_tree(&self.repo, &tree).unwrap(),
+            parents: parents.iter().map(|p| p.to_string()).collect(),
+            author: Some(signature(time)),
+            committer: Some(signature(time)),
+            message: format!("{message}\n"),
+        };
+        data::write_object(&self.repo, &Object::Commit(commit)).unwrap()
+    }
+
+    /// Moves `refs/heads/<name>` to `oid` at `time`, recording the move in
+    /// the branch's reflog.
+    pub fn branch(&self, name: &str, oid: &str, time: i64) {
+        let name = format!("refs/heads/{name}");
+        let old = data::get_ref(&self.repo, &name, false).unwrap().value;
+        data::set_ref(
+            &self.repo,
+            &name,
+            RefValue::new(Some(oid.to_string())),
+            false,
+        )
+        .unwrap();
+        let entry = ReflogEntry {
+            old: old.unwrap_or_else(|| data::NULL_OID.to_string()),
+            new: oid.to_string(),
+            committer: signature(time),
+            message: "test".to_string(),
+        };
+        data::append_reflog(&self.repo, &name, &entry).unwrap();
+    }
+}
+
+fn signature(time: i64) -> Signature {
+    Signature {
+        name: "A U Thor".to_string(),
+        email: "author@example.com".to_string(),
+        time,
+        offset: 0,
+    }
+}
+
+impl Deref for TestRepo {
+    type Target = Repository;
+
+    fn deref(&self) -> &Repository {
+        &self.repo
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
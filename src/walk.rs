@@ -0,0 +1,679 @@
+//! Walking commit history: the commits reachable from some revisions but
+//! not from others, in the order `log` and `rev-list` show them.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use regex::Regex;
+
+use crate::base::TreeFiles;
+use crate::diff::{self, Pickaxe, Renames, Source, TreeChange};
+use crate::revision::{self, Tip};
+use crate::{base, data, Repository, Result};
+
+// how many commits to walk on once only excluded history is left, in case
+// clock skew put an ancestor of an excluded commit behind a listed one
+const SLOP: usize = 5;
+
+/// The order commits come out of a walk in, newest first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// By commit date as they are reached, without waiting for every child
+    /// of a commit to be shown first.
+    #[default]
+    Walk,
+    /// By commit date, but never a parent before all its children.
+    Date,
+    /// Never a parent before all its children, and each line of history
+    /// kept together instead of interleaving branches by date.
+    Topo,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    pub order: Order,
+    /// Show the commits oldest first; applied after `skip` and `max_count`.
+    pub reverse: bool,
+    pub max_count: Option<usize>,
+    pub skip: usize,
+    /// Follow only the first parent of merges.
+    pub first_parent: bool,
+    /// Keep only commits that descend from an excluded commit.
+    pub ancestry_path: bool,
+    /// Keep only commits that changed one of these paths. A commit that
+    /// has them as one of its parents had them is followed through that
+    /// parent alone, leaving out side branches whose changes were dropped.
+    pub paths: Vec<String>,
+    /// Follow the one path of `paths` back through the renames, and copies
+    /// if asked for, that these settings find, like `log --follow`. History
+    /// is not simplified then, and merges are left out.
+    pub follow: Option<Renames>,
+    /// Keep only commits made at or after this time, and stop following
+    /// history at older ones.
+    pub since: Option<i64>,
+    /// Keep only commits made at or before this time.
+    pub until: Option<i64>,
+    /// Keep only commits whose author matches one of these.
+    pub author: Vec<Regex>,
+    /// Keep only commits whose message matches one of these.
+    pub grep: Vec<Regex>,
+    /// Keep only commits other than merges whose changes the search finds.
+    pub pickaxe: Option<Pickaxe>,
+}
+
+/// The commits a walk found.
+#[derive(Debug, Clone, Default)]
+pub struct Walk {
+    /// The commits in the order asked for.
+    pub commits: Vec<String>,
+    /// The parents of each commit that are part of the walk, leaving out
+    /// excluded ones and, with `first_parent`, all but the first. Commits
+    /// left out by a filter are skipped over to the kept ones behind them.
+    pub parents: HashMap<String, Vec<String>>,
+}
+
+// a commit in the walk, with its parents as far as the walk follows them
+struct Node {
+    time: i64,
+    parents: Vec<String>,
+    uninteresting: bool,
+    // whether the commit passed the filters, once the walk reached it
+    shown: Option<bool>,
+}
+
+/// The commits reachable from the included `tips` but from none of the
+/// excluded ones.
+pub fn commits(repo: &Repository, tips: &[Tip], options: &WalkOptions) -> Result<Walk> {
+    // the plain walk can stop as soon as it has found enough commits
+    let limit = match options {
+        WalkOptions {
+            order: Order::Walk,
+            reverse: false,
+            ancestry_path: false,
+            max_count: Some(count),
+            skip,
+            ..
+        } if tips.iter().all(|tip| !tip.excluded) => Some(skip + count),
+        _ => None,
+    };
+
+    let mut nodes = HashMap::<String, Node>::new();
+    let mut queue = BinaryHeap::new();
+    let mut sequence = 0;
+    let mut push = |queue: &mut BinaryHeap<_>, time: i64, oid: String| {
+        queue.push((time, Reverse(sequence), oid));
+        sequence += 1;
+    };
+
+    for tip in tips {
+        let oid = revision::peel(repo, &tip.oid, "commit")?;
+        match nodes.get_mut(&oid) {
+            Some(node) => node.uninteresting |= tip.excluded,
+            None => {
+                let node = load(repo, &oid, options, tip.excluded)?;
+                push(&mut queue, node.time, oid.clone());
+                nodes.insert(oid, node);
+            }
+        }
+    }
+
+    let mut list = vec![];
+    let mut shown = 0;
+    let mut slop = SLOP;
+    // the paths being looked for, which --follow changes at each rename
+    let mut paths = options.paths.clone();
+    while let Some((_, _, oid)) = queue.pop() {
+        let uninteresting = nodes[&oid].uninteresting;
+        // history older than --since is not followed
+        let too_old = options.since.is_some_and(|since| nodes[&oid].time < since);
+        let mut treesame = false;
+        // with --follow, how the commit changed the followed file
+        let mut followed = None;
+        if !uninteresting && !too_old && !paths.is_empty() {
+            treesame = match &options.follow {
+                Some(renames) => {
+                    followed = follow(repo, &nodes, &oid, &mut paths, renames)?;
+                    followed.is_none()
+                }
+                None => simplify(repo, &mut nodes, &oid, &paths)?,
+            };
+        }
+
+        if !too_old || uninteresting {
+            for parent in nodes[&oid].parents.clone() {
+                match nodes.get(&parent) {
+                    Some(_) if uninteresting => mark_uninteresting(&mut nodes, &parent),
+                    Some(_) => {}
+                    None => {
+                        let node = load(repo, &parent, options, uninteresting)?;
+                        push(&mut queue, node.time, parent.clone());
+                        nodes.insert(parent, node);
+                    }
+                }
+            }
+        }
+
+        if !uninteresting {
+            let keep =
+                !too_old && !treesame && matches(repo, &oid, options, &paths, followed.as_deref())?;
+            nodes.get_mut(&oid).unwrap().shown = Some(keep);
+            list.push(oid);
+            shown += usize::from(keep);
+            if limit.is_some_and(|limit| shown >= limit) {
+                break;
+            }
+        }
+        // whatever is left can only lead to excluded history
+        if queue.iter().any(|(_, _, oid)| !nodes[oid].uninteresting) {
+            slop = SLOP;
+        } else {
+            slop -= 1;
+            if slop == 0 {
+                break;
+            }
+        }
+    }
+    list.retain(|oid| !nodes[oid].uninteresting);
+
+    if options.ancestry_path {
+        let bottoms = tips
+            .iter()
+            .filter(|tip| tip.excluded)
+            .map(|tip| revision::peel(repo, &tip.oid, "commit"))
+            .collect::<Result<HashSet<_>>>()?;
+        let on_path = ancestry_path(&nodes, &sort(&nodes, list.clone(), Order::Topo), &bottoms);
+        list.retain(|oid| {
+            let keep = on_path.contains(oid);
+            if !keep {
+                nodes.get_mut(oid).unwrap().shown = Some(false);
+            }
+            keep
+        });
+    }
+    if options.order != Order::Walk {
+        list = sort(&nodes, list, options.order);
+    }
+    list.retain(|oid| nodes[oid].shown == Some(true));
+
+    let mut commits = list
+        .into_iter()
+        .skip(options.skip)
+        .take(options.max_count.unwrap_or(usize::MAX))
+        .collect::<Vec<_>>();
+    if options.reverse {
+        commits.reverse();
+    }
+
+    let parents = commits
+        .iter()
+        .map(|oid| (oid.clone(), shown_parents(&nodes, oid)))
+        .collect();
+    Ok(Walk { commits, parents })
+}
+
+fn load(repo: &Repository, oid: &str, options: &WalkOptions, uninteresting: bool) -> Result<Node> {
+    let commit = data::get_commit(repo, oid)?;
+    let mut parents = commit.parents.clone();
+    if options.first_parent {
+        parents.truncate(1);
+    }
+
+    Ok(Node {
+        time: base::commit_time(&commit),
+        parents,
+        uninteresting,
+        shown: None,
+    })
+}
+
+// Compares a commit with its parents under `paths` and returns whether it
+// left them as they were. When it has them as one of its parents had them,
+// the walk only follows that parent, like git's default history
+// simplification. Parents already excluded are not followed this way, so
+// a merge keeps its other branches.
+fn simplify(
+    repo: &Repository,
+    nodes: &mut HashMap<String, Node>,
+    oid: &str,
+    paths: &[String],
+) -> Result<bool> {
+    let tree = data::get_commit(repo, oid)?.tree;
+    let parents = nodes[oid].parents.clone();
+    if parents.is_empty() {
+        return Ok(!diff::tree_changed(repo, None, Some(&tree), paths)?);
+    }
+
+    let mut changed_from_any = false;
+    for parent in parents {
+        let parent_tree = data::get_commit(repo, &parent)?.tree;
+        let changed = diff::tree_changed(repo, Some(&parent_tree), Some(&tree), paths)?;
+        let interesting = nodes.get(&parent).is_none_or(|node| !node.uninteresting);
+        if !changed && interesting {
+            nodes.get_mut(oid).unwrap().parents = vec![parent];
+            return Ok(true);
+        }
+        changed_from_any |= changed;
+    }
+
+    Ok(!changed_from_any)
+}
+
+// How a commit changed the followed path, or None if it left it as its
+// parent had it. Unlike simplify, no parents are left out; as with git's
+// --follow, merges count as unchanged. A commit that added the path may
+// turn out to have renamed or copied it from another file, which older
+// commits are then searched for instead, and the change is then from that
+// file.
+fn follow(
+    repo: &Repository,
+    nodes: &HashMap<String, Node>,
+    oid: &str,
+    paths: &mut Vec<String>,
+    renames: &Renames,
+) -> Result<Option<Vec<TreeChange>>> {
+    let parent_tree = match &nodes[oid].parents[..] {
+        [] => None,
+        [parent] => Some(data::get_commit(repo, parent)?.tree),
+        _ => return Ok(None),
+    };
+    let tree = data::get_commit(repo, oid)?.tree;
+    if !diff::tree_changed(repo, parent_tree.as_deref(), Some(&tree), paths)? {
+        return Ok(None);
+    }
+
+    let old = diff::read_source(repo, &Source::Tree(parent_tree))?;
+    let new = diff::read_source(repo, &Source::Tree(Some(tree)))?;
+    let path = paths[0].clone();
+    if let Some(source) = diff::find_source(repo, &old, &new, &path, renames)? {
+        *paths = vec![source];
+    }
+
+    let side = |files: &TreeFiles, from: &str| -> TreeFiles {
+        files
+            .get(from)
+            .map(|entry| (path.clone(), entry.clone()))
+            .into_iter()
+            .collect()
+    };
+    Ok(Some(diff::diff_files(
+        &side(&old, &paths[0]),
+        &side(&new, &path),
+    )))
+}
+
+// Whether a commit passes the filters on its date, author, message and
+// changes under `paths`, or with --follow the `followed` changes.
+fn matches(
+    repo: &Repository,
+    oid: &str,
+    options: &WalkOptions,
+    paths: &[String],
+    followed: Option<&[TreeChange]>,
+) -> Result<bool> {
+    if options.until.is_none()
+        && options.author.is_empty()
+        && options.grep.is_empty()
+        && options.pickaxe.is_none()
+    {
+        return Ok(true);
+    }
+
+    let commit = data::get_commit(repo, oid)?;
+    if options
+        .until
+        .is_some_and(|until| base::commit_time(&commit) > until)
+    {
+        return Ok(false);
+    }
+    if !options.author.is_empty() {
+        let author = commit
+            .author
+            .as_ref()
+            .map(|a| format!("{} <{}>", a.name, a.email))
+            .unwrap_or_default();
+        if !options.author.iter().any(|regex| regex.is_match(&author)) {
+            return Ok(false);
+        }
+    }
+    // like git, messages are searched line by line
+    let found = |regex: &Regex| commit.message.lines().any(|line| regex.is_match(line));
+    if !options.grep.is_empty() && !options.grep.iter().any(found) {
+        return Ok(false);
+    }
+
+    if let Some(pickaxe) = &options.pickaxe {
+        // like git, merges are not searched
+        if commit.parents.len() > 1 {
+            return Ok(false);
+        }
+        let changes = match followed {
+            Some(changes) => changes.to_vec(),
+            None => diff::compare_trees(repo, oid, paths, &Renames::default())?,
+        };
+        return pickaxe.matches(repo, &changes);
+    }
+
+    Ok(true)
+}
+
+// The parents of a listed commit as far as the walk shows them: commits
+// the filters left out are skipped over to the ones behind them.
+fn shown_parents(nodes: &HashMap<String, Node>, oid: &str) -> Vec<String> {
+    let mut parents = vec![];
+    let mut seen = HashSet::new();
+    let mut stack = nodes[oid].parents.iter().rev().cloned().collect::<Vec<_>>();
+    while let Some(parent) = stack.pop() {
+        if !seen.insert(parent.clone()) {
+            continue;
+        }
+        let Some(node) = nodes.get(&parent) else {
+            continue;
+        };
+        match (node.uninteresting, node.shown) {
+            (true, _) => {}
+            // listed, or beyond where the walk stopped
+            (false, Some(true) | None) => parents.push(parent),
+            (false, Some(false)) => stack.extend(node.parents.iter().rev().cloned()),
+        }
+    }
+    parents
+}
+
+// excluding a commit also excludes the ancestors the walk already reached
+fn mark_uninteresting(nodes: &mut HashMap<String, Node>, oid: &str) {
+    let mut stack = vec![oid.to_string()];
+    while let Some(oid) = stack.pop() {
+        if let Some(node) = nodes.get_mut(&oid) {
+            if !node.uninteresting {
+                node.uninteresting = true;
+                stack.extend(node.parents.iter().cloned());
+            }
+        }
+    }
+}
+
+// Orders `list` so that children come before their parents. Commits that
+// become ready are taken newest first for `Order::Date`, or last-ready
+// first for `Order::Topo` so a branch is finished before the next starts.
+fn sort(nodes: &HashMap<String, Node>, list: Vec<String>, order: Order) -> Vec<String> {
+    let mut children = HashMap::<&str, usize>::new();
+    for oid in &list {
+        children.entry(oid).or_default();
+    }
+    for oid in &list {
+        for parent in &nodes[oid].parents {
+            if let Some(count) = children.get_mut(parent.as_str()) {
+                *count += 1;
+            }
+        }
+    }
+
+    let mut ready = BinaryHeap::new();
+    let mut stack = vec![];
+    for (i, oid) in list.iter().enumerate() {
+        if children[oid.as_str()] == 0 {
+            ready.push((nodes[oid].time, Reverse(i), oid.clone()));
+            stack.push(oid.clone());
+        }
+    }
+    stack.reverse();
+
+    let mut sorted = vec![];
+    let mut sequence = list.len();
+    loop {
+        let oid = match order {
+            Order::Topo => stack.pop(),
+            _ => ready.pop().map(|(_, _, oid)| oid),
+        };
+        let Some(oid) = oid else {
+            break;
+        };
+
+        for parent in &nodes[&oid].parents {
+            let Some(count) = children.get_mut(parent.as_str()) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                ready.push((nodes[parent].time, Reverse(sequence), parent.clone()));
+                stack.push(parent.clone());
+                sequence += 1;
+            }
+        }
+        sorted.push(oid);
+    }
+
+    sorted
+}
+
+// the commits of `sorted`, which lists children first, that have an
+// excluded commit among their ancestors
+fn ancestry_path(
+    nodes: &HashMap<String, Node>,
+    sorted: &[String],
+    bottoms: &HashSet<String>,
+) -> HashSet<String> {
+    let mut on_path = HashSet::new();
+    for oid in sorted.iter().rev() {
+        let parents = &nodes[oid].parents;
+        if parents
+            .iter()
+            .any(|parent| bottoms.contains(parent) || on_path.contains(parent))
+        {
+            on_path.insert(oid.clone());
+        }
+    }
+
+    on_path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    //   a - b1 - b2 ------ m - n
+    //   |     \          /    /
+    //   |      c2 ------     /
+    //    \                  /
+    //     x1 ---------------
+    //
+    // c2 is dated before its parent b1, and only b* change f, c2 adds g
+    // and x1 adds h
+    struct History {
+        repo: TestRepo,
+        names: HashMap<String, &'static str>,
+        tip: String,
+    }
+
+    fn history() -> History {
+        let repo = TestRepo::new();
+        let mut names = HashMap::new();
+        let mut commit =
+            |name: &'static str, time: i64, parents: &[&str], files: &[(&str, &str)]| {
+                let oid = repo.commit(parents, files, 1_700_000_000 + time, name);
+                names.insert(oid.clone(), name);
+                oid
+            };
+        let a = commit("a", 10, &[], &[("f", "a")]);
+        let b1 = commit("b1", 20, &[&a], &[("f", "b1")]);
+        let b2 = commit("b2", 40, &[&b1], &[("f", "b2")]);
+        let c2 = commit("c2", 15, &[&b1], &[("f", "b1"), ("g", "c2")]);
+        let m = commit("m", 60, &[&b2, &c2], &[("f", "b2"), ("g", "c2")]);
+        let x1 = commit("x1", 50, &[&a], &[("f", "a"), ("h", "x1")]);
+        let n = commit(
+            "n",
+            70,
+            &[&m, &x1],
+            &[("f", "b2"), ("g", "c2"), ("h", "x1")],
+        );
+
+        History {
+            repo,
+            names,
+            tip: n,
+        }
+    }
+
+    impl History {
+        fn walk(&self, tips: &[Tip], options: &WalkOptions) -> Vec<&'static str> {
+            let walk = commits(&self.repo, tips, options).unwrap();
+            walk.commits.iter().map(|oid| self.names[oid]).collect()
+        }
+
+        fn log(&self, options: &WalkOptions) -> Vec<&'static str> {
+            self.walk(&[self.tip(false)], options)
+        }
+
+        fn tip(&self, excluded: bool) -> Tip {
+            Tip {
+                oid: self.tip.clone(),
+                excluded,
+            }
+        }
+
+        fn named(&self, name: &str, excluded: bool) -> Tip {
+            let oid = self.names.iter().find(|(_, n)| **n == name).unwrap().0;
+            Tip {
+                oid: oid.clone(),
+                excluded,
+            }
+        }
+    }
+
+    fn ordered(order: Order) -> WalkOptions {
+        WalkOptions {
+            order,
+            ..WalkOptions::default()
+        }
+    }
+
+    fn limited(order: Order, paths: &[&str]) -> WalkOptions {
+        WalkOptions {
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            ..ordered(order)
+        }
+    }
+
+    #[test]
+    fn orders() {
+        let history = history();
+        assert_eq!(
+            history.log(&ordered(Order::Walk)),
+            ["n", "m", "x1", "b2", "b1", "c2", "a"]
+        );
+        assert_eq!(
+            history.log(&ordered(Order::Date)),
+            ["n", "m", "x1", "b2", "c2", "b1", "a"]
+        );
+        assert_eq!(
+            history.log(&ordered(Order::Topo)),
+            ["n", "x1", "m", "c2", "b2", "b1", "a"]
+        );
+    }
+
+    #[test]
+    fn reverse_first_parent_and_ranges() {
+        let history = history();
+        let reverse = WalkOptions {
+            reverse: true,
+            ..ordered(Order::Topo)
+        };
+        assert_eq!(
+            history.log(&reverse),
+            ["a", "b1", "b2", "c2", "m", "x1", "n"]
+        );
+
+        let first_parent = WalkOptions {
+            first_parent: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(history.log(&first_parent), ["n", "m", "b2", "b1", "a"]);
+
+        let range = [history.tip(false), history.named("x1", true)];
+        assert_eq!(
+            history.walk(&range, &WalkOptions::default()),
+            ["n", "m", "b2", "b1", "c2"]
+        );
+        let ancestry = WalkOptions {
+            ancestry_path: true,
+            ..WalkOptions::default()
+        };
+        let range = [history.tip(false), history.named("b1", true)];
+        assert_eq!(history.walk(&range, &ancestry), ["n", "m", "b2", "c2"]);
+    }
+
+    #[test]
+    fn excluded_history_is_walked_past_clock_skew() {
+        //   a - x
+        //    \
+        //     s - y
+        //
+        // s is dated before a, so a is listed before s can exclude it
+        let repo = TestRepo::new();
+        let a = repo.commit(&[], &[("f", "a")], 1_700_000_040, "a");
+        let s = repo.commit(&[&a], &[("f", "s")], 1_700_000_030, "s");
+        let y = repo.commit(&[&s], &[("f", "y")], 1_700_000_060, "y");
+        let x = repo.commit(&[&a], &[("f", "x")], 1_700_000_050, "x");
+        let tips = [
+            Tip {
+                oid: x.clone(),
+                excluded: false,
+            },
+            Tip {
+                oid: y,
+                excluded: true,
+            },
+        ];
+        let walk = commits(&repo, &tips, &WalkOptions::default()).unwrap();
+        assert_eq!(walk.commits, [x]);
+    }
+
+    #[test]
+    fn path_limiting_simplifies_history() {
+        let history = history();
+        for order in [Order::Walk, Order::Topo] {
+            assert_eq!(history.log(&limited(order, &["f"])), ["b2", "b1", "a"]);
+            assert_eq!(history.log(&limited(order, &["g"])), ["c2"]);
+            assert_eq!(history.log(&limited(order, &["h"])), ["x1"]);
+        }
+        // a merge differing from each parent is kept, with both sides
+        assert_eq!(
+            history.log(&limited(Order::Walk, &["f", "g"])),
+            ["m", "b2", "b1", "c2", "a"]
+        );
+        assert_eq!(
+            history.log(&limited(Order::Topo, &["f", "g"])),
+            ["m", "c2", "b2", "b1", "a"]
+        );
+    }
+
+    #[test]
+    fn simplified_parents() {
+        let history = history();
+        let walk = commits(
+            &history.repo,
+            &[history.tip(false)],
+            &limited(Order::Topo, &["f", "g"]),
+        )
+        .unwrap();
+        let parents = |name: &str| -> Vec<&str> {
+            let oid = &history.named(name, false).oid;
+            walk.parents[oid].iter().map(|p| history.names[p]).collect()
+        };
+        assert_eq!(parents("m"), ["b2", "c2"]);
+        assert_eq!(parents("c2"), ["b1"]);
+        assert_eq!(parents("a"), Vec::<&str>::new());
+
+        let walk = commits(
+            &history.repo,
+            &[history.tip(false)],
+            &limited(Order::Walk, &["g"]),
+        )
+        .unwrap();
+        assert_eq!(
+            walk.parents[&history.named("c2", false).oid],
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,1591 @@
+//! Line diffs of blob contents, printed as unified hunks like `git diff`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// How matching lines are chosen when several alignments are equally short.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    /// Anchors on lines that occur once on each side, which keeps
+    /// reordered blocks of code together.
+    Patience,
+    /// Like patience, but anchors on the rarest lines even if they repeat.
+    Histogram,
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Algorithm, Error> {
+        match s.to_lowercase().as_str() {
+            "myers" | "default" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => Err(Error::BadConfig(format!("unknown diff algorithm '{s}'"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Unchanged lines shown around each change.
+    pub context: usize,
+    pub algorithm: Algorithm,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            context: 3,
+            algorithm: Algorithm::Myers,
+        }
+    }
+}
+
+/// One step turning the old lines into the new ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// Old line `.0` is kept as new line `.1`.
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Splits `data` into lines, each keeping its trailing newline.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Git treats blobs with a NUL byte near the start as binary.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// The edits turning `old` into `new`, in order, with deletions before
+/// insertions wherever both happen between the same unchanged lines.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm) -> Vec<Edit> {
+    let mut edits = vec![];
+    diff_range(old, new, 0..old.len(), 0..new.len(), algorithm, &mut edits);
+
+    // changed lines of each side, with an unchanged line before and after
+    let mut deleted = vec![false; old.len() + 2];
+    let mut inserted = vec![false; new.len() + 2];
+    for edit in edits {
+        match edit {
+            Edit::Delete(i) => deleted[i + 1] = true,
+            Edit::Insert(j) => inserted[j + 1] = true,
+            Edit::Equal(..) => {}
+        }
+    }
+    compact_changes(old, &mut deleted, &inserted);
+    compact_changes(new, &mut inserted, &deleted);
+
+    let mut edits = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if deleted[i + 1] {
+            edits.push(Edit::Delete(i));
+            i += 1;
+        } else if inserted[j + 1] {
+            edits.push(Edit::Insert(j));
+            j += 1;
+        } else {
+            edits.push(Edit::Equal(i, j));
+            (i, j) = (i + 1, j + 1);
+        }
+    }
+    edits
+}
+
+/// A run of changed lines, `start..end` counting from 1 like the padded
+/// change flags, and possibly empty.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+/// Slides each group of changed lines of one side down as far as lines
+/// equal to it allow, or to line up with a change on the other side, the
+/// way git's `xdl_change_compact` does. Where several placements describe
+/// the same change, this picks the one git shows.
+fn compact_changes(lines: &[&[u8]], changed: &mut [bool], other: &[bool]) {
+    let count = lines.len();
+    let line = |n: usize| lines[n - 1];
+
+    let first = |changed: &[bool]| {
+        let mut end = 1;
+        while changed[end] {
+            end += 1;
+        }
+        Group { start: 1, end }
+    };
+    let next = |changed: &[bool], g: &mut Group, count: usize| {
+        if g.end == count + 1 {
+            return false;
+        }
+        g.start = g.end + 1;
+        g.end = g.start;
+        while changed[g.end] {
+            g.end += 1;
+        }
+        true
+    };
+    let previous = |changed: &[bool], g: &mut Group| {
+        if g.start == 1 {
+            return false;
+        }
+        g.end = g.start - 1;
+        g.start = g.end;
+        while changed[g.start - 1] {
+            g.start -= 1;
+        }
+        true
+    };
+    let slide_down = |changed: &mut [bool], g: &mut Group| {
+        if g.end > count || line(g.start) != line(g.end) {
+            return false;
+        }
+        changed[g.start] = false;
+        changed[g.end] = true;
+        g.start += 1;
+        g.end += 1;
+        while changed[g.end] {
+            g.end += 1;
+        }
+        true
+    };
+    let slide_up = |changed: &mut [bool], g: &mut Group| {
+        if g.start == 1 || line(g.start - 1) != line(g.end - 1) {
+            return false;
+        }
+        g.start -= 1;
+        g.end -= 1;
+        changed[g.start] = true;
+        changed[g.end] = false;
+        while changed[g.start - 1] {
+            g.start -= 1;
+        }
+        true
+    };
+    let other_count = other.len() - 2;
+
+    let mut g = first(changed);
+    let mut go = first(other);
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = g.end - g.start;
+                end_matching_other = None;
+
+                while slide_up(changed, &mut g) {
+                    previous(other, &mut go);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+
+                while slide_down(changed, &mut g) {
+                    next(other, &mut go, other_count);
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+
+                // sliding can merge the group with its neighbours; go again if so
+                if size == g.end - g.start {
+                    break;
+                }
+            }
+
+            // line the group up with the last change on the other side it can reach
+            if g.end != earliest_end && end_matching_other.is_some() {
+                while go.end == go.start {
+                    slide_up(changed, &mut g);
+                    previous(other, &mut go);
+                }
+            }
+        }
+
+        if !next(changed, &mut g, count) {
+            break;
+        }
+        next(other, &mut go, other_count);
+    }
+}
+
+type Range = std::ops::Range<usize>;
+
+fn diff_range(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    a: Range,
+    b: Range,
+    algorithm: Algorithm,
+    edits: &mut Vec<Edit>,
+) {
+    match algorithm {
+        Algorithm::Myers => myers(old, new, a, b, edits),
+        Algorithm::Patience => patience(old, new, a, b, edits),
+        Algorithm::Histogram => histogram(old, new, a, b, edits),
+    }
+}
+
+// every line of the ranges changed, deletions first
+fn push_changed(a: Range, b: Range, edits: &mut Vec<Edit>) {
+    edits.extend(a.map(Edit::Delete));
+    edits.extend(b.map(Edit::Insert));
+}
+
+/// Myers' O(ND) algorithm in its linear space form: find the middle snake
+/// of a shortest edit script by searching from both ends at once, then
+/// diff the boxes before and after it, like git's `xdl_recs_cmp`. Edit
+/// scripts that grow too costly settle for a good enough split instead of
+/// the shortest, the way git's heuristics do.
+fn myers(old: &[&[u8]], new: &[&[u8]], a: Range, b: Range, edits: &mut Vec<Edit>) {
+    // lines are compared by number rather than by contents
+    let mut ids = HashMap::new();
+    let mut id = |line: &[u8]| {
+        let next = ids.len();
+        *ids.entry(line.to_vec()).or_insert(next)
+    };
+    let old_ids = old[a.clone()]
+        .iter()
+        .map(|line| id(line))
+        .collect::<Vec<_>>();
+    let new_ids = new[b.clone()]
+        .iter()
+        .map(|line| id(line))
+        .collect::<Vec<_>>();
+
+    // the common ends are kept, and lines git keeps out of the search
+    // are changed without taking part in it
+    let (mut start, mut end) = (0, 0);
+    while start < old_ids.len().min(new_ids.len()) && old_ids[start] == new_ids[start] {
+        start += 1;
+    }
+    while end < old_ids.len().min(new_ids.len()) - start
+        && old_ids[old_ids.len() - 1 - end] == new_ids[new_ids.len() - 1 - end]
+    {
+        end += 1;
+    }
+    let mut deleted = vec![false; old_ids.len()];
+    let mut inserted = vec![false; new_ids.len()];
+    let old_kept = searched_lines(&old_ids, &new_ids, start..old_ids.len() - end, &mut deleted);
+    let new_kept = searched_lines(
+        &new_ids,
+        &old_ids,
+        start..new_ids.len() - end,
+        &mut inserted,
+    );
+
+    let old_searched = old_kept.iter().map(|&i| old_ids[i]).collect::<Vec<_>>();
+    let new_searched = new_kept.iter().map(|&j| new_ids[j]).collect::<Vec<_>>();
+    let (n, m) = (old_searched.len(), new_searched.len());
+    let mut search = Search {
+        old: &old_searched,
+        new: &new_searched,
+        forward: vec![0; n + m + 3],
+        backward: vec![0; n + m + 3],
+        offset: m as isize + 1,
+        max_cost: bogosqrt(n + m + 3).max(MAX_COST_MIN),
+        deleted: vec![false; n],
+        inserted: vec![false; m],
+    };
+    search.compare(0, n as isize, 0, m as isize, false);
+    for (k, &i) in old_kept.iter().enumerate() {
+        deleted[i] = search.deleted[k];
+    }
+    for (k, &j) in new_kept.iter().enumerate() {
+        inserted[j] = search.inserted[k];
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < deleted.len() || j < inserted.len() {
+        if i < deleted.len() && deleted[i] {
+            edits.push(Edit::Delete(a.start + i));
+            i += 1;
+        } else if j < inserted.len() && inserted[j] {
+            edits.push(Edit::Insert(b.start + j));
+            j += 1;
+        } else {
+            edits.push(Edit::Equal(a.start + i, b.start + j));
+            (i, j) = (i + 1, j + 1);
+        }
+    }
+}
+
+/// The lines of `range` the Myers search looks at, like git's
+/// `xdl_cleanup_records`. Lines `other` lacks can only be changes, and
+/// lines it has many copies of are left out when they sit among such
+/// lines; both are marked in `changed` instead.
+fn searched_lines(
+    lines: &[usize],
+    other: &[usize],
+    range: Range,
+    changed: &mut [bool],
+) -> Vec<usize> {
+    const MAX_EQUAL_LIMIT: usize = 1024;
+
+    let mut counts = HashMap::new();
+    for line in other {
+        *counts.entry(line).or_insert(0) += 1;
+    }
+    let limit = (bogosqrt(lines.len()) as usize).min(MAX_EQUAL_LIMIT);
+    let matches = range
+        .clone()
+        .map(|i| match counts.get(&lines[i]).copied().unwrap_or(0) {
+            0 => Matches::None,
+            count if count >= limit => Matches::Many,
+            _ => Matches::Some,
+        })
+        .collect::<Vec<_>>();
+
+    let mut kept = vec![];
+    for (k, i) in range.enumerate() {
+        let keep = match matches[k] {
+            Matches::None => false,
+            Matches::Some => true,
+            Matches::Many => !among_unmatched(&matches, k),
+        };
+        if keep {
+            kept.push(i);
+        } else {
+            changed[i] = true;
+        }
+    }
+    kept
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Matches {
+    None,
+    Some,
+    Many,
+}
+
+// git's `xdl_clean_mmatch`: whether the line at `k`, with many matches,
+// sits in a run of unmatched and many-matched lines that is mostly
+// unmatched, with unmatched lines on both sides
+fn among_unmatched(matches: &[Matches], k: usize) -> bool {
+    const WINDOW: usize = 100;
+    const KEPT_RUN: usize = 4;
+
+    let count_run = |run: &mut dyn Iterator<Item = &Matches>| {
+        let (mut none, mut many) = (0, 1);
+        for m in run {
+            match m {
+                Matches::None => none += 1,
+                Matches::Many => many += 1,
+                Matches::Some => break,
+            }
+        }
+        (none, many)
+    };
+    let before = &matches[k.saturating_sub(WINDOW)..k];
+    let (none_before, many_before) = count_run(&mut before.iter().rev());
+    if none_before == 0 {
+        return false;
+    }
+    let after = &matches[k + 1..matches.len().min(k + 1 + WINDOW)];
+    let (none_after, many_after) = count_run(&mut after.iter());
+    if none_after == 0 {
+        return false;
+    }
+
+    let (none, many) = (none_before + none_after, many_before + many_after);
+    many * KEPT_RUN < many + none
+}
+
+// git's limits on how long the search may run before taking a shortcut
+const MAX_COST_MIN: isize = 256;
+const HEUR_MIN_COST: isize = 256;
+const SNAKE_CNT: isize = 20;
+const K_HEUR: isize = 4;
+
+// roughly the square root of `n`, as git estimates it
+fn bogosqrt(mut n: usize) -> isize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Where `Search::split` divides a box, and whether each half must be
+/// diffed minimally or may use the heuristics again.
+struct Split {
+    i1: isize,
+    i2: isize,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+/// The state of one `myers` diff: the furthest reaching paths on each
+/// diagonal, searching forward and backward, indexed by diagonal plus
+/// `offset`, and the lines found changed.
+struct Search<'a> {
+    old: &'a [usize],
+    new: &'a [usize],
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+    deleted: Vec<bool>,
+    inserted: Vec<bool>,
+}
+
+impl Search<'_> {
+    fn compare(
+        &mut self,
+        mut off1: isize,
+        mut lim1: isize,
+        mut off2: isize,
+        mut lim2: isize,
+        need_min: bool,
+    ) {
+        let (old, new) = (self.old, self.new);
+        while off1 < lim1 && off2 < lim2 && old[off1 as usize] == new[off2 as usize] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && old[lim1 as usize - 1] == new[lim2 as usize - 1] {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            self.inserted[off2 as usize..lim2 as usize].fill(true);
+        } else if off2 == lim2 {
+            self.deleted[off1 as usize..lim1 as usize].fill(true);
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+            self.compare(off1, split.i1, off2, split.i2, split.min_lo);
+            self.compare(split.i1, lim1, split.i2, lim2, split.min_hi);
+        }
+    }
+
+    // git's xdl_split: the diagonals are extended one step of edit cost at
+    // a time from the top left and the bottom right until the two searches
+    // overlap
+    fn split(
+        &mut self,
+        off1: isize,
+        lim1: isize,
+        off2: isize,
+        lim2: isize,
+        need_min: bool,
+    ) -> Split {
+        let (old, new) = (self.old, self.new);
+        let at = |d: isize| (d + self.offset) as usize;
+        let (kvdf, kvdb) = (&mut self.forward, &mut self.backward);
+        let same = |i1: isize, i2: isize| old[i1 as usize] == new[i2 as usize];
+
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax, mut bmin, mut bmax) = (fmid, fmid, bmid, bmid);
+        kvdf[at(fmid)] = off1;
+        kvdb[at(bmid)] = lim1;
+
+        let mut ec = 0;
+        loop {
+            ec += 1;
+            let mut got_snake = false;
+
+            // widen the range of diagonals by one, turning back at the
+            // edges of the box; the ones just outside it are never taken
+            if fmin > dmin {
+                fmin -= 1;
+                kvdf[at(fmin - 1)] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                kvdf[at(fmax + 1)] = -1;
+            } else {
+                fmax -= 1;
+            }
+
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if kvdf[at(d - 1)] >= kvdf[at(d + 1)] {
+                    kvdf[at(d - 1)] + 1
+                } else {
+                    kvdf[at(d + 1)]
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && same(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                kvdf[at(d)] = i1;
+                if odd && bmin <= d && d <= bmax && kvdb[at(d)] <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                kvdb[at(bmin - 1)] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                kvdb[at(bmax + 1)] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if kvdb[at(d - 1)] < kvdb[at(d + 1)] {
+                    kvdb[at(d - 1)]
+                } else {
+                    kvdb[at(d + 1)] - 1
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && same(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                kvdb[at(d)] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= kvdf[at(d)] {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if need_min {
+                continue;
+            }
+
+            // once the script is costly, a diagonal that got far from its
+            // corner on a long enough snake is a good enough place to split
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = 0;
+                let mut split = None;
+                let mut d = fmax;
+                while d >= fmin {
+                    let dd = (d - fmid).abs();
+                    let i1 = kvdf[at(d)];
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 + SNAKE_CNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_CNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_CNT).all(|k| same(i1 - k, i2 - k))
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((i1, i2)) = split {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: false,
+                    };
+                }
+
+                let mut d = bmax;
+                while d >= bmin {
+                    let dd = (d - bmid).abs();
+                    let i1 = kvdb[at(d)];
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_CNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_CNT
+                        && (0..SNAKE_CNT).all(|k| same(i1 + k, i2 + k))
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((i1, i2)) = split {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: false,
+                        min_hi: true,
+                    };
+                }
+            }
+
+            // enough is enough: split at whichever search got furthest
+            if ec >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = kvdf[at(d)].min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = kvdb[at(d)].max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_lo: true,
+                        min_hi: false,
+                    }
+                } else {
+                    Split {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_lo: false,
+                        min_hi: true,
+                    }
+                };
+            }
+        }
+    }
+}
+
+/// Anchors on the lines occurring exactly once on each side, as many of
+/// them as can be matched in order, and diffs the stretches between them
+/// like git's patience diff. Matching lines next to an anchor are kept with
+/// it; a range without anchors is left to Myers.
+fn patience(old: &[&[u8]], new: &[&[u8]], mut a: Range, mut b: Range, edits: &mut Vec<Edit>) {
+    if a.is_empty() || b.is_empty() {
+        return push_changed(a, b, edits);
+    }
+    let anchors = patience_anchors(old, new, &a, &b);
+    if anchors.is_empty() {
+        return myers(old, new, a, b, edits);
+    }
+
+    let ends = anchors.into_iter().map(Some).chain([None]);
+    for anchor in ends {
+        // grow the anchor backward, then the last one forward
+        let (mut x, mut y) = anchor.unwrap_or((a.end, b.end));
+        if anchor.is_some() {
+            while x > a.start && y > b.start && old[x - 1] == new[y - 1] {
+                (x, y) = (x - 1, y - 1);
+            }
+        }
+        while a.start < x && b.start < y && old[a.start] == new[b.start] {
+            edits.push(Edit::Equal(a.start, b.start));
+            (a.start, b.start) = (a.start + 1, b.start + 1);
+        }
+        patience(old, new, a.start..x, b.start..y, edits);
+
+        if let Some((anchor_x, anchor_y)) = anchor {
+            edits.extend(
+                (x..=anchor_x)
+                    .zip(y..=anchor_y)
+                    .map(|(i, j)| Edit::Equal(i, j)),
+            );
+            (a.start, b.start) = (anchor_x + 1, anchor_y + 1);
+        }
+    }
+}
+
+/// Lines occurring exactly once in both ranges, reduced to their longest
+/// increasing sequence so they can all be matched in order.
+fn patience_anchors(old: &[&[u8]], new: &[&[u8]], a: &Range, b: &Range) -> Vec<(usize, usize)> {
+    let mut counts: HashMap<&[u8], (usize, usize, usize)> = HashMap::new();
+    for i in a.clone() {
+        counts.entry(old[i]).or_default().0 += 1;
+    }
+    for j in b.clone() {
+        if let Some(entry) = counts.get_mut(new[j]) {
+            entry.1 += 1;
+            entry.2 = j;
+        }
+    }
+    let unique = a
+        .clone()
+        .filter_map(|i| match counts[old[i]] {
+            (1, 1, j) => Some((i, j)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // patience sorting on the new positions, in order of the old ones
+    let mut piles: Vec<usize> = vec![];
+    let mut back: Vec<Option<usize>> = vec![None; unique.len()];
+    for (n, &(_, j)) in unique.iter().enumerate() {
+        let pile = piles.partition_point(|&top| unique[top].1 < j);
+        if pile > 0 {
+            back[n] = Some(piles[pile - 1]);
+        }
+        if pile == piles.len() {
+            piles.push(n);
+        } else {
+            piles[pile] = n;
+        }
+    }
+
+    let mut anchors = vec![];
+    let mut next = piles.last().copied();
+    while let Some(n) = next {
+        anchors.push(unique[n]);
+        next = back[n];
+    }
+    anchors.reverse();
+    anchors
+}
+
+/// Splits the ranges at the run `histogram_lcs` picks and diffs the parts
+/// before and after it the same way, like git's histogram diff. A range
+/// without such a run is left to Myers.
+fn histogram(old: &[&[u8]], new: &[&[u8]], mut a: Range, mut b: Range, edits: &mut Vec<Edit>) {
+    while !a.is_empty() && !b.is_empty() {
+        let Some((i, j, len)) = histogram_lcs(old, new, &a, &b) else {
+            return myers(old, new, a, b, edits);
+        };
+        histogram(old, new, a.start..i, b.start..j, edits);
+        edits.extend((i..i + len).zip(j..j + len).map(|(i, j)| Edit::Equal(i, j)));
+        (a.start, b.start) = (i + len, j + len);
+    }
+    push_changed(a, b, edits);
+}
+
+/// The run of lines both ranges share that git's histogram diff splits
+/// them at: every occurrence of a new line in the old range is grown into
+/// the longest run around it, and the run whose rarest line occurs least
+/// often in the old range wins, or the longer one when equally rare. Lines
+/// occurring too often are never used. Returns where the run starts in
+/// each range and its length.
+fn histogram_lcs(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    a: &Range,
+    b: &Range,
+) -> Option<(usize, usize, usize)> {
+    const MAX_OCCURRENCES: usize = 64;
+
+    let mut occurrences: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for i in a.clone() {
+        occurrences.entry(old[i]).or_default().push(i);
+    }
+    let count = |i: usize| occurrences[old[i]].len();
+
+    // start of the best run in each range, its length and rarest count
+    let mut best = None;
+    let (mut best_len, mut best_count) = (1, MAX_OCCURRENCES + 1);
+    let mut j = b.start;
+    while j < b.end {
+        let mut next = j + 1;
+        let positions = match occurrences.get(new[j]) {
+            Some(positions) if positions.len() <= best_count => positions,
+            _ => {
+                j = next;
+                continue;
+            }
+        };
+
+        let mut k = 0;
+        while k < positions.len() {
+            let (mut start_a, mut start_b) = (positions[k], j);
+            let (mut end_a, mut end_b) = (start_a + 1, j + 1);
+            let mut rarest = positions.len();
+            while start_a > a.start && start_b > b.start && old[start_a - 1] == new[start_b - 1] {
+                start_a -= 1;
+                start_b -= 1;
+                rarest = rarest.min(count(start_a));
+            }
+            while end_a < a.end && end_b < b.end && old[end_a] == new[end_b] {
+                rarest = rarest.min(count(end_a));
+                end_a += 1;
+                end_b += 1;
+            }
+
+            next = next.max(end_b);
+            if best_len < end_a - start_a || rarest < best_count {
+                best = Some((start_a, start_b));
+                (best_len, best_count) = (end_a - start_a, rarest);
+            }
+            // occurrences inside this run would only find it again
+            while k < positions.len() && positions[k] < end_a {
+                k += 1;
+            }
+        }
+        j = next;
+    }
+
+    best.map(|(i, j)| (i, j, best_len))
+}
+
+/// A run of changes with its surrounding context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// First old line, counting from 1, and number of old lines.
+    pub old: (usize, usize),
+    pub new: (usize, usize),
+    pub edits: Vec<Edit>,
+}
+
+/// Groups `edits` into hunks with `context` unchanged lines around each
+/// change; changes closer than twice that share a hunk.
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| !matches!(e, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    let mut groups: Vec<(usize, usize)> = vec![];
+    for &i in &changes {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(edits.len());
+        match groups.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => groups.push((start, end)),
+        }
+    }
+
+    // lines of each side consumed before every edit
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut i, mut j) = (0, 0);
+    for edit in edits {
+        positions.push((i, j));
+        match edit {
+            Edit::Equal(..) => (i, j) = (i + 1, j + 1),
+            Edit::Delete(_) => i += 1,
+            Edit::Insert(_) => j += 1,
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(start, end)| {
+            let edits = edits[start..end].to_vec();
+            let (old_start, new_start) = positions[start];
+            let old_len = edits
+                .iter()
+                .filter(|e| !matches!(e, Edit::Insert(_)))
+                .count();
+            let new_len = edits
+                .iter()
+                .filter(|e| !matches!(e, Edit::Delete(_)))
+                .count();
+
+            Hunk {
+                old: (old_start + 1, old_len),
+                new: (new_start + 1, new_len),
+                edits,
+            }
+        })
+        .collect()
+}
+
+/// The last line before `line` that looks like the start of a function:
+/// one beginning with a letter, `_` or `$`, like git's default rule.
+fn function_context(lines: &[&[u8]], line: usize) -> Option<String> {
+    lines[..line.min(lines.len())]
+        .iter()
+        .rev()
+        .find(|l| {
+            l.first()
+                .is_some_and(|&c| c.is_ascii_alphabetic() || c == b'_' || c == b'$')
+        })
+        .map(|l| {
+            let text = String::from_utf8_lossy(l);
+            let text = text.trim_end();
+            // git cuts the heading at 80 bytes
+            let mut end = text.len().min(80);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text[..end].to_string()
+        })
+}
+
+/// The hunks of a unified diff between `old` and `new`, without file
+/// headers. Lines are copied byte for byte, so the patch applies to the
+/// blobs whatever their encoding.
+pub fn unified(old: &[u8], new: &[u8], options: &Options) -> Vec<u8> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let edits = diff_lines(&old_lines, &new_lines, options.algorithm);
+
+    let mut out = vec![];
+    for hunk in hunks(&edits, options.context) {
+        let range = |(start, len): (usize, usize)| match len {
+            0 => format!("{},0", start - 1),
+            1 => start.to_string(),
+            _ => format!("{start},{len}"),
+        };
+        let mut header = format!("@@ -{} +{} @@", range(hunk.old), range(hunk.new));
+        if let Some(heading) = function_context(&old_lines, hunk.old.0 - 1) {
+            let _ = write!(header, " {heading}");
+        }
+        header.push('\n');
+        out.extend_from_slice(header.as_bytes());
+
+        for edit in &hunk.edits {
+            let (sign, line) = match *edit {
+                Edit::Equal(i, _) => (b' ', old_lines[i]),
+                Edit::Delete(i) => (b'-', old_lines[i]),
+                Edit::Insert(j) => (b'+', new_lines[j]),
+            };
+            out.push(sign);
+            out.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                out.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    out
+}
+
+/// How regions both sides of a merge changed are written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictStyle {
+    /// Our and their lines, without the lines both sides agree on.
+    #[default]
+    Merge,
+    /// Our, base and their lines in full.
+    Diff3,
+    /// Like `Diff3`, but lines both sides agree on stay out of the conflict.
+    Zdiff3,
+}
+
+impl FromStr for ConflictStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ConflictStyle, Error> {
+        match s.to_lowercase().as_str() {
+            "merge" => Ok(ConflictStyle::Merge),
+            "diff3" => Ok(ConflictStyle::Diff3),
+            "zdiff3" => Ok(ConflictStyle::Zdiff3),
+            _ => Err(Error::BadConfig(format!("unknown conflict style '{s}'"))),
+        }
+    }
+}
+
+/// Resolves regions both sides changed instead of marking a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Favor {
+    Ours,
+    Theirs,
+    /// Our lines followed by theirs.
+    Union,
+}
+
+impl FromStr for Favor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Favor, Error> {
+        match s {
+            "ours" => Ok(Favor::Ours),
+            "theirs" => Ok(Favor::Theirs),
+            "union" => Ok(Favor::Union),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown merge option '{s}'"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeOptions {
+    pub style: ConflictStyle,
+    pub favor: Option<Favor>,
+    pub algorithm: Algorithm,
+    /// Names of our, the base and their version, shown after the markers.
+    pub labels: [String; 3],
+}
+
+/// A file merged from two sets of changes to a common base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    pub content: Vec<u8>,
+    /// Regions both sides changed differently, marked up in `content`.
+    pub conflicts: usize,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line, the
+/// way diff3 does. Regions changed on one side take that change; regions
+/// both sides changed differently are resolved by `options.favor` or kept
+/// between conflict markers.
+pub fn merge3(base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> MergeResult {
+    let base = split_lines(base);
+    let ours = split_lines(ours);
+    let theirs = split_lines(theirs);
+
+    let mut chunks = merge_chunks(&base, &ours, &theirs, options.algorithm);
+    match options.style {
+        ConflictStyle::Merge => {
+            chunks = refine_conflicts(chunks, &ours, &theirs, options.algorithm);
+            chunks = join_conflicts(chunks, &ours);
+        }
+        ConflictStyle::Zdiff3 => chunks = trim_conflicts(chunks, &ours, &theirs),
+        ConflictStyle::Diff3 => {}
+    }
+
+    let mut result = MergeResult {
+        content: vec![],
+        conflicts: 0,
+    };
+    let out = &mut result.content;
+    for chunk in chunks {
+        match chunk {
+            Chunk::Same { ours: range, .. } => push_lines(out, &ours[range]),
+            Chunk::Changed(lines) => push_lines(out, &lines),
+            Chunk::Conflict {
+                base: base_range,
+                ours: ours_range,
+                theirs: theirs_range,
+            } => {
+                let (ours, theirs) = (&ours[ours_range], &theirs[theirs_range]);
+                match options.favor {
+                    Some(Favor::Ours) => push_lines(out, ours),
+                    Some(Favor::Theirs) => push_lines(out, theirs),
+                    Some(Favor::Union) => {
+                        push_conflict_side(out, ours);
+                        push_lines(out, theirs);
+                    }
+                    None => {
+                        let [ours_label, base_label, theirs_label] = &options.labels;
+                        push_marker(out, "<<<<<<<", ours_label);
+                        push_conflict_side(out, ours);
+                        if options.style != ConflictStyle::Merge {
+                            push_marker(out, "|||||||", base_label);
+                            push_conflict_side(out, &base[base_range]);
+                        }
+                        push_marker(out, "=======", "");
+                        push_conflict_side(out, theirs);
+                        push_marker(out, ">>>>>>>", theirs_label);
+                        result.conflicts += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// A stretch of a merged file.
+#[derive(Debug, Clone)]
+enum Chunk<'a> {
+    /// Lines both sides have in common.
+    Same { ours: Range, theirs: Range },
+    /// The result of a change made on one side, or the same way on both.
+    Changed(Vec<&'a [u8]>),
+    /// Lines both sides changed differently.
+    Conflict {
+        base: Range,
+        ours: Range,
+        theirs: Range,
+    },
+}
+
+// Splits the files at the base lines both sides kept; what lies between
+// two such lines was changed by one side, both, or neither.
+fn merge_chunks<'a>(
+    base: &[&'a [u8]],
+    ours: &[&'a [u8]],
+    theirs: &[&'a [u8]],
+    algorithm: Algorithm,
+) -> Vec<Chunk<'a>> {
+    let ours_kept = kept_lines(base, ours, algorithm);
+    let theirs_kept = kept_lines(base, theirs, algorithm);
+
+    let mut chunks = vec![];
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        let sync = (o..base.len()).find_map(|i| Some((i, ours_kept[i]?, theirs_kept[i]?)));
+        let (i, j, k) = sync.unwrap_or((base.len(), ours.len(), theirs.len()));
+
+        let (base_lines, ours_lines, theirs_lines) = (&base[o..i], &ours[a..j], &theirs[b..k]);
+        if ours_lines == base_lines && theirs_lines == base_lines {
+            // nothing changed
+        } else if ours_lines == base_lines || ours_lines == theirs_lines {
+            chunks.push(Chunk::Changed(theirs_lines.to_vec()));
+        } else if theirs_lines == base_lines {
+            chunks.push(Chunk::Changed(ours_lines.to_vec()));
+        } else {
+            chunks.push(Chunk::Conflict {
+                base: o..i,
+                ours: a..j,
+                theirs: b..k,
+            });
+        }
+
+        let Some((i, j, k)) = sync else {
+            break;
+        };
+        push_same(&mut chunks, j..j + 1, k..k + 1);
+        (o, a, b) = (i + 1, j + 1, k + 1);
+    }
+
+    chunks
+}
+
+// for each base line, the line of `side` it was kept as
+fn kept_lines(base: &[&[u8]], side: &[&[u8]], algorithm: Algorithm) -> Vec<Option<usize>> {
+    let mut kept = vec![None; base.len()];
+    for edit in diff_lines(base, side, algorithm) {
+        if let Edit::Equal(i, j) = edit {
+            kept[i] = Some(j);
+        }
+    }
+    kept
+}
+
+// adds common lines, extending the previous chunk if it ends right before them
+fn push_same(chunks: &mut Vec<Chunk>, ours: Range, theirs: Range) {
+    if ours.is_empty() {
+        return;
+    }
+    if let Some(Chunk::Same {
+        ours: last_ours,
+        theirs: last_theirs,
+    }) = chunks.last_mut()
+    {
+        if last_ours.end == ours.start && last_theirs.end == theirs.start {
+            last_ours.end = ours.end;
+            last_theirs.end = theirs.end;
+            return;
+        }
+    }
+    chunks.push(Chunk::Same { ours, theirs });
+}
+
+// Diffs the two sides of each conflict against each other, so that only the
+// lines where they really differ are left in conflict, like git's zealous
+// merge level.
+fn refine_conflicts<'a>(
+    chunks: Vec<Chunk<'a>>,
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    algorithm: Algorithm,
+) -> Vec<Chunk<'a>> {
+    let mut refined = vec![];
+    for chunk in chunks {
+        let (ours_range, theirs_range) = match chunk {
+            Chunk::Conflict { ours, theirs, .. } => (ours, theirs),
+            // joined with common lines the refining leaves next to them
+            Chunk::Same { ours, theirs } => {
+                push_same(&mut refined, ours, theirs);
+                continue;
+            }
+            chunk => {
+                refined.push(chunk);
+                continue;
+            }
+        };
+
+        let (a, b) = (ours_range.start, theirs_range.start);
+        let edits = diff_lines(&ours[ours_range], &theirs[theirs_range], algorithm);
+        let (mut i, mut j, mut n) = (0, 0, 0);
+        while n < edits.len() {
+            if let Edit::Equal(..) = edits[n] {
+                push_same(&mut refined, a + i..a + i + 1, b + j..b + j + 1);
+                (i, j, n) = (i + 1, j + 1, n + 1);
+                continue;
+            }
+
+            let (i_start, j_start) = (i, j);
+            while let Some(Edit::Delete(_) | Edit::Insert(_)) = edits.get(n) {
+                match edits[n] {
+                    Edit::Delete(_) => i += 1,
+                    _ => j += 1,
+                }
+                n += 1;
+            }
+            refined.push(Chunk::Conflict {
+                base: 0..0,
+                ours: a + i_start..a + i,
+                theirs: b + j_start..b + j,
+            });
+        }
+    }
+
+    refined
+}
+
+// Joins conflicts separated by at most three common lines, or by lines
+// without any letters or digits: one larger conflict reads more easily
+// than several next to each other.
+fn join_conflicts<'a>(chunks: Vec<Chunk<'a>>, ours: &[&[u8]]) -> Vec<Chunk<'a>> {
+    let mut joined: Vec<Chunk> = vec![];
+    for chunk in chunks {
+        if let (
+            Chunk::Conflict {
+                base: base_range,
+                ours: ours_range,
+                theirs: theirs_range,
+            },
+            [.., Chunk::Conflict { .. }, Chunk::Same { ours: gap, .. }],
+        ) = (&chunk, &joined[..])
+        {
+            let trivial = gap.len() <= 3
+                || !ours[gap.clone()]
+                    .iter()
+                    .any(|line| line.iter().any(u8::is_ascii_alphanumeric));
+            if trivial {
+                joined.pop();
+                let Some(Chunk::Conflict { base, ours, theirs }) = joined.last_mut() else {
+                    unreachable!("checked above");
+                };
+                base.end = base_range.end.max(base.start);
+                ours.end = ours_range.end;
+                theirs.end = theirs_range.end;
+                continue;
+            }
+        }
+        joined.push(chunk);
+    }
+
+    joined
+}
+
+// Moves lines both sides of a conflict start or end with out of it,
+// keeping the whole base, for zdiff3.
+fn trim_conflicts<'a>(chunks: Vec<Chunk<'a>>, ours: &[&[u8]], theirs: &[&[u8]]) -> Vec<Chunk<'a>> {
+    let mut trimmed = vec![];
+    for chunk in chunks {
+        let Chunk::Conflict {
+            base,
+            ours: ours_range,
+            theirs: theirs_range,
+        } = chunk
+        else {
+            trimmed.push(chunk);
+            continue;
+        };
+
+        let (ours_lines, theirs_lines) = (&ours[ours_range.clone()], &theirs[theirs_range.clone()]);
+        let prefix = ours_lines
+            .iter()
+            .zip(theirs_lines)
+            .take_while(|(x, y)| x == y)
+            .count();
+        let suffix = ours_lines[prefix..]
+            .iter()
+            .rev()
+            .zip(theirs_lines[prefix..].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+
+        let (a, b) = (ours_range.start, theirs_range.start);
+        push_same(&mut trimmed, a..a + prefix, b..b + prefix);
+        trimmed.push(Chunk::Conflict {
+            base,
+            ours: a + prefix..ours_range.end - suffix,
+            theirs: b + prefix..theirs_range.end - suffix,
+        });
+        push_same(
+            &mut trimmed,
+            ours_range.end - suffix..ours_range.end,
+            theirs_range.end - suffix..theirs_range.end,
+        );
+    }
+
+    trimmed
+}
+
+fn push_lines(out: &mut Vec<u8>, lines: &[&[u8]]) {
+    lines.iter().for_each(|line| out.extend_from_slice(line));
+}
+
+fn push_marker(out: &mut Vec<u8>, marker: &str, label: &str) {
+    out.extend_from_slice(marker.as_bytes());
+    if !label.is_empty() {
+        out.push(b' ');
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(b'\n');
+}
+
+// a side missing its final newline still needs one before the next marker
+fn push_conflict_side(out: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+    if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        out.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str, algorithm: Algorithm) -> String {
+        let options = Options {
+            algorithm,
+            ..Options::default()
+        };
+        String::from_utf8(unified(old.as_bytes(), new.as_bytes(), &options)).unwrap()
+    }
+
+    #[test]
+    fn histogram_anchors_on_rare_lines() {
+        let (old, new) = ("a\nb\nc\na\nb\nb\na\n", "c\nb\na\nb\na\nc\n");
+        let shared = "@@ -1,7 +1,6 @@\n-a\n-b\n c\n-a\n b\n+a\n b\n a\n+c\n";
+        assert_eq!(diff(old, new, Algorithm::Myers), shared);
+        assert_eq!(diff(old, new, Algorithm::Patience), shared);
+        assert_eq!(
+            diff(old, new, Algorithm::Histogram),
+            "@@ -1,7 +1,6 @@\n-a\n-b\n c\n-a\n-b\n b\n a\n+b\n+a\n+c\n"
+        );
+    }
+
+    #[test]
+    fn patience_keeps_moved_blocks_together() {
+        let old = "void f() {\n  a();\n}\n\nvoid g() {\n  b();\n}\n";
+        let new = "void g() {\n  b();\n}\n\nvoid f() {\n  a();\n}\n\nvoid h() {\n  c();\n}\n";
+        let myers = "@@ -1,7 +1,11 @@\n\
+                     +void g() {\n+  b();\n+}\n+\n \
+                     void f() {\n   a();\n }\n \n\
+                     -void g() {\n-  b();\n+void h() {\n+  c();\n }\n";
+        assert_eq!(diff(old, new, Algorithm::Myers), myers);
+        assert_eq!(diff(old, new, Algorithm::Histogram), myers);
+        assert_eq!(
+            diff(old, new, Algorithm::Patience),
+            "@@ -1,7 +1,11 @@\n\
+             -void f() {\n-  a();\n-}\n-\n \
+             void g() {\n   b();\n }\n\
+             +\n+void f() {\n+  a();\n+}\n+\n+void h() {\n+  c();\n+}\n"
+        );
+    }
+
+    #[test]
+    fn myers_leaves_unmatched_lines_out_of_the_search() {
+        assert_eq!(
+            diff("a\nz\nz\nc\nc\n", "z\nc\n", Algorithm::Myers),
+            "@@ -1,5 +1,2 @@\n-a\n z\n-z\n-c\n c\n"
+        );
+    }
+
+    #[test]
+    fn patience_prefers_the_later_of_crossing_unique_lines() {
+        assert_eq!(
+            diff("y\nz\nc\n", "z\ny\n", Algorithm::Patience),
+            "@@ -1,3 +1,2 @@\n-y\n z\n-c\n+y\n"
+        );
+    }
+
+    #[test]
+    fn histogram_does_not_trim_common_ends_first() {
+        assert_eq!(
+            diff("y\nx\nc\n", "c\ny\nc\n", Algorithm::Histogram),
+            "@@ -1,3 +1,3 @@\n-y\n-x\n+c\n+y\n c\n"
+        );
+    }
+
+    #[test]
+    fn separate_hunks_and_missing_newline() {
+        let old = (1..=20).map(|i| format!("{i}\n")).collect::<String>();
+        let new = old.replace("\n3\n", "\nthree\n").replace("\n15\n", "\n") + "x";
+        for algorithm in [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram] {
+            assert_eq!(
+                diff(&old, &new, algorithm),
+                "@@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+                 @@ -12,9 +12,9 @@\n 12\n 13\n 14\n-15\n 16\n 17\n 18\n 19\n 20\n\
+                 +x\n\\ No newline at end of file\n"
+            );
+        }
+    }
+
+    #[test]
+    fn context_lines() {
+        let options = Options {
+            context: 1,
+            ..Options::default()
+        };
+        assert_eq!(
+            unified(b"1\n2\n3\n4\n5\n", b"1\n2\nx\n4\n5\n", &options),
+            b"@@ -2,3 +2,3 @@\n 2\n-3\n+x\n 4\n"
+        );
+    }
+
+    #[test]
+    fn lines_are_copied_byte_for_byte() {
+        assert_eq!(
+            unified(b"caf\xe9\n", b"caf\xe9!\n", &Options::default()),
+            b"@@ -1 +1 @@\n-caf\xe9\n+caf\xe9!\n"
+        );
+    }
+
+    fn merge(base: &str, ours: &str, theirs: &str, style: ConflictStyle) -> (String, usize) {
+        let options = MergeOptions {
+            style,
+            labels: ["ours".into(), "base".into(), "theirs".into()],
+            ..MergeOptions::default()
+        };
+        let result = merge3(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            &options,
+        );
+        (String::from_utf8(result.content).unwrap(), result.conflicts)
+    }
+
+    const BASE: &str = "1\n2\n3\n4\n5\n";
+    const OURS: &str = "1\nA\nx\n3\n4\n5\n";
+    const THEIRS: &str = "1\nA\ny\n3\n4\nB\n";
+
+    #[test]
+    fn merge_style_leaves_out_lines_both_sides_added() {
+        assert_eq!(
+            merge(BASE, OURS, THEIRS, ConflictStyle::Merge),
+            (
+                "1\nA\n<<<<<<< ours\nx\n=======\ny\n>>>>>>> theirs\n3\n4\nB\n".to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn diff3_style_shows_the_whole_base() {
+        assert_eq!(
+            merge(BASE, OURS, THEIRS, ConflictStyle::Diff3),
+            (
+                "1\n<<<<<<< ours\nA\nx\n||||||| base\n2\n=======\nA\ny\n>>>>>>> theirs\n\
+                 3\n4\nB\n"
+                    .to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn zdiff3_style_moves_common_lines_out_of_the_conflict() {
+        assert_eq!(
+            merge(BASE, OURS, THEIRS, ConflictStyle::Zdiff3),
+            (
+                "1\nA\n<<<<<<< ours\nx\n||||||| base\n2\n=======\ny\n>>>>>>> theirs\n\
+                 3\n4\nB\n"
+                    .to_string(),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn favored_side_resolves_conflicts() {
+        let mut options = MergeOptions {
+            favor: Some(Favor::Union),
+            ..MergeOptions::default()
+        };
+        let union = merge3(
+            BASE.as_bytes(),
+            OURS.as_bytes(),
+            THEIRS.as_bytes(),
+            &options,
+        );
+        assert_eq!(union.content, b"1\nA\nx\ny\n3\n4\nB\n");
+        assert_eq!(union.conflicts, 0);
+
+        options.favor = Some(Favor::Ours);
+        let ours = merge3(
+            BASE.as_bytes(),
+            OURS.as_bytes(),
+            THEIRS.as_bytes(),
+            &options,
+        );
+        assert_eq!(ours.content, b"1\nA\nx\n3\n4\nB\n");
+    }
+
+    #[test]
+    fn conflicts_close_together_are_joined() {
+        let (content, conflicts) = merge(
+            "7\n2\n6\n0\n1\n9\n",
+            "7\na\n6\nz\n1\n",
+            "2\na\n6\nz\nd\nc\nx\n",
+            ConflictStyle::Merge,
+        );
+        assert_eq!(
+            content,
+            "<<<<<<< ours\n7\na\n6\nz\n1\n=======\n2\na\n6\nz\nd\nc\nx\n>>>>>>> theirs\n"
+        );
+        assert_eq!(conflicts, 1);
+    }
+
+    #[test]
+    fn myers_finds_changes_in_long_files() {
+        // long enough for the middle snake search to split several times
+        let old = (0..5000)
+            .map(|i| format!("{}\n", i * 7 % 1000))
+            .collect::<String>();
+        let new = old.replacen("\n0\n", "\nzero\n", 1) + "end\n";
+        let old_lines = split_lines(old.as_bytes());
+        let new_lines = split_lines(new.as_bytes());
+        let edits = diff_lines(&old_lines, &new_lines, Algorithm::Myers);
+
+        let changes = edits
+            .iter()
+            .filter(|e| !matches!(e, Edit::Equal(..)))
+            .collect::<Vec<_>>();
+        assert_eq!(changes.len(), 3);
+        for edit in edits {
+            if let Edit::Equal(i, j) = edit {
+                assert_eq!(old_lines[i], new_lines[j]);
+            }
+        }
+    }
+}